@@ -1,14 +1,29 @@
 import sys
 
-def main(filename: str) -> None:
+SPECIAL_PAGES = 4
+MAGIC = b"dbfile\0\0"
+VERSION = 1
+PAGE_SIZES = [4096, 8192, 16384, 32768]
+
+def main(filename: str, page_size: int) -> None:
     with open(filename, "wb") as f:
-        for _ in range(4096 * 8):
-            f.write((0).to_bytes(1))
-        pass
-    print("ran", filename)
+        # header: magic, then version, capacity, used pages and page size,
+        # each a big endian u32
+        f.write(MAGIC)
+        f.write(VERSION.to_bytes(4, "big"))
+        f.write((0).to_bytes(4, "big"))
+        f.write((0).to_bytes(4, "big"))
+        f.write(page_size.to_bytes(4, "big"))
+        for _ in range(SPECIAL_PAGES * page_size - 24):
+            f.write((0).to_bytes(1, "big"))
+    print("ran", filename, "with page size", page_size)
 
 if __name__ == "__main__":
     if len(sys.argv) < 2:
         print("please specify db file", file=sys.stderr) 
         exit()
-    main(sys.argv[1])
+    page_size = int(sys.argv[2]) if len(sys.argv) > 2 else 4096
+    if page_size not in PAGE_SIZES:
+        print("page size must be one of", PAGE_SIZES, file=sys.stderr)
+        exit()
+    main(sys.argv[1], page_size)
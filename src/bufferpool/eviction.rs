@@ -15,14 +15,9 @@ pub struct LruK {
 }
 
 impl EvictionStrategy for LruK {
+    #[allow(clippy::map_clone)]
     fn update_entry(&mut self, frame: usize) {
-        let current_time = SystemTime::now();
-        // Calculate the duration since the Unix epoch
-        let duration_since_epoch = current_time
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-        // Convert the duration to milliseconds
-        let milliseconds_since_epoch = duration_since_epoch.as_millis();
+        let milliseconds_since_epoch = now();
 
         // we have the this frame, lets find the entry
         // from there we can create a new, updated entry
@@ -39,14 +34,40 @@ impl EvictionStrategy for LruK {
         self.heap.push(new_entry);
     }
 
-    fn find_victim<'a>(&'a mut self, pool: &'a Pool) -> (RwLockWriteGuard<super::Page>, usize) {
-        let buffer = self.heap.pop().unwrap();
+    fn find_victim<'a>(&mut self, pool: &'a Pool) -> (RwLockWriteGuard<'a, super::Page>, usize) {
+        // pinned frames are in use and can't be evicted, so set them aside
+        let mut skipped = Vec::new();
+        let buffer = loop {
+            let buffer = self
+                .heap
+                .pop()
+                .expect("every frame in the buffer pool is pinned");
+            if pool.pinned[buffer.frame].get() > 0 {
+                skipped.push(buffer);
+            } else {
+                break buffer;
+            }
+        };
+        self.heap.extend(skipped);
         let frame_idx = buffer.frame;
+        // the frame is about to hold a freshly loaded page, which counts as an access.
+        // without putting it back the heap would run dry after `buffer_size` evictions
+        self.heap.push(buffer.update(now()));
 
         (pool.frames[frame_idx].write().unwrap(), frame_idx)
     }
 }
 
+fn now() -> u128 {
+    let current_time = SystemTime::now();
+    // Calculate the duration since the Unix epoch
+    let duration_since_epoch = current_time
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards");
+    // Convert the duration to milliseconds
+    duration_since_epoch.as_millis()
+}
+
 impl LruK {
     pub fn new(buffer_size: usize, k: usize) -> Self {
         let mut heap = BinaryHeap::new();
@@ -61,15 +82,18 @@ impl LruK {
 // ordering for this is flipped compared to normal
 // this is because i am using a max heap, but we want the oldest value
 // the oldest value will have the smallest time
+// times are compared oldest to newest, so the frame whose k-th most recent
+// access is furthest in the past is evicted first
 impl Ord for TimeRingBuffer {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        for i in 0..self.times.len() {
-            let first = self.times[i];
-            let second = other.times[i];
+        let size = self.times.len();
+        for offset in 1..size + 1 {
+            let first = self.times[(self.head + offset) % size];
+            let second = other.times[(other.head + offset) % size];
             if first < second {
                 return std::cmp::Ordering::Greater;
             }
-            if second > first {
+            if first > second {
                 return std::cmp::Ordering::Less;
             }
         }
@@ -85,6 +109,7 @@ impl PartialOrd for TimeRingBuffer {
 }
 
 impl PartialEq for TimeRingBuffer {
+    #[allow(clippy::needless_return)]
     fn eq(&self, other: &Self) -> bool {
         let size = self.times.len();
         for offset in 1..size + 1 {
@@ -100,6 +125,7 @@ impl PartialEq for TimeRingBuffer {
 
 impl Eq for TimeRingBuffer {}
 
+#[allow(clippy::unnecessary_cast)]
 impl TimeRingBuffer {
     fn new(frame: usize, size: usize) -> Self {
         TimeRingBuffer {
@@ -109,6 +135,7 @@ impl TimeRingBuffer {
         }
     }
 
+    #[allow(dead_code)]
     fn from(frame: usize, size: usize, time: u128) -> Self {
         let mut times = vec![0 as u128; size];
         times[0] = time;
@@ -1,12 +1,16 @@
 pub mod eviction;
-use crate::disk::DiskManager;
+mod page;
+mod pins;
+use crate::disk::{Corruption, DiskManager, IoRequest};
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex, MutexGuard, RwLock, RwLockWriteGuard},
-    usize,
+    ops::{Deref, DerefMut},
+    sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard},
 };
 
 use super::utils::bitmap::Bitmap;
+pub use page::{Page, PAGE_ALIGNMENT};
+use pins::{PinCount, PinTracker};
 
 // What does our interface need?
 // we must be able to
@@ -21,42 +25,66 @@ use super::utils::bitmap::Bitmap;
 // ideally i should implement this using composition,
 // such that our pool contains a "eviction" strategy object that tracks usages
 //
-// TODO: create lock ordering to prevent silly mistakes
+// locks are taken in the order frame -> frame_to_id -> dirty, so a thread
+// holding a frame's latch can always mark it dirty
 
-type ID = u32;
+pub type ID = u32;
 pub struct Pool {
     // our buffer pool can use a map to track cached pages, and its frame in memory
     cache: RwLock<HashMap<ID, usize>>,
     frames: Vec<RwLock<Page>>,
     frame_to_id: Vec<Mutex<Option<ID>>>,
     dirty: Mutex<Bitmap>,
-    pinned: Vec<Mutex<u8>>,
+    pinned: Vec<PinCount>,
+    pin_tracker: PinTracker,
     strategy: Mutex<Box<dyn EvictionStrategy>>,
     disk: DiskManager,
 }
 
-pub type Page = [u8; 4096];
-
 pub struct PageGuard<'a> {
     data: &'a Pool,
+    #[allow(dead_code)]
     page_id: ID,
     pool_idx: usize,
+    pin_token: u64,
+}
+
+// a page latched until it's dropped, for holding several latches at once
+// while crabbing down a tree. the latch goes before the pin does
+pub struct ReadLatch<'a> {
+    page: RwLockReadGuard<'a, Page>,
+    _guard: PageGuard<'a>,
+}
+
+pub struct WriteLatch<'a> {
+    page: RwLockWriteGuard<'a, Page>,
+    _guard: PageGuard<'a>,
 }
 
 pub trait EvictionStrategy {
+    #[allow(dead_code)]
     fn update_entry(&mut self, frame: usize);
-    fn find_victim<'a>(&'a mut self, pool: &'a Pool) -> (RwLockWriteGuard<Page>, usize);
+    fn find_victim<'a>(&mut self, pool: &'a Pool) -> (RwLockWriteGuard<'a, Page>, usize);
 }
 
 impl Pool {
     // we need to init bitmaps, cache, and choose eviction strategy
     pub fn new(capacity: usize, strategy: Mutex<Box<dyn EvictionStrategy>>) -> Self {
+        Pool::with_disk(capacity, strategy, DiskManager::new())
+    }
+
+    pub fn with_disk(
+        capacity: usize,
+        strategy: Mutex<Box<dyn EvictionStrategy>>,
+        disk: DiskManager,
+    ) -> Self {
+        let page_size = disk.page_size();
         let mut frames = Vec::with_capacity(capacity);
         let mut pinned = Vec::with_capacity(capacity);
         let mut frame_to_id = Vec::with_capacity(capacity);
         for _ in 0..capacity {
-            frames.push(RwLock::new([10; 4096]));
-            pinned.push(Mutex::new(0 as u8));
+            frames.push(RwLock::new(Page::zeroed(page_size)));
+            pinned.push(PinCount::new());
             frame_to_id.push(Mutex::new(None));
         }
         Pool {
@@ -65,144 +93,453 @@ impl Pool {
             dirty: Mutex::new(Bitmap::with_capacity(capacity)),
             frame_to_id,
             pinned,
+            pin_tracker: PinTracker::new(),
             strategy,
-            disk: DiskManager::new(),
-        }
-    }
-
-    pub fn new_page(&self) -> Option<(ID, PageGuard)> {
-        let possible_page_id = self.disk.new_page();
-        if possible_page_id.is_none() {
-            return None;
-        } else {
-            let new_page_id = possible_page_id.unwrap();
-            let page_guard = self.get_page(new_page_id);
-            return Some((new_page_id, page_guard));
-        }
-    }
-
-    pub fn get_page(&self, page: ID) -> PageGuard {
-        let idx = {
-            let cache = self.cache.read().unwrap();
-            if let Some(idx) = cache.get(&page) {
-                *idx
-            } else {
-                drop(cache);
-                let write_cache = self.cache.write().unwrap();
-                if let Some(idx) = write_cache.get(&page) {
-                    *idx
-                } else {
-                    // eprintln!("couldnt find {} in {:?}", page, write_cache);
-                    self.replace_entry(page, write_cache)
-                }
+            disk,
+        }
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.disk.page_size()
+    }
+
+    pub fn new_page(&self) -> Option<(ID, PageGuard<'_>)> {
+        let new_page_id = self.disk.new_page()?;
+        let page_guard = self.get_page(new_page_id);
+        Some((new_page_id, page_guard))
+    }
+
+    // give a page back to the disk. whatever is cached for it is thrown away,
+    // so nobody may still hold a guard on it
+    pub fn delete_page(&self, page: ID) {
+        assert!(
+            self.try_delete_page(page),
+            "deleting page {} while it is pinned",
+            page
+        );
+    }
+
+    // like delete_page, but leaves a pinned page alone and returns false
+    pub fn try_delete_page(&self, page: ID) -> bool {
+        let mut cache = self.cache.write().unwrap();
+        if let Some(&frame) = cache.get(&page) {
+            // pins are taken under the cache lock, so none can show up while we hold it
+            if self.pinned[frame].get() > 0 {
+                return false;
             }
+            cache.remove(&page);
+            *self.frame_to_id[frame].lock().unwrap() = None;
+            self.dirty.lock().unwrap().unset(frame);
+        }
+        self.disk.delete_page(page);
+        true
+    }
+
+    #[allow(dead_code)]
+    pub fn used_pages(&self) -> u32 {
+        self.disk.used_pages()
+    }
+
+    // compress the page on disk from now on, see DiskManager::set_compressed.
+    // frames always hold the plain page
+    #[cfg(feature = "compression")]
+    pub fn set_compressed(&self, page: ID, compressed: bool) {
+        self.disk.set_compressed(page, compressed);
+    }
+
+    // like get_page, but a page that isn't in the file or fails its checksum is
+    // an error instead of a panic, for walking pages that may be corrupt. a miss
+    // is checked before it's loaded, so it's read from disk twice
+    pub fn try_get_page(&self, page: ID) -> Result<PageGuard<'_>, Corruption> {
+        if !self.cache.read().unwrap().contains_key(&page) {
+            self.disk.try_read(page)?;
+        }
+        Ok(self.get_page(page))
+    }
+
+    pub fn get_page(&self, page: ID) -> PageGuard<'_> {
+        // the frame is pinned before we let go of the cache,
+        // otherwise it could be evicted before we pin it
+        let cache = self.cache.read().unwrap();
+        if let Some(&idx) = cache.get(&page) {
+            return PageGuard::new(self, page, idx);
+        }
+        drop(cache);
+        let mut write_cache = self.cache.write().unwrap();
+        let idx = match write_cache.get(&page) {
+            Some(&idx) => idx,
+            None => self.replace_entry(page, &mut write_cache),
         };
         PageGuard::new(self, page, idx)
     }
 
     // returns what slot is now empty
-    fn replace_entry(
-        &self,
-        new_page_id: ID,
-        mut cache: RwLockWriteGuard<HashMap<u32, usize>>,
-    ) -> usize {
-        // we start by finding the page to remove, and acquire a write lock on it
+    fn replace_entry(&self, new_page_id: ID, cache: &mut HashMap<u32, usize>) -> usize {
+        self.load_pages(&[new_page_id], cache)[0]
+    }
+
+    // bring pages that aren't cached yet into frames, returning the frame of each.
+    // the write-backs of dirty victims and the reads of the new pages
+    // go to the disk as a single batch
+    fn load_pages(&self, pages: &[ID], cache: &mut HashMap<u32, usize>) -> Vec<usize> {
+        // we start by finding the pages to remove, and acquire a write lock on each
         let mut strat = self.strategy.lock().unwrap();
-        let (mut victim_guard, frame) = strat.find_victim(self);
-        // remove old cached id
-        let mut frame_to_id_guard = self.frame_to_id[frame].lock().unwrap();
-        match *frame_to_id_guard {
-            None => {}
-            Some(victim_id) => {
-                // eprintln!("set to remove {}", victim_id);
-                cache.remove(&victim_id);
-
-                // if frame is dirty -> flush changes
-                // we do not need to do so if this frame did not store a page,
-                // thats why its in this match clause
-                let mut dirty_frames = self.dirty.lock().unwrap();
-                if dirty_frames.check(frame) {
-                    let page_content = self.frames[frame].read().unwrap();
-                    self.disk.write(victim_id, &(*&page_content));
+        let mut victims = Vec::with_capacity(pages.len());
+        let mut write_backs = Vec::new();
+        for &new_page_id in pages {
+            let (victim_guard, frame) = strat.find_victim(self);
+            // pinned until the batch is done, so we can't pick the same frame twice
+            self.pinned[frame].pin();
+            // remove old cached id
+            let mut frame_to_id_guard = self.frame_to_id[frame].lock().unwrap();
+            match *frame_to_id_guard {
+                None => {}
+                Some(victim_id) => {
+                    cache.remove(&victim_id);
+
+                    // if frame is dirty -> flush changes
+                    // we do not need to do so if this frame did not store a page,
+                    // thats why its in this match clause
+                    let mut dirty_frames = self.dirty.lock().unwrap();
+                    if dirty_frames.check(frame) {
+                        write_backs.push((victim_id, victims.len()));
+                    }
+                    dirty_frames.unset(frame);
                 }
-                dirty_frames.unset(frame);
             }
+            *frame_to_id_guard = Some(new_page_id);
+            drop(frame_to_id_guard);
+            // update the entry, removing old key and adding new one
+            cache.insert(new_page_id, frame);
+            victims.push((victim_guard, frame));
         }
-        *frame_to_id_guard = Some(new_page_id);
-        drop(frame_to_id_guard);
-        // update the entry, removing old key and adding new one
-        cache.insert(new_page_id, frame);
-        let new_frame = self.disk.read(new_page_id);
-        // let new_frame = [0; 4096];
-        *victim_guard = new_frame;
+        drop(strat);
 
-        // eprintln!("put id {} in frame {}, resulting in {:?}", new_page_id, frame, cache);
-        drop(victim_guard);
-        drop(cache);
-        frame
+        // the victims still hold their old contents for the write-back,
+        // so the new pages are read into fresh buffers and swapped in afterwards
+        let mut new_frames: Vec<Page> = pages
+            .iter()
+            .map(|_| Page::zeroed(self.page_size()))
+            .collect();
+        let mut requests: Vec<IoRequest> = write_backs
+            .iter()
+            .map(|&(victim_id, i)| IoRequest::Write(victim_id, &victims[i].0))
+            .collect();
+        requests.extend(
+            pages
+                .iter()
+                .zip(new_frames.iter_mut())
+                .map(|(&id, page)| IoRequest::Read(id, page)),
+        );
+        self.disk.submit(&mut requests);
+        drop(requests);
+
+        victims
+            .into_iter()
+            .zip(new_frames)
+            .map(|((mut victim_guard, frame), new_frame)| {
+                *victim_guard = new_frame;
+                self.pinned[frame].unpin();
+                frame
+            })
+            .collect()
+    }
+
+    // load pages we expect to need soon, reading every miss in one batch
+    #[allow(dead_code)]
+    pub fn prefetch(&self, pages: &[ID]) {
+        let mut cache = self.cache.write().unwrap();
+        let mut misses = Vec::new();
+        for &page in pages {
+            if !cache.contains_key(&page) && !misses.contains(&page) {
+                misses.push(page);
+            }
+        }
+        // prefetching more than we have free frames for would evict our own
+        // prefetches, or run out of frames to evict. pins are only taken under
+        // the cache lock, so no more frames can get pinned before we're done
+        let unpinned = self.pinned.iter().filter(|pin| pin.get() == 0).count();
+        misses.truncate(unpinned);
+        if !misses.is_empty() {
+            self.load_pages(&misses, &mut cache);
+        }
+    }
+
+    // write every dirty frame back to disk in one batch.
+    // frames latched for writing are in the middle of a change, so they stay
+    // dirty and go out with the next flush or when they're evicted
+    pub fn flush_all(&self) {
+        let mut dirty_pages = Vec::new();
+        for (i, frame) in self.frames.iter().enumerate() {
+            let Ok(page_content) = frame.try_read() else {
+                continue;
+            };
+            let frame_to_id = self.frame_to_id[i].lock().unwrap();
+            let mut dirty_frames = self.dirty.lock().unwrap();
+            if dirty_frames.check(i) {
+                if let Some(id) = *frame_to_id {
+                    dirty_pages.push((id, page_content));
+                }
+            }
+            dirty_frames.unset(i);
+        }
+
+        let mut requests: Vec<IoRequest> = dirty_pages
+            .iter()
+            .map(|(id, page_content)| IoRequest::Write(*id, page_content))
+            .collect();
+        if !requests.is_empty() {
+            self.disk.submit(&mut requests);
+        }
     }
 }
 
 impl<'a> PageGuard<'a> {
     pub fn new(pool: &'a Pool, page_id: ID, pool_idx: usize) -> Self {
-        // have to get mutex before critical section
-        let mut pin_count = pool.pinned[pool_idx].lock().unwrap();
-        *pin_count += 1;
-        // currently printing for debugging
-        // println!("picked up page {}, pin is {}", page_id, *pin_count);
-        drop(pin_count);
+        pool.pinned[pool_idx].pin();
+        let pin_token = pool.pin_tracker.track(page_id, pool_idx);
 
         PageGuard {
             data: pool,
             page_id,
             pool_idx,
+            pin_token,
         }
     }
 
     pub fn read(&self) -> Page {
-        // return a clone of the data, after acquiring read permission
+        // return a copy of the data, after acquiring read permission
         let data = self.data.frames[self.pool_idx].read().unwrap();
         let res = data.clone();
         drop(data);
 
-        return res;
+        res
     }
 
-    pub fn write(&self) -> RwLockWriteGuard<Page> {
-        // we need to acquire locks on the data and dirty bitmap
-        // we update the data and drop these mutexes
-        let mut dirty_map = self.data.dirty.lock().unwrap();
+    pub fn write(&self) -> RwLockWriteGuard<'_, Page> {
+        // latch the frame before marking it dirty, so a flush can't
+        // write it out and clear the bit in between
         let frame_data = self.data.frames[self.pool_idx].write().unwrap();
-        dirty_map.set(self.pool_idx);
-        drop(dirty_map);
+        self.data.dirty.lock().unwrap().set(self.pool_idx);
         frame_data
     }
+
+    pub fn read_latch(self) -> ReadLatch<'a> {
+        let pool = self.data;
+        ReadLatch {
+            page: pool.frames[self.pool_idx].read().unwrap(),
+            _guard: self,
+        }
+    }
+
+    pub fn write_latch(self) -> WriteLatch<'a> {
+        let pool = self.data;
+        let page = pool.frames[self.pool_idx].write().unwrap();
+        pool.dirty.lock().unwrap().set(self.pool_idx);
+        WriteLatch { page, _guard: self }
+    }
+}
+
+impl Deref for ReadLatch<'_> {
+    type Target = Page;
+
+    fn deref(&self) -> &Page {
+        &self.page
+    }
+}
+
+impl Deref for WriteLatch<'_> {
+    type Target = Page;
+
+    fn deref(&self) -> &Page {
+        &self.page
+    }
+}
+
+impl DerefMut for WriteLatch<'_> {
+    fn deref_mut(&mut self) -> &mut Page {
+        &mut self.page
+    }
 }
 
 impl<'a> Drop for PageGuard<'a> {
     fn drop(&mut self) {
-        let idx = self.pool_idx;
-        // acquire mutex
-        let mut pin_count = self.data.pinned[idx].lock().unwrap();
-        *pin_count -= 1;
-        // currently printing for debugging
-        // println!("dropped page {}, pin is {}", self.page_id, *pin_count);
-        drop(pin_count);
+        self.data.pin_tracker.release(self.pin_token);
+        self.data.pinned[self.pool_idx].unpin();
     }
 }
 
 impl Drop for Pool {
     fn drop(&mut self) {
-        let dirty_frames = self.dirty.lock().unwrap();
-        for i in 0..dirty_frames.len() {
-            let frame_to_id = self.frame_to_id[i].lock().unwrap();
-            if dirty_frames.check(i) && frame_to_id.is_some() {
-                let page_content = self.frames[i].read().unwrap();
-                self.disk.write(frame_to_id.unwrap(), &(*page_content));
+        // a guard borrows the pool, so any pin still held here
+        // belongs to a guard that was leaked instead of dropped
+        for (frame, pin_count) in self.pinned.iter().enumerate() {
+            if pin_count.get() > 0 {
+                eprintln!(
+                    "buffer pool dropped with frame {} still pinned {} time(s)",
+                    frame,
+                    pin_count.get()
+                );
             }
         }
+        for leaked in self.pin_tracker.outstanding() {
+            eprintln!("{}", leaked);
+        }
+
+        self.flush_all();
     }
 }
 
 unsafe impl Send for Pool {}
 unsafe impl Sync for Pool {}
+
+#[cfg(test)]
+mod tests {
+    use super::{eviction::LruK, EvictionStrategy, Pool};
+    use crate::disk::{DiskManager, PageSize};
+    use crate::utils::testing::{pool, temp_path};
+    use std::sync::Mutex;
+
+    #[test]
+    fn many_guards_on_one_page() {
+        let pool = pool("many_guards", 4);
+        let (id, first) = pool.new_page().unwrap();
+        let guards: Vec<_> = (0..300).map(|_| pool.get_page(id)).collect();
+        assert_eq!(pool.pinned[first.pool_idx].get(), 301);
+        drop(guards);
+        assert_eq!(pool.pinned[first.pool_idx].get(), 1);
+    }
+
+    #[test]
+    fn evicted_pages_are_written_back() {
+        let pool = pool("evicted_write_back", 2);
+        let mut ids = Vec::new();
+        for i in 0..6 {
+            let (id, guard) = pool.new_page().unwrap();
+            guard.write()[0] = i;
+            ids.push(id);
+        }
+        for (i, id) in ids.into_iter().enumerate() {
+            assert_eq!(pool.get_page(id).read()[0], i as u8);
+        }
+    }
+
+    #[test]
+    fn pinned_frames_are_not_evicted() {
+        let pool = pool("pinned_not_evicted", 2);
+        let (id, kept) = pool.new_page().unwrap();
+        kept.write()[0] = 9;
+        for _ in 0..4 {
+            pool.new_page().unwrap();
+        }
+        assert_eq!(pool.cache.read().unwrap().get(&id), Some(&kept.pool_idx));
+        assert_eq!(kept.read()[0], 9);
+    }
+
+    #[test]
+    fn prefetch_loads_misses() {
+        let pool = pool("prefetch", 4);
+        let mut ids = Vec::new();
+        for i in 0..8 {
+            let (id, guard) = pool.new_page().unwrap();
+            guard.write()[0] = i;
+            ids.push(id);
+        }
+        pool.prefetch(&ids[..4]);
+        let cache = pool.cache.read().unwrap();
+        assert!(ids[..4].iter().all(|id| cache.contains_key(id)));
+        assert!(ids[4..].iter().all(|id| !cache.contains_key(id)));
+        drop(cache);
+        for (i, &id) in ids.iter().enumerate() {
+            assert_eq!(pool.get_page(id).read()[0], i as u8);
+        }
+    }
+
+    #[test]
+    fn prefetch_skips_pinned_frames() {
+        let pool = pool("prefetch_pinned", 4);
+        let mut ids = Vec::new();
+        for _ in 0..8 {
+            ids.push(pool.new_page().unwrap().0);
+        }
+        let pinned: Vec<_> = ids[..3].iter().map(|&id| pool.get_page(id)).collect();
+        // only one frame is free, so only the first miss is loaded
+        pool.prefetch(&ids[3..]);
+        let cache = pool.cache.read().unwrap();
+        assert!(cache.contains_key(&ids[3]));
+        assert!(ids[4..].iter().all(|id| !cache.contains_key(id)));
+        drop(cache);
+        drop(pinned);
+    }
+
+    #[test]
+    fn flush_all_writes_dirty_frames() {
+        let pool = pool("flush_all", 4);
+        let (id, guard) = pool.new_page().unwrap();
+        guard.write()[0] = 5;
+        pool.flush_all();
+        assert!(!pool.dirty.lock().unwrap().check(guard.pool_idx));
+        assert_eq!(pool.disk.read(id)[0], 5);
+    }
+
+    #[test]
+    fn deleted_pages_come_back_empty() {
+        let pool = pool("delete_page", 4);
+        let (id, guard) = pool.new_page().unwrap();
+        guard.write()[0] = 5;
+        drop(guard);
+        let used = pool.used_pages();
+        pool.delete_page(id);
+        assert_eq!(pool.used_pages(), used - 1);
+        // the dirty frame was dropped rather than written back over the zeroed page
+        let (reused, guard) = pool.new_page().unwrap();
+        assert_eq!(reused, id);
+        assert_eq!(guard.read()[0], 0);
+    }
+
+    #[test]
+    fn latches_keep_their_page_pinned() {
+        let pool = pool("latches", 4);
+        let (id, guard) = pool.new_page().unwrap();
+        let mut latch = guard.write_latch();
+        latch[0] = 3;
+        assert!(!pool.try_delete_page(id));
+        // a latched frame is skipped by flushes instead of waiting on it
+        pool.flush_all();
+        assert_eq!(pool.disk.read(id)[0], 0);
+        drop(latch);
+
+        let latch = pool.get_page(id).read_latch();
+        assert_eq!(latch[0], 3);
+        drop(latch);
+        pool.flush_all();
+        assert_eq!(pool.disk.read(id)[0], 3);
+        assert!(pool.try_delete_page(id));
+    }
+
+    #[test]
+    fn frames_match_disk_page_size() {
+        let strat: Mutex<Box<dyn EvictionStrategy>> = Mutex::new(Box::new(LruK::new(2, 2)));
+        let disk = DiskManager::create(temp_path("frames_page_size"), PageSize::Size32K);
+        let pool = Pool::with_disk(2, strat, disk);
+        let (_, guard) = pool.new_page().unwrap();
+        assert_eq!(pool.page_size(), 32768);
+        assert_eq!(guard.read().len(), 32768);
+        assert_eq!(guard.write().len(), 32768);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn forgotten_guard_is_reported() {
+        let pool = pool("forgotten_guard", 4);
+        let (id, guard) = pool.new_page().unwrap();
+        let kept = pool.get_page(id);
+        std::mem::forget(guard);
+
+        let leaked = pool.pin_tracker.outstanding();
+        // the guard we still hold is outstanding as well
+        assert_eq!(leaked.len(), 2);
+        assert!(leaked.iter().all(|pin| pin.page_id == id));
+        drop(kept);
+    }
+}
@@ -0,0 +1,100 @@
+use std::alloc::{self, Layout};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::slice;
+
+// every page buffer starts on a 4 KiB boundary, which is what O_DIRECT
+// needs from the memory side. page sizes are all multiples of this
+pub const PAGE_ALIGNMENT: usize = 4096;
+
+// the raw bytes of a page, sized by the page size the database was created with
+pub struct Page {
+    data: NonNull<u8>,
+    len: usize,
+}
+
+impl Page {
+    pub fn zeroed(size: usize) -> Self {
+        assert!(size.is_multiple_of(PAGE_ALIGNMENT));
+        Page::zeroed_partial(size)
+    }
+
+    // fewer bytes than a whole block, like a compressed page. it still starts
+    // on a boundary, but can't be handed to a file opened with O_DIRECT
+    pub fn zeroed_partial(size: usize) -> Self {
+        assert!(size > 0);
+        let layout = Page::layout(size);
+        // SAFETY: the layout has a non zero size
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let data = match NonNull::new(ptr) {
+            Some(data) => data,
+            None => alloc::handle_alloc_error(layout),
+        };
+        Page { data, len: size }
+    }
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size, PAGE_ALIGNMENT).unwrap()
+    }
+}
+
+impl Clone for Page {
+    fn clone(&self) -> Self {
+        let mut copy = Page::zeroed_partial(self.len);
+        copy.copy_from_slice(self);
+        copy
+    }
+}
+
+impl Deref for Page {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: data points to len initialized bytes that we own
+        unsafe { slice::from_raw_parts(self.data.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for Page {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: data points to len initialized bytes that we own exclusively
+        unsafe { slice::from_raw_parts_mut(self.data.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for Page {
+    fn drop(&mut self) {
+        // SAFETY: data was allocated in zeroed with this same layout
+        unsafe { alloc::dealloc(self.data.as_ptr(), Page::layout(self.len)) }
+    }
+}
+
+// a page owns its buffer like a Box<[u8]> would
+unsafe impl Send for Page {}
+unsafe impl Sync for Page {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Page, PAGE_ALIGNMENT};
+
+    #[test]
+    fn aligned() {
+        for size in [4096, 8192, 16384, 32768] {
+            let page = Page::zeroed(size);
+            assert_eq!(page.as_ptr() as usize % PAGE_ALIGNMENT, 0);
+            assert_eq!(page.len(), size);
+            assert!(page.iter().all(|&b| b == 0));
+        }
+    }
+
+    #[test]
+    fn clone_is_deep() {
+        let mut page = Page::zeroed(4096);
+        page[10] = 3;
+        let mut copy = page.clone();
+        copy[10] = 4;
+        assert_eq!(page[10], 3);
+        assert_eq!(copy[10], 4);
+        assert_ne!(page.as_ptr(), copy.as_ptr());
+    }
+}
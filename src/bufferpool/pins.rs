@@ -0,0 +1,164 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+#[cfg(debug_assertions)]
+use std::{backtrace::Backtrace, collections::HashMap, sync::atomic::AtomicU64, sync::Mutex};
+
+use super::ID;
+
+// pin count for a single frame
+//
+// a frame can be pinned by many guards at once (hot pages like a btree root),
+// so this is a u32 instead of the u8 we started with, and every change is
+// checked so we panic loudly instead of silently wrapping around
+pub struct PinCount(AtomicU32);
+
+impl PinCount {
+    pub fn new() -> Self {
+        PinCount(AtomicU32::new(0))
+    }
+
+    pub fn pin(&self) -> u32 {
+        let previous = self
+            .0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                count.checked_add(1)
+            })
+            .expect("pin count overflow");
+        previous + 1
+    }
+
+    pub fn unpin(&self) -> u32 {
+        let previous = self
+            .0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                count.checked_sub(1)
+            })
+            .expect("pin count underflow, page was unpinned more times than it was pinned");
+        previous - 1
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+// a pin that was never released, reported when the pool is dropped
+pub struct LeakedPin {
+    pub page_id: ID,
+    pub frame: usize,
+    #[cfg(debug_assertions)]
+    pub backtrace: Backtrace,
+}
+
+impl fmt::Display for LeakedPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "leaked page guard for page {} in frame {}",
+            self.page_id, self.frame
+        )?;
+        #[cfg(debug_assertions)]
+        write!(f, ", pinned at:\n{}", self.backtrace)?;
+        Ok(())
+    }
+}
+
+// in debug builds we remember where every outstanding guard was created,
+// so a guard that never gets dropped (mem::forget, Rc cycles, ...)
+// can be tracked back to the code that pinned it.
+// set RUST_BACKTRACE=1 to capture full backtraces.
+//
+// in release builds this is a no-op
+#[cfg(debug_assertions)]
+pub struct PinTracker {
+    next_token: AtomicU64,
+    outstanding: Mutex<HashMap<u64, LeakedPin>>,
+}
+
+#[cfg(debug_assertions)]
+impl PinTracker {
+    pub fn new() -> Self {
+        PinTracker {
+            next_token: AtomicU64::new(0),
+            outstanding: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn track(&self, page_id: ID, frame: usize) -> u64 {
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        let pin = LeakedPin {
+            page_id,
+            frame,
+            backtrace: Backtrace::capture(),
+        };
+        self.outstanding.lock().unwrap().insert(token, pin);
+        token
+    }
+
+    pub fn release(&self, token: u64) {
+        self.outstanding.lock().unwrap().remove(&token);
+    }
+
+    pub fn outstanding(&self) -> Vec<LeakedPin> {
+        let mut outstanding = self.outstanding.lock().unwrap();
+        let mut pins: Vec<(u64, LeakedPin)> = outstanding.drain().collect();
+        pins.sort_by_key(|(token, _)| *token);
+        pins.into_iter().map(|(_, pin)| pin).collect()
+    }
+}
+
+#[cfg(not(debug_assertions))]
+pub struct PinTracker;
+
+#[cfg(not(debug_assertions))]
+impl PinTracker {
+    pub fn new() -> Self {
+        PinTracker
+    }
+
+    pub fn track(&self, _page_id: ID, _frame: usize) -> u64 {
+        0
+    }
+
+    pub fn release(&self, _token: u64) {}
+
+    pub fn outstanding(&self) -> Vec<LeakedPin> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PinCount;
+
+    #[test]
+    fn pin_past_u8() {
+        let count = PinCount::new();
+        for _ in 0..300 {
+            count.pin();
+        }
+        assert_eq!(count.get(), 300);
+        for _ in 0..300 {
+            count.unpin();
+        }
+        assert_eq!(count.get(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "pin count underflow")]
+    fn unpin_below_zero() {
+        let count = PinCount::new();
+        count.unpin();
+    }
+
+    #[test]
+    #[should_panic(expected = "pin count overflow")]
+    fn pin_overflow() {
+        let count = PinCount::new();
+        count
+            .0
+            .store(u32::MAX, std::sync::atomic::Ordering::Release);
+        count.pin();
+    }
+}
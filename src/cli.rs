@@ -0,0 +1,158 @@
+use crate::bufferpool::{eviction::LruK, EvictionStrategy, Pool};
+use crate::disk::DiskManager;
+use crate::page_interpretation::{Column, TupleFieldTypes};
+use crate::utils::btree::{BTree, KeyColumn};
+use std::path::Path;
+use std::sync::Mutex;
+
+const USAGE: &str = "usage: db btree <verify|dump> <database file> <root page> <key column>...
+key columns are types like INT, VARCHAR(64) or DECIMAL(10,2), with :desc on descending ones";
+
+const POOL_SIZE: usize = 64;
+
+// run the command in the arguments after the program's name, returning
+// what to tell the user if it fails
+pub fn run(args: &[String]) -> Result<(), String> {
+    let [command, action, path, root, key @ ..] = args else {
+        return Err(USAGE.to_string());
+    };
+    if command != "btree" || !matches!(action.as_str(), "verify" | "dump") || key.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    let root: u32 = root
+        .parse()
+        .map_err(|_| format!("root page {:?} isn't a page number", root))?;
+    let key = key
+        .iter()
+        .map(|spec| parse_key_column(spec))
+        .collect::<Result<Vec<_>, _>>()?;
+    // opening a file that isn't there would create it
+    if !Path::new(path).is_file() {
+        return Err(format!("no database file at {}", path));
+    }
+
+    let strat: Mutex<Box<dyn EvictionStrategy>> = Mutex::new(Box::new(LruK::new(POOL_SIZE, 2)));
+    let pool = Pool::with_disk(POOL_SIZE, strat, DiskManager::open(path));
+    // records are never decoded, so the schema doesn't matter
+    let tree = BTree::open(&pool, root, key, Vec::new());
+    if action == "verify" {
+        tree.verify().map_err(|e| e.to_string())?;
+        println!("ok, {} levels", tree.depth());
+    } else {
+        print!("{}", tree.dump().map_err(|e| e.to_string())?);
+    }
+    Ok(())
+}
+
+// a type, then :asc or :desc if it says which way it sorts
+fn parse_key_column(spec: &str) -> Result<KeyColumn, String> {
+    let (name, descending) = match spec.rsplit_once(':') {
+        Some((name, "asc")) => (name, false),
+        Some((name, "desc")) => (name, true),
+        Some(_) => return Err(format!("key column {:?} isn't :asc or :desc", spec)),
+        None => (spec, false),
+    };
+    let field_type = parse_type(name).ok_or_else(|| format!("unknown column type {:?}", name))?;
+    // a null key just encodes as a marker, so nullable reads every key
+    let column = Column::nullable(field_type);
+    Ok(match descending {
+        true => KeyColumn::desc(column),
+        false => KeyColumn::asc(column),
+    })
+}
+
+fn parse_type(name: &str) -> Option<TupleFieldTypes> {
+    let name = name.trim().to_ascii_uppercase();
+    let (base, args) = match name.split_once('(') {
+        Some((base, rest)) => (base.trim(), Some(rest.strip_suffix(')')?)),
+        None => (name.as_str(), None),
+    };
+    let field_type = match (base, args) {
+        ("INT", None) => TupleFieldTypes::INT,
+        ("SIGNEDINT", None) => TupleFieldTypes::SIGNEDINT,
+        ("DOUBLE", None) => TupleFieldTypes::DOUBLE,
+        ("CHAR", None) => TupleFieldTypes::CHAR,
+        ("TEXT256", None) => TupleFieldTypes::TEXT256,
+        ("BOOL", None) => TupleFieldTypes::BOOL,
+        ("BIGINT", None) => TupleFieldTypes::BIGINT,
+        ("SIGNEDBIGINT", None) => TupleFieldTypes::SIGNEDBIGINT,
+        ("DOUBLE64", None) => TupleFieldTypes::DOUBLE64,
+        ("DATE", None) => TupleFieldTypes::DATE,
+        ("TIMESTAMP", None) => TupleFieldTypes::TIMESTAMP,
+        ("BLOB", None) => TupleFieldTypes::BLOB,
+        ("VARCHAR", Some(len)) => TupleFieldTypes::VARCHAR(len.trim().parse().ok()?),
+        ("DECIMAL", Some(args)) => {
+            let (precision, scale) = args.split_once(',')?;
+            TupleFieldTypes::DECIMAL {
+                precision: precision.trim().parse().ok()?,
+                scale: scale.trim().parse().ok()?,
+            }
+        }
+        _ => return None,
+    };
+    Some(field_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_key_column, run};
+    use crate::bufferpool::{eviction::LruK, EvictionStrategy, Pool};
+    use crate::disk::DiskManager;
+    use crate::page_interpretation::{Column, TupleField, TupleFieldTypes};
+    use crate::utils::btree::{BTree, KeyColumn, Order};
+    use crate::utils::testing::temp_db;
+    use std::sync::Mutex;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn key_columns_parse() {
+        let key = parse_key_column("varchar(64):desc").unwrap();
+        assert_eq!(key.column.field_type, TupleFieldTypes::VARCHAR(64));
+        assert!(matches!(key.order, Order::Descending));
+        let key = parse_key_column("DECIMAL(10, 2)").unwrap();
+        assert_eq!(
+            key.column.field_type,
+            TupleFieldTypes::DECIMAL {
+                precision: 10,
+                scale: 2
+            }
+        );
+        assert!(matches!(key.order, Order::Ascending));
+        assert!(parse_key_column("INT:sideways").is_err());
+        assert!(parse_key_column("VARCHAR").is_err());
+        assert!(parse_key_column("INT(4)").is_err());
+    }
+
+    #[test]
+    fn verifies_a_tree_on_disk() {
+        let path = temp_db("cli_verify");
+        let root = {
+            let strat: Mutex<Box<dyn EvictionStrategy>> = Mutex::new(Box::new(LruK::new(16, 2)));
+            let pool = Pool::with_disk(16, strat, DiskManager::open(&path));
+            let key = vec![KeyColumn::asc(Column::not_null(TupleFieldTypes::INT))];
+            let schema = vec![Column::not_null(TupleFieldTypes::VARCHAR(100))];
+            let tree = BTree::create(&pool, key, schema).unwrap();
+            for i in 0..500 {
+                tree.insert(
+                    &[TupleField::Int(i)],
+                    &[TupleField::Varchar("x".repeat(50))],
+                )
+                .unwrap();
+            }
+            pool.flush_all();
+            tree.root_page()
+        };
+        let path = path.to_str().unwrap();
+        let root = root.to_string();
+        run(&args(&["btree", "verify", path, &root, "INT"])).unwrap();
+        run(&args(&["btree", "dump", path, &root, "INT"])).unwrap();
+        // the keys are one column, not two
+        assert!(run(&args(&["btree", "verify", path, &root, "INT", "INT"])).is_err());
+        assert!(run(&args(&["btree", "verify", path, &root])).is_err());
+        assert!(run(&args(&["btree", "check", path, &root, "INT"])).is_err());
+        assert!(run(&args(&["btree", "verify", "/no/such/db", &root, "INT"])).is_err());
+    }
+}
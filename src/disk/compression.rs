@@ -0,0 +1,434 @@
+use super::{get_file_offset, max_pages, read_exact_at, write_all_at, Corruption, DiskManager};
+use crate::bufferpool::{Page, PAGE_ALIGNMENT};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+// compressed pages start with a header:
+//
+// | magic (8 bytes) | compressed length (u32) | crc32 of the plain page (u32) | lz4 block ... |
+//
+// and are padded out to whole 512 byte sectors. filesystems only punch holes
+// in whole blocks, so a compressed page left in its own slot of the database
+// would only save space when pages are bigger than a block. instead compressed
+// pages are packed back to back into a file next to the database, and their
+// slot in the database is punched out:
+//
+// | map: (first sector u32, sector count u16, unused u16) per page | sectors ... |
+//
+// a sector count of 0 means the page is in the database as it is. a rewrite
+// goes to free sectors and only then repoints the map, so a crash in between
+// leaves the old copy. only pages the map points at are decompressed, so a
+// plain page is read as it is whatever it starts with
+const MAGIC: [u8; 8] = *b"dbLZ4pg\0";
+const HEADER_SIZE: usize = 16;
+const SECTOR: usize = 512;
+const MAP_ENTRY: usize = 8;
+
+// None if compressing doesn't save at least a sector
+fn compress(page: &[u8]) -> Option<Page> {
+    let compressed = lz4_flex::block::compress(page);
+    let len = (HEADER_SIZE + compressed.len()).next_multiple_of(SECTOR);
+    if len >= page.len() {
+        return None;
+    }
+    let mut out = Page::zeroed_partial(len);
+    out[..8].copy_from_slice(&MAGIC);
+    out[8..12].copy_from_slice(&(compressed.len() as u32).to_be_bytes());
+    out[12..16].copy_from_slice(&crc32fast::hash(page).to_be_bytes());
+    out[HEADER_SIZE..HEADER_SIZE + compressed.len()].copy_from_slice(&compressed);
+    Some(out)
+}
+
+// replace a compressed page with its plain contents
+fn decompress(page: &mut Page) -> Result<(), &'static str> {
+    if page[..8] != MAGIC {
+        return Err("packed page isn't compressed");
+    }
+    let len = u32::from_be_bytes(page[8..12].try_into().unwrap()) as usize;
+    let crc = u32::from_be_bytes(page[12..16].try_into().unwrap());
+    if HEADER_SIZE + len > page.len() {
+        return Err("compressed page is longer than a page");
+    }
+    let mut plain = Page::zeroed(page.len());
+    let written =
+        lz4_flex::block::decompress_into(&page[HEADER_SIZE..HEADER_SIZE + len], &mut plain)
+            .map_err(|_| "compressed page is corrupt")?;
+    if written != page.len() {
+        return Err("compressed page decompressed to the wrong size");
+    }
+    if crc32fast::hash(&plain) != crc {
+        return Err("compressed page failed its checksum");
+    }
+    *page = plain;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn punch_hole(file: &File, offset: u64, len: u64) {
+    use std::os::unix::io::AsRawFd;
+    // only saves space, filesystems that can't punch holes just keep the old bytes
+    unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            len as libc::off_t,
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn punch_hole(_file: &File, _offset: u64, _len: u64) {}
+
+pub(super) fn packed_path(db_path: &Path) -> PathBuf {
+    let mut path = OsString::from(db_path.as_os_str());
+    path.push(".lz4");
+    PathBuf::from(path)
+}
+
+// the file compressed pages are packed into
+pub(super) struct Packed {
+    file: File,
+    // where the sectors start, after the map
+    sectors_at: u64,
+    // each packed page's first sector and sector count
+    pages: HashMap<u32, (u32, u16)>,
+    // the same, by first sector, to find the free sectors between them
+    runs: BTreeMap<u32, u16>,
+}
+
+impl Packed {
+    pub(super) fn open(db_path: &Path, page_size: usize) -> Self {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(packed_path(db_path))
+            .unwrap();
+        let map_len = (max_pages(page_size) as usize * MAP_ENTRY).next_multiple_of(PAGE_ALIGNMENT);
+        // the map only grows as far as the last page that was packed
+        let mut map = vec![0; map_len];
+        let file_len = file.metadata().unwrap().len() as usize;
+        read_exact_at(&file, &mut map[..file_len.min(map_len)], 0).unwrap();
+
+        let mut packed = Packed {
+            file,
+            sectors_at: map_len as u64,
+            pages: HashMap::new(),
+            runs: BTreeMap::new(),
+        };
+        for (page_id, entry) in map.chunks_exact(MAP_ENTRY).enumerate() {
+            let count = u16::from_be_bytes([entry[4], entry[5]]);
+            if count > 0 {
+                let sector = u32::from_be_bytes(entry[..4].try_into().unwrap());
+                packed.pages.insert(page_id as u32, (sector, count));
+                packed.runs.insert(sector, count);
+            }
+        }
+        packed
+    }
+
+    fn sector_offset(&self, sector: u32) -> u64 {
+        self.sectors_at + sector as u64 * SECTOR as u64
+    }
+
+    // read a packed page's sectors into the front of the page, false if it isn't packed
+    fn read(&self, page_id: u32, page: &mut Page) -> bool {
+        let Some(&(sector, count)) = self.pages.get(&page_id) else {
+            return false;
+        };
+        let len = count as usize * SECTOR;
+        read_exact_at(&self.file, &mut page[..len], self.sector_offset(sector)).unwrap();
+        page[len..].fill(0);
+        true
+    }
+
+    fn write(&mut self, page_id: u32, bytes: &[u8]) {
+        let count = (bytes.len() / SECTOR) as u16;
+        let sector = self.allocate(count);
+        write_all_at(&self.file, bytes, self.sector_offset(sector)).unwrap();
+        self.write_entry(page_id, sector, count);
+        if let Some(old) = self.pages.insert(page_id, (sector, count)) {
+            self.free(old);
+        }
+    }
+
+    // the page went back into the database as it is
+    fn remove(&mut self, page_id: u32) {
+        if let Some(old) = self.pages.remove(&page_id) {
+            self.write_entry(page_id, 0, 0);
+            self.free(old);
+        }
+    }
+
+    fn write_entry(&self, page_id: u32, sector: u32, count: u16) {
+        let mut entry = [0; MAP_ENTRY];
+        entry[..4].copy_from_slice(&sector.to_be_bytes());
+        entry[4..6].copy_from_slice(&count.to_be_bytes());
+        write_all_at(&self.file, &entry, page_id as u64 * MAP_ENTRY as u64).unwrap();
+    }
+
+    // the first gap between runs with room, or the end of the last one
+    fn allocate(&mut self, count: u16) -> u32 {
+        let mut free = 0;
+        for (&start, &len) in &self.runs {
+            if start - free >= count as u32 {
+                break;
+            }
+            free = start + len as u32;
+        }
+        self.runs.insert(free, count);
+        free
+    }
+
+    fn free(&mut self, (sector, count): (u32, u16)) {
+        self.runs.remove(&sector);
+        // punch out every whole block in the gap the run leaves behind
+        let gap_start = match self.runs.range(..sector).next_back() {
+            Some((&start, &len)) => start + len as u32,
+            None => 0,
+        };
+        let gap_end = match self.runs.range(sector..).next() {
+            Some((&start, _)) => start,
+            None => sector + count as u32,
+        };
+        let block = PAGE_ALIGNMENT as u64;
+        let start = self.sector_offset(gap_start).next_multiple_of(block);
+        let end = self.sector_offset(gap_end) / block * block;
+        if end > start {
+            punch_hole(&self.file, start, end - start);
+        }
+    }
+}
+
+impl DiskManager {
+    // compress this page whenever it is written from now on.
+    // pages that were read compressed stay compressed without asking again
+    pub fn set_compressed(&self, page_id: u32, compressed: bool) {
+        let mut map = self.compressed.lock().unwrap();
+        if compressed {
+            map.set(page_id as usize);
+        } else {
+            map.unset(page_id as usize);
+        }
+    }
+
+    pub(super) fn compress_page(&self, page_id: u32, page: &Page) -> Option<Page> {
+        if !self.compressed.lock().unwrap().check(page_id as usize) {
+            return None;
+        }
+        compress(page)
+    }
+
+    pub(super) fn is_packed(&self, page_id: u32) -> bool {
+        self.packed.lock().unwrap().pages.contains_key(&page_id)
+    }
+
+    pub(super) fn read_packed(&self, page_id: u32, page: &mut Page) -> bool {
+        self.packed.lock().unwrap().read(page_id, page)
+    }
+
+    // the page's slot in the database is stale once the map points at the packed copy
+    pub(super) fn write_packed(&self, page_id: u32, bytes: &[u8]) {
+        self.packed.lock().unwrap().write(page_id, bytes);
+        let offset = get_file_offset(page_id, self.page_size);
+        punch_hole(&self.file, offset, self.page_size as u64);
+    }
+
+    // after the page was written to its slot in the database as it is
+    pub(super) fn unpack(&self, page_id: u32) {
+        self.packed.lock().unwrap().remove(page_id);
+    }
+
+    // after read_raw, which read the page from the packed file if the map points there
+    pub(super) fn decompress_page(&self, page_id: u32, page: &mut Page) -> Result<(), Corruption> {
+        if !self.is_packed(page_id) {
+            return Ok(());
+        }
+        decompress(page).map_err(|reason| Corruption { page_id, reason })?;
+        self.set_compressed(page_id, true);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress, packed_path};
+    #[cfg(target_os = "linux")]
+    use crate::disk::IoRequest;
+    use crate::disk::{DiskManager, PageSize};
+    use crate::utils::testing::temp_path;
+    #[cfg(target_os = "linux")]
+    use std::os::unix::fs::MetadataExt;
+    #[cfg(target_os = "linux")]
+    use std::path::Path;
+
+    // the database and the compressed pages packed next to it.
+    // holes are only punched on linux, so that's the only place this shrinks
+    #[cfg(target_os = "linux")]
+    fn allocated_bytes(path: &Path) -> u64 {
+        [path.to_path_buf(), packed_path(path)]
+            .iter()
+            .map(|path| std::fs::metadata(path).map_or(0, |m| m.blocks() * 512))
+            .sum()
+    }
+
+    fn repetitive(page: &mut [u8], seed: u8) {
+        for (i, byte) in page.iter_mut().enumerate() {
+            *byte = seed.wrapping_add((i / 100) as u8);
+        }
+    }
+
+    #[test]
+    fn only_compresses_when_it_saves_a_sector() {
+        let mut page = crate::bufferpool::Page::zeroed(4096);
+        repetitive(&mut page, 3);
+        let compressed = compress(&page).unwrap();
+        assert!(compressed.len() <= 1024);
+        assert_eq!(compressed.len() % 512, 0);
+        // as a read finds it, the rest of the page is zeros
+        let mut read = crate::bufferpool::Page::zeroed(4096);
+        read[..compressed.len()].copy_from_slice(&compressed);
+        assert_eq!(decompress(&mut read), Ok(()));
+        assert_eq!(&read[..], &page[..]);
+
+        // random bytes don't compress
+        let mut rng = crate::utils::testing::Rng::new(39);
+        let noise: Vec<u8> = (0..4096).map(|_| rng.next_u32() as u8).collect();
+        assert!(compress(&noise).is_none());
+        // pages without the header aren't compressed
+        assert!(decompress(&mut page.clone()).is_err());
+        // and damaged ones are reported rather than handed back
+        read[..compressed.len()].copy_from_slice(&compressed);
+        read[100] ^= 1;
+        assert!(decompress(&mut read).is_err());
+    }
+
+    // write every page, half through write and half through a batch
+    #[cfg(target_os = "linux")]
+    fn write_all(disk: &DiskManager, ids: &[u32], pages: &[crate::bufferpool::Page]) {
+        for (&id, page) in ids.iter().zip(pages).take(ids.len() / 2) {
+            disk.write(id, page);
+        }
+        let mut writes: Vec<_> = ids
+            .iter()
+            .zip(pages)
+            .skip(ids.len() / 2)
+            .map(|(&id, page)| IoRequest::Write(id, page))
+            .collect();
+        disk.submit(&mut writes);
+    }
+
+    #[cfg(target_os = "linux")]
+    fn compressed_pages_use_less_disk(name: &str, page_size: PageSize) {
+        let path = temp_path(name);
+        let disk = DiskManager::create(&path, page_size);
+        let ids: Vec<u32> = (0..32).map(|_| disk.new_page().unwrap()).collect();
+        let mut pages: Vec<_> = ids.iter().map(|&id| disk.read(id)).collect();
+        for (i, page) in pages.iter_mut().enumerate() {
+            repetitive(page, i as u8);
+        }
+        write_all(&disk, &ids, &pages);
+        disk.persist();
+        let plain = allocated_bytes(&path);
+
+        for &id in &ids {
+            disk.set_compressed(id, true);
+        }
+        write_all(&disk, &ids, &pages);
+        // each page shrinks to a sector or two, packed many to a block
+        let saved = plain - allocated_bytes(&path);
+        assert!(saved >= 32 * page_size.bytes() as u64 * 3 / 4, "{}", saved);
+        drop(disk);
+
+        // reads decompress, and pages read compressed are written compressed again
+        let disk = DiskManager::open(&path);
+        let mut read_back: Vec<_> = ids.iter().map(|_| disk.read(ids[0])).collect();
+        let mut reads: Vec<_> = ids
+            .iter()
+            .zip(read_back.iter_mut())
+            .map(|(&id, page)| IoRequest::Read(id, page))
+            .collect();
+        disk.submit(&mut reads);
+        drop(reads);
+        for (page, expected) in read_back.iter().zip(&pages) {
+            assert_eq!(&page[..], &expected[..]);
+        }
+        let before = allocated_bytes(&path);
+        disk.write(ids[0], &pages[0]);
+        assert!(disk.is_packed(ids[0]));
+        // the new copy goes to free sectors before the old one is let go
+        assert!(allocated_bytes(&path) <= before + 4096);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn compressed_4k_pages_use_less_disk() {
+        compressed_pages_use_less_disk("compressed_4k_pages", PageSize::Size4K);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn compressed_16k_pages_use_less_disk() {
+        compressed_pages_use_less_disk("compressed_16k_pages", PageSize::Size16K);
+    }
+
+    #[test]
+    fn plain_pages_that_look_compressed_are_read_as_they_are() {
+        let path = temp_path("looks_compressed");
+        let disk = DiskManager::create(&path, PageSize::Size4K);
+        let id = disk.new_page().unwrap();
+        let mut page = crate::bufferpool::Page::zeroed(4096);
+        repetitive(&mut page, 5);
+        let mut lookalike = crate::bufferpool::Page::zeroed(4096);
+        let compressed = compress(&page).unwrap();
+        lookalike[..compressed.len()].copy_from_slice(&compressed);
+        disk.write(id, &lookalike);
+        drop(disk);
+
+        let disk = DiskManager::open(&path);
+        assert_eq!(&disk.read(id)[..], &lookalike[..]);
+    }
+
+    #[test]
+    fn pages_move_between_the_database_and_the_packed_file() {
+        let path = temp_path("packed_moves");
+        let disk = DiskManager::create(&path, PageSize::Size4K);
+        let ids: Vec<u32> = (0..8).map(|_| disk.new_page().unwrap()).collect();
+        let mut compressible = crate::bufferpool::Page::zeroed(4096);
+        repetitive(&mut compressible, 7);
+        let mut rng = crate::utils::testing::Rng::new(39);
+        let mut noise = crate::bufferpool::Page::zeroed(4096);
+        noise.iter_mut().for_each(|b| *b = rng.next_u32() as u8);
+
+        for &id in &ids {
+            disk.set_compressed(id, true);
+            disk.write(id, &compressible);
+        }
+        assert!(ids.iter().all(|&id| disk.is_packed(id)));
+        // a page that stops compressing goes back to its slot, and its
+        // sectors are reused by the next page that needs them
+        let sector = |id| disk.packed.lock().unwrap().pages.get(&id).map(|run| run.0);
+        let freed = sector(ids[3]);
+        disk.write(ids[3], &noise);
+        assert_eq!(sector(ids[3]), None);
+        assert_eq!(&disk.read(ids[3])[..], &noise[..]);
+        disk.write(ids[3], &compressible);
+        assert_eq!(sector(ids[3]), freed);
+        // deleting a page unpacks it
+        disk.delete_page(ids[5]);
+        assert!(!disk.is_packed(ids[5]));
+        assert!(disk.read(ids[5]).iter().all(|&b| b == 0));
+        drop(disk);
+
+        let disk = DiskManager::open(&path);
+        for &id in &ids {
+            let expected = if id == ids[5] { 0 } else { 1 };
+            assert_eq!(disk.is_packed(id) as u8, expected);
+        }
+        assert_eq!(&disk.read(ids[3])[..], &compressible[..]);
+    }
+}
@@ -0,0 +1,481 @@
+use super::{read_exact_at, write_all_at, Corruption};
+use crate::bufferpool::Page;
+
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{Key, Tag, XChaCha20Poly1305, XNonce};
+use pbkdf2::pbkdf2_hmac;
+use sha2::Sha256;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+// the key itself is never stored. everything else needed to open a page lives
+// in a sidecar file next to the database (<path>.crypt), starting with:
+//
+// | magic (8 bytes) | salt (16 bytes) | pbkdf2 rounds (u32) | key check nonce (24 bytes) | key check tag (16 bytes) |
+//
+// followed by two slots per page:
+//
+// | nonce (24 bytes) | tag (16 bytes) | sealed length (u32) | write count (u64) |
+//
+// a write seals into the page's older slot and syncs the sidecar before the page
+// itself is written, so until the page write lands the newer slot still opens
+// what's on disk. a crash in between leaves the page as it was, rather than unreadable
+//
+// pages are encrypted in place, so they still fit their slot in the database
+// file. a nonce is the page id followed by 20 random bytes, and the page id and
+// length are authenticated along with the contents, so a page copied into
+// another page's slot fails to open just like a tampered one does.
+// the metadata pages (header and free page bitmap) stay in plaintext
+const MAGIC: [u8; 8] = *b"dbcrypt\0";
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: usize = 16;
+const HEADER_SIZE: usize = MAGIC.len() + SALT_SIZE + 4 + NONCE_SIZE + TAG_SIZE;
+const SLOT_SIZE: usize = NONCE_SIZE + TAG_SIZE + 4 + 8;
+
+// owasp's recommendation for pbkdf2-hmac-sha256
+const PBKDF2_ROUNDS: u32 = 600_000;
+
+// where the key for an encrypted database comes from, given each time it's opened
+#[derive(Clone)]
+#[allow(dead_code)]
+pub enum KeySource {
+    // stretched into a key with pbkdf2 and the salt in the sidecar
+    Passphrase(String),
+    // a file holding exactly the 32 bytes of the key
+    KeyFile(PathBuf),
+    Key([u8; 32]),
+}
+
+// options get printed, keys shouldn't be
+impl fmt::Debug for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::Passphrase(_) => write!(f, "Passphrase(..)"),
+            KeySource::KeyFile(path) => write!(f, "KeyFile({:?})", path),
+            KeySource::Key(_) => write!(f, "Key(..)"),
+        }
+    }
+}
+
+impl KeySource {
+    fn derive(&self, salt: &[u8], rounds: u32) -> [u8; 32] {
+        match self {
+            KeySource::Passphrase(passphrase) => {
+                let mut key = [0; 32];
+                pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, rounds, &mut key);
+                key
+            }
+            KeySource::KeyFile(path) => {
+                let bytes = std::fs::read(path).unwrap();
+                bytes
+                    .try_into()
+                    .unwrap_or_else(|_| panic!("key file {:?} must hold exactly 32 bytes", path))
+            }
+            KeySource::Key(key) => *key,
+        }
+    }
+}
+
+pub(super) fn sidecar_path(db_path: &Path) -> PathBuf {
+    let mut path = OsString::from(db_path.as_os_str());
+    path.push(".crypt");
+    PathBuf::from(path)
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).expect("couldn't get random bytes from the OS");
+    bytes
+}
+
+// what's authenticated alongside a page's contents
+fn associated_data(page_id: u32, len: u32) -> [u8; 8] {
+    let mut data = [0; 8];
+    data[..4].copy_from_slice(&page_id.to_be_bytes());
+    data[4..].copy_from_slice(&len.to_be_bytes());
+    data
+}
+
+fn slot_offset(page_id: u32, slot: usize) -> u64 {
+    HEADER_SIZE as u64 + (page_id as u64 * 2 + slot as u64) * SLOT_SIZE as u64
+}
+
+// how to open one sealing of a page
+#[derive(Clone, Copy)]
+struct Slot {
+    nonce: [u8; NONCE_SIZE],
+    tag: [u8; TAG_SIZE],
+    len: u32,
+    // counts up with every write of the page, so the newer slot has the bigger one
+    seq: u64,
+}
+
+impl Slot {
+    // None for a slot of all zeros, which was never written
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.iter().all(|&byte| byte == 0) {
+            return None;
+        }
+        let len_at = NONCE_SIZE + TAG_SIZE;
+        Some(Slot {
+            nonce: bytes[..NONCE_SIZE].try_into().unwrap(),
+            tag: bytes[NONCE_SIZE..len_at].try_into().unwrap(),
+            len: u32::from_be_bytes(bytes[len_at..len_at + 4].try_into().unwrap()),
+            seq: u64::from_be_bytes(bytes[len_at + 4..].try_into().unwrap()),
+        })
+    }
+
+    fn to_bytes(self) -> [u8; SLOT_SIZE] {
+        let mut bytes = [0; SLOT_SIZE];
+        let len_at = NONCE_SIZE + TAG_SIZE;
+        bytes[..NONCE_SIZE].copy_from_slice(&self.nonce);
+        bytes[NONCE_SIZE..len_at].copy_from_slice(&self.tag);
+        bytes[len_at..len_at + 4].copy_from_slice(&self.len.to_be_bytes());
+        bytes[len_at + 4..].copy_from_slice(&self.seq.to_be_bytes());
+        bytes
+    }
+}
+
+fn seq(slot: Option<Slot>) -> u64 {
+    slot.map_or(0, |slot| slot.seq)
+}
+
+pub(super) struct Cipher {
+    aead: XChaCha20Poly1305,
+    sidecar: File,
+}
+
+impl Cipher {
+    // an empty or missing sidecar is a new encrypted database, anything else
+    // has to match the key we were given
+    pub(super) fn open(db_path: &Path, source: &KeySource) -> Self {
+        let path = sidecar_path(db_path);
+        let sidecar = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+
+        let mut header = [0; HEADER_SIZE];
+        if sidecar.metadata().unwrap().len() == 0 {
+            let salt: [u8; SALT_SIZE] = random();
+            let aead =
+                XChaCha20Poly1305::new(Key::from_slice(&source.derive(&salt, PBKDF2_ROUNDS)));
+            // sealing nothing still gives a tag, which only the right key reproduces
+            let nonce: [u8; NONCE_SIZE] = random();
+            let tag = aead
+                .encrypt_in_place_detached(XNonce::from_slice(&nonce), &MAGIC, &mut [])
+                .unwrap();
+
+            let mut offset = 0;
+            for field in [
+                &MAGIC[..],
+                &salt,
+                &PBKDF2_ROUNDS.to_be_bytes(),
+                &nonce,
+                &tag,
+            ] {
+                header[offset..offset + field.len()].copy_from_slice(field);
+                offset += field.len();
+            }
+            write_all_at(&sidecar, &header, 0).unwrap();
+            return Cipher { aead, sidecar };
+        }
+
+        read_exact_at(&sidecar, &mut header, 0).unwrap();
+        assert_eq!(header[..8], MAGIC, "{:?} isn't an encryption sidecar", path);
+        let salt = &header[8..8 + SALT_SIZE];
+        let rounds = u32::from_be_bytes(header[24..28].try_into().unwrap());
+        let nonce = &header[28..28 + NONCE_SIZE];
+        let tag = &header[28 + NONCE_SIZE..];
+
+        let aead = XChaCha20Poly1305::new(Key::from_slice(&source.derive(salt, rounds)));
+        aead.decrypt_in_place_detached(
+            XNonce::from_slice(nonce),
+            &MAGIC,
+            &mut [],
+            Tag::from_slice(tag),
+        )
+        .unwrap_or_else(|_| panic!("wrong key for the encrypted database at {:?}", db_path));
+        Cipher { aead, sidecar }
+    }
+
+    fn slots(&self, page_id: u32) -> [Option<Slot>; 2] {
+        let mut bytes = [0; 2 * SLOT_SIZE];
+        for (slot, bytes) in bytes.chunks_mut(SLOT_SIZE).enumerate() {
+            match read_exact_at(&self.sidecar, bytes, slot_offset(page_id, slot)) {
+                Ok(()) => {}
+                // the sidecar only grows as far as the last slot written
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => bytes.fill(0),
+                Err(e) => panic!("{}", e),
+            }
+        }
+        [
+            Slot::from_bytes(&bytes[..SLOT_SIZE]),
+            Slot::from_bytes(&bytes[SLOT_SIZE..]),
+        ]
+    }
+
+    fn write_slot(&self, page_id: u32, slot: usize, bytes: &[u8; SLOT_SIZE]) {
+        write_all_at(&self.sidecar, bytes, slot_offset(page_id, slot)).unwrap();
+    }
+
+    // encrypt the bytes going into a page's slot, and remember how to open them
+    pub(super) fn seal(&self, page_id: u32, page: &mut Page) {
+        let mut nonce: [u8; NONCE_SIZE] = random();
+        nonce[..4].copy_from_slice(&page_id.to_be_bytes());
+        let len = page.len() as u32;
+        let tag = self
+            .aead
+            .encrypt_in_place_detached(
+                XNonce::from_slice(&nonce),
+                &associated_data(page_id, len),
+                page,
+            )
+            .unwrap();
+
+        // the newer slot opens what's on disk until the page is written, keep it
+        let slots = self.slots(page_id);
+        let older = (seq(slots[1]) < seq(slots[0])) as usize;
+        let slot = Slot {
+            nonce,
+            tag: tag.into(),
+            len,
+            seq: seq(slots[0]).max(seq(slots[1])) + 1,
+        };
+        self.write_slot(page_id, older, &slot.to_bytes());
+        // otherwise the page write could reach the disk before its slot does
+        self.sidecar.sync_data().unwrap();
+    }
+
+    // decrypt a page's slot as it was read from the file
+    pub(super) fn unseal(&self, page_id: u32, page: &mut Page) -> Result<(), Corruption> {
+        let slots = self.slots(page_id);
+        let newer = (seq(slots[1]) > seq(slots[0])) as usize;
+        for (tries, slot) in [newer, 1 - newer].into_iter().enumerate() {
+            let opened = match slots[slot] {
+                // pages that were never written are still the zeros the file grew by
+                None => page.iter().all(|&byte| byte == 0),
+                Some(sealed) => self.open_with(page_id, sealed, page),
+            };
+            if opened {
+                if tries > 0 {
+                    // the newer slot is from a write that never reached the page.
+                    // clear it, so the next write goes there instead of over this one
+                    self.write_slot(page_id, newer, &[0; SLOT_SIZE]);
+                }
+                return Ok(());
+            }
+        }
+        let reason = match slots {
+            [None, None] => "page isn't encrypted",
+            _ => "page failed authentication",
+        };
+        Err(Corruption { page_id, reason })
+    }
+
+    // decrypt page with one of its slots, leaving it untouched if that fails
+    fn open_with(&self, page_id: u32, slot: Slot, page: &mut Page) -> bool {
+        let len = slot.len as usize;
+        if len > page.len() {
+            return false;
+        }
+        let opened = self
+            .aead
+            .decrypt_in_place_detached(
+                XNonce::from_slice(&slot.nonce),
+                &associated_data(page_id, slot.len),
+                &mut page[..len],
+                Tag::from_slice(&slot.tag),
+            )
+            .is_ok();
+        if opened {
+            // compressed pages don't fill their slot, the rest is a hole
+            page[len..].fill(0);
+        }
+        opened
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sidecar_path, KeySource};
+    use crate::disk::{read_exact_at, write_all_at, DiskManager, DiskOptions, PageSize};
+    use crate::utils::testing::temp_path;
+
+    fn options(key: KeySource) -> DiskOptions {
+        DiskOptions {
+            key: Some(key),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pages_round_trip_and_are_unreadable_on_disk() {
+        let path = temp_path("encrypted_pages");
+        let key = KeySource::Key([7; 32]);
+        let disk = DiskManager::create_with(&path, PageSize::Size4K, options(key.clone()));
+        let ids: Vec<u32> = (0..4).map(|_| disk.new_page().unwrap()).collect();
+        // never written pages read back as zeros
+        assert!(disk.read(ids[3]).iter().all(|&byte| byte == 0));
+        for &id in &ids[..3] {
+            let mut page = disk.read(id);
+            page[..18].copy_from_slice(b"attack at dawn!!!!");
+            page[4095] = id as u8;
+            disk.write(id, &page);
+        }
+        drop(disk);
+
+        let raw = std::fs::read(&path).unwrap();
+        assert!(!raw.windows(14).any(|w| w == b"attack at dawn"));
+
+        let disk = DiskManager::open_with(&path, options(key));
+        for &id in &ids[..3] {
+            let page = disk.read(id);
+            assert_eq!(&page[..18], b"attack at dawn!!!!");
+            assert_eq!(page[4095], id as u8);
+        }
+    }
+
+    #[test]
+    fn tampering_is_reported_as_corruption() {
+        let path = temp_path("encrypted_tampering");
+        let disk =
+            DiskManager::create_with(&path, PageSize::Size4K, options(KeySource::Key([1; 32])));
+        let a = disk.new_page().unwrap();
+        let b = disk.new_page().unwrap();
+        let mut page = disk.read(a);
+        page[0] = 1;
+        disk.write(a, &page);
+        page[0] = 2;
+        disk.write(b, &page);
+
+        // flip a bit of a's ciphertext
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .read(true)
+            .open(&path)
+            .unwrap();
+        let offset = super::super::get_file_offset(a, 4096);
+        let mut byte = [0];
+        read_exact_at(&file, &mut byte, offset + 10).unwrap();
+        write_all_at(&file, &[byte[0] ^ 1], offset + 10).unwrap();
+        let Err(err) = disk.try_read(a) else {
+            panic!("tampered page was read back")
+        };
+        assert_eq!((err.page_id, err.reason), (a, "page failed authentication"));
+
+        // b's bytes and its slot copied over a's still don't open as a
+        let mut b_bytes = vec![0; 4096];
+        read_exact_at(&file, &mut b_bytes, super::super::get_file_offset(b, 4096)).unwrap();
+        write_all_at(&file, &b_bytes, offset).unwrap();
+        let sidecar = std::fs::OpenOptions::new()
+            .write(true)
+            .read(true)
+            .open(sidecar_path(&path))
+            .unwrap();
+        // each was written once, into its first slot
+        let mut slot = [0; super::SLOT_SIZE];
+        read_exact_at(&sidecar, &mut slot, super::slot_offset(b, 0)).unwrap();
+        write_all_at(&sidecar, &slot, super::slot_offset(a, 0)).unwrap();
+        assert!(disk.try_read(a).is_err());
+        assert_eq!(disk.try_read(b).unwrap()[0], 2);
+    }
+
+    #[test]
+    fn writes_that_never_reach_the_page_fall_back_to_the_last_one() {
+        let path = temp_path("encrypted_torn_writes");
+        let disk =
+            DiskManager::create_with(&path, PageSize::Size4K, options(KeySource::Key([2; 32])));
+        let cipher = disk.cipher.as_ref().unwrap();
+        let id = disk.new_page().unwrap();
+        let mut page = disk.read(id);
+
+        // sealing writes the slot, a crash before the page write looks like this
+        page[0] = 1;
+        cipher.seal(id, &mut page.clone());
+        assert!(disk.try_read(id).unwrap().iter().all(|&byte| byte == 0));
+
+        page[0] = 2;
+        disk.write(id, &page);
+        page[0] = 3;
+        cipher.seal(id, &mut page.clone());
+        assert_eq!(disk.try_read(id).unwrap()[0], 2);
+        // and crashing again right after doesn't lose the slot that opens it
+        cipher.seal(id, &mut page.clone());
+        assert_eq!(disk.try_read(id).unwrap()[0], 2);
+
+        page[0] = 4;
+        disk.write(id, &page);
+        assert_eq!(disk.try_read(id).unwrap()[0], 4);
+    }
+
+    #[test]
+    fn wrong_passphrase_is_refused() {
+        let path = temp_path("encrypted_passphrase");
+        let right = KeySource::Passphrase("correct horse battery staple".to_string());
+        let disk = DiskManager::create_with(&path, PageSize::Size4K, options(right.clone()));
+        let id = disk.new_page().unwrap();
+        let mut page = disk.read(id);
+        page[0] = 9;
+        disk.write(id, &page);
+        drop(disk);
+
+        let wrong = KeySource::Passphrase("Tr0ub4dor&3".to_string());
+        let opened = std::panic::catch_unwind(|| DiskManager::open_with(&path, options(wrong)));
+        assert!(opened.is_err());
+        // and leaving the key out altogether doesn't read ciphertext as pages
+        assert!(std::panic::catch_unwind(|| DiskManager::open(&path)).is_err());
+
+        let disk = DiskManager::open_with(&path, options(right));
+        assert_eq!(disk.read(id)[0], 9);
+    }
+
+    #[test]
+    fn key_files_hold_raw_keys() {
+        let path = temp_path("encrypted_key_file");
+        let key_path = temp_path("encrypted_key_file.key");
+        std::fs::write(&key_path, [3; 32]).unwrap();
+        let disk = DiskManager::create_with(
+            &path,
+            PageSize::Size4K,
+            options(KeySource::KeyFile(key_path.clone())),
+        );
+        let id = disk.new_page().unwrap();
+        disk.write(id, &disk.read(id));
+        drop(disk);
+
+        // the same bytes given directly are the same key
+        let disk = DiskManager::open_with(&path, options(KeySource::Key([3; 32])));
+        assert!(disk.try_read(id).is_ok());
+        assert_eq!(format!("{:?}", KeySource::Key([3; 32])), "Key(..)");
+    }
+
+    #[test]
+    #[cfg(all(feature = "compression", target_os = "linux"))]
+    fn compressed_pages_are_encrypted_too() {
+        use std::os::unix::fs::MetadataExt;
+
+        let path = temp_path("encrypted_compressed");
+        let disk =
+            DiskManager::create_with(&path, PageSize::Size16K, options(KeySource::Key([5; 32])));
+        let id = disk.new_page().unwrap();
+        let mut page = disk.read(id);
+        for (i, byte) in page.iter_mut().enumerate() {
+            *byte = (i / 100) as u8;
+        }
+        disk.write(id, &page);
+        let before = std::fs::metadata(&path).unwrap().blocks();
+        disk.set_compressed(id, true);
+        disk.write(id, &page);
+        // compressed before it's encrypted, or it wouldn't have shrunk
+        assert!(std::fs::metadata(&path).unwrap().blocks() < before);
+        assert_eq!(&disk.read(id)[..], &page[..]);
+    }
+}
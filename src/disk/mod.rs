@@ -1,26 +1,190 @@
-use crate::bufferpool::Page;
+#[cfg(feature = "compression")]
+mod compression;
+#[cfg(feature = "encryption")]
+mod encryption;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+
+use crate::bufferpool::{Page, PAGE_ALIGNMENT};
 use crate::utils::bitmap::Bitmap;
 
+use std::fmt;
 use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
-use std::io::{self, SeekFrom};
+use std::path::Path;
 use std::sync::Mutex;
 
+#[cfg(feature = "encryption")]
+pub use encryption::KeySource;
+
 const SPECIAL_PAGES: u32 = 4;
 
-fn merge_u8(first: u8, second: u8) -> u16 {
-    (first as u16) << 8 | (second as u16)
+// the magic, then the version, capacity, used pages and page size,
+// each a big endian u32. see docs/metadata.md
+const MAGIC: [u8; 8] = *b"dbfile\0\0";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 24;
+
+// databases from before the header had a magic or a version: 4 KiB pages,
+// a u16 capacity and used count, then the free page bitmap
+const BASELINE_PAGE_SIZE: usize = 4096;
+const BASELINE_MAX_PAGES: u32 = 4096 * 8;
+
+// how many pages we grow the file by when we run out of space
+const GROWTH: u32 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageSize {
+    Size4K,
+    Size8K,
+    Size16K,
+    Size32K,
+}
+
+impl PageSize {
+    pub fn bytes(&self) -> usize {
+        match self {
+            PageSize::Size4K => 4096,
+            PageSize::Size8K => 8192,
+            PageSize::Size16K => 16384,
+            PageSize::Size32K => 32768,
+        }
+    }
+
+    fn from_header(bytes: u32) -> Option<Self> {
+        match bytes {
+            4096 => Some(PageSize::Size4K),
+            8192 => Some(PageSize::Size8K),
+            16384 => Some(PageSize::Size16K),
+            32768 => Some(PageSize::Size32K),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DiskOptions {
+    // bypass the OS page cache with O_DIRECT (linux only), so the buffer
+    // pool is the only place pages are cached. every buffer we hand to the
+    // file must then be a Page, since those are aligned
+    pub direct_io: bool,
+    // encrypt every page at rest. an encrypted database has to be opened with
+    // the same key it was created with
+    #[cfg(feature = "encryption")]
+    pub key: Option<KeySource>,
+}
+
+// a page whose bytes on disk aren't what we wrote there
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Corruption {
+    pub page_id: u32,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "page {} is corrupt: {}", self.page_id, self.reason)
+    }
+}
+
+impl std::error::Error for Corruption {}
+
+// one page of a batch handed to DiskManager::submit
+pub enum IoRequest<'a> {
+    Read(u32, &'a mut Page),
+    Write(u32, &'a Page),
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_be_bytes(bytes)
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
 }
 
-fn split_u16(data: u16) -> (u8, u8) {
-    let first: u8 = (data >> 8) as u8;
-    let second: u8 = (data & 0xFF) as u8;
-    (first, second)
+fn write_header(data: &mut [u8], capacity: u32, used: u32, page_size: usize) {
+    data[..MAGIC.len()].copy_from_slice(&MAGIC);
+    write_u32(data, 8, VERSION);
+    write_u32(data, 12, capacity);
+    write_u32(data, 16, used);
+    write_u32(data, 20, page_size as u32);
 }
 
-fn get_file_offset(page_id: u32) -> SeekFrom {
+// rewrite a baseline database's metadata pages with the current header.
+// its used count can't be more than its capacity, but "dbfile" read as
+// two u16s would be, so no baseline file starts with the magic.
+// the bitmap covers at most 2^15 pages, which still fits after the longer header
+fn upgrade_baseline(file: &File) {
+    let mut old = Page::zeroed(SPECIAL_PAGES as usize * BASELINE_PAGE_SIZE);
+    read_exact_at(file, &mut old, 0).unwrap();
+    let capacity = u16::from_be_bytes([old[0], old[1]]) as u32;
+    let used = u16::from_be_bytes([old[2], old[3]]) as u32;
+    assert!(
+        used <= capacity && capacity <= BASELINE_MAX_PAGES,
+        "not a database file, its header has no magic and isn't an older header either"
+    );
+
+    let mut new = Page::zeroed(old.len());
+    write_header(&mut new, capacity, used, BASELINE_PAGE_SIZE);
+    let bitmap = (capacity as usize).div_ceil(8);
+    new[HEADER_SIZE..HEADER_SIZE + bitmap].copy_from_slice(&old[4..4 + bitmap]);
+    write_all_at(file, &new, 0).unwrap();
+}
+
+fn get_file_offset(page_id: u32, page_size: usize) -> u64 {
     let physical_page = page_id + SPECIAL_PAGES;
-    SeekFrom::Start(4096 * physical_page as u64)
+    page_size as u64 * physical_page as u64
+}
+
+// the free page bitmap fills every metadata page after the header
+fn max_pages(page_size: usize) -> u32 {
+    ((SPECIAL_PAGES as usize * page_size - HEADER_SIZE) * 8) as u32
+}
+
+// reads and writes at an offset, without moving a cursor other threads share
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+// elsewhere there's only the cursor, so each seek and the read or write
+// after it happen under one lock
+#[cfg(not(unix))]
+static CURSOR: Mutex<()> = Mutex::new(());
+
+#[cfg(not(unix))]
+fn read_exact_at(mut file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    let _cursor = CURSOR.lock().unwrap();
+    file.seek(std::io::SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+#[cfg(not(unix))]
+fn write_all_at(mut file: &File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    let _cursor = CURSOR.lock().unwrap();
+    file.seek(std::io::SeekFrom::Start(offset))?;
+    file.write_all(buf)
+}
+
+#[cfg(target_os = "linux")]
+fn set_direct_io(options: &mut OpenOptions) {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.custom_flags(libc::O_DIRECT);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_direct_io(_options: &mut OpenOptions) {
+    panic!("direct I/O is only supported on linux");
 }
 
 fn min<T: Ord>(first: T, second: T) -> T {
@@ -32,82 +196,330 @@ fn min<T: Ord>(first: T, second: T) -> T {
 }
 
 pub struct DiskManager {
-    capacity: Mutex<u16>,
-    used: Mutex<u16>,
+    page_size: usize,
+    capacity: Mutex<u32>,
+    used: Mutex<u32>,
     map: Mutex<Bitmap>,
-    file: Mutex<File>,
+    // every access goes through read_exact_at / write_all_at with its own
+    // offset, so on unix there's no shared cursor and no need to lock the file
+    file: File,
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    uring: Option<uring::Uring>,
+    // pages to compress when they're written
+    #[cfg(feature = "compression")]
+    compressed: Mutex<Bitmap>,
+    // where compressed pages are written instead of their slot
+    #[cfg(feature = "compression")]
+    packed: Mutex<compression::Packed>,
+    #[cfg(feature = "encryption")]
+    cipher: Option<encryption::Cipher>,
+}
+
+// pages are compressed and then encrypted on the way to the file, and the
+// other way around on the way back. without either feature every page goes
+// to the file as it is
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+impl DiskManager {
+    // the bytes to write in place of the page, if they're any different
+    fn encode_page(&self, page_id: u32, page: &Page) -> Option<Page> {
+        let encoded = self.compress_page(page_id, page);
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            let mut sealed = encoded.unwrap_or_else(|| page.clone());
+            cipher.seal(page_id, &mut sealed);
+            return Some(sealed);
+        }
+        encoded
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn compress_page(&self, page_id: u32, page: &Page) -> Option<Page> {
+        None
+    }
+
+    // written is how many bytes stand for the page. a whole page went to its
+    // slot as it is, so any compressed copy is out of date
+    fn after_write(&self, page_id: u32, written: usize) {
+        #[cfg(feature = "compression")]
+        if written == self.page_size {
+            self.unpack(page_id);
+        }
+    }
+
+    #[cfg(not(feature = "compression"))]
+    #[allow(dead_code)]
+    fn is_packed(&self, page_id: u32) -> bool {
+        false
+    }
+
+    fn decode_page(&self, page_id: u32, page: &mut Page) -> Result<(), Corruption> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            cipher.unseal(page_id, page)?;
+        }
+        #[cfg(feature = "compression")]
+        self.decompress_page(page_id, page)?;
+        Ok(())
+    }
 }
 
 impl DiskManager {
     pub fn new() -> Self {
-        // open file
+        DiskManager::open("./files/db.dat")
+    }
+
+    // create a fresh database file, overwriting anything at the path
+    #[allow(dead_code)]
+    pub fn create<P: AsRef<Path>>(path: P, page_size: PageSize) -> Self {
+        DiskManager::create_with(path, page_size, DiskOptions::default())
+    }
+
+    #[allow(dead_code)]
+    pub fn create_with<P: AsRef<Path>>(path: P, page_size: PageSize, options: DiskOptions) -> Self {
         let mut f = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open("./files/db.dat")
+            .truncate(true)
+            .open(&path)
             .unwrap();
 
-        // read first page of metadata
-        let mut data = Page::from([0; 4096]);
-        f.read_exact(&mut data).unwrap();
+        let mut metadata = vec![0; SPECIAL_PAGES as usize * page_size.bytes()];
+        write_header(&mut metadata, 0, 0, page_size.bytes());
+        f.write_all(&metadata).unwrap();
+        drop(f);
+
+        // the old database's keys and nonces don't belong to this one
+        #[cfg(feature = "encryption")]
+        if let Err(e) = std::fs::remove_file(encryption::sidecar_path(path.as_ref())) {
+            assert_eq!(e.kind(), std::io::ErrorKind::NotFound, "{}", e);
+        }
+        // and neither do its compressed pages
+        #[cfg(feature = "compression")]
+        if let Err(e) = std::fs::remove_file(compression::packed_path(path.as_ref())) {
+            assert_eq!(e.kind(), std::io::ErrorKind::NotFound, "{}", e);
+        }
+
+        DiskManager::open_with(path, options)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        DiskManager::open_with(path, DiskOptions::default())
+    }
+
+    pub fn open_with<P: AsRef<Path>>(path: P, options: DiskOptions) -> Self {
+        // open file
+        let mut open_options = OpenOptions::new();
+        open_options
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false);
+        if options.direct_io {
+            set_direct_io(&mut open_options);
+        }
+        let f = open_options.open(&path).unwrap();
+
+        // read the header to find out how big our pages are.
+        // direct I/O can't read just the header, so read a whole aligned block
+        let mut header = Page::zeroed(PAGE_ALIGNMENT);
+        read_exact_at(&f, &mut header, 0).unwrap();
+        if header[..MAGIC.len()] != MAGIC {
+            upgrade_baseline(&f);
+            read_exact_at(&f, &mut header, 0).unwrap();
+        }
+        let version = read_u32(&header, 8);
+        assert_eq!(version, VERSION, "unsupported database file version");
+        let capacity = read_u32(&header, 12);
+        let used = read_u32(&header, 16);
+        let page_size = PageSize::from_header(read_u32(&header, 20))
+            .expect("database header has an unsupported page size")
+            .bytes();
 
-        // read first 2 pairs of ints as u16 for correct fields
-        let capacity = merge_u8(data[0], data[1]);
-        let used = merge_u8(data[2], data[3]);
+        // read the rest of the metadata pages and populate bitmap
+        let mut metadata = Page::zeroed(SPECIAL_PAGES as usize * page_size);
+        read_exact_at(&f, &mut metadata, 0).unwrap();
 
-        // read remaining page and populate bitmap
-        let mut map = Bitmap::with_capacity(4092 * 8 + (4096 * 8 * 3));
-        for i in 4..4096 {
-            let cur = data[i];
+        let mut map = Bitmap::with_capacity(max_pages(page_size) as usize);
+        for (i, &cur) in metadata.iter().enumerate().skip(HEADER_SIZE) {
             for bit in 0..8 {
-                let map_offset = ((i - 4) * 8) + bit;
+                let map_offset = ((i - HEADER_SIZE) * 8) + bit;
                 if cur & (1 << bit) == (1 << bit) {
                     map.set(map_offset);
                 }
             }
         }
 
-        for page in 0..3 {
-            f.read_exact(&mut data).unwrap();
-            let initial_offset = 4092 * 8;
-            let added_offset = (4096 * 8) * page;
-            for i in 0..4096 {
-                let cur = data[i];
-                for bit in 0..8 {
-                    let current_offset = (i * 8) + bit;
-                    let map_offset = current_offset + initial_offset + added_offset;
-                    if cur & (1 << bit) == (1 << bit) {
-                        map.set(map_offset);
-                    }
-                }
+        // kernels or sandboxes without io_uring still work, just without batching
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        let uring = match uring::Uring::new(&f) {
+            Ok(uring) => Some(uring),
+            Err(e) => {
+                eprintln!(
+                    "io_uring unavailable, falling back to synchronous I/O: {}",
+                    e
+                );
+                None
             }
-        }
+        };
+
+        #[cfg(feature = "encryption")]
+        let cipher = match &options.key {
+            Some(key) => Some(encryption::Cipher::open(path.as_ref(), key)),
+            None => {
+                let sidecar = encryption::sidecar_path(path.as_ref());
+                assert!(
+                    std::fs::metadata(&sidecar).map_or(true, |m| m.len() == 0),
+                    "{:?} is encrypted, open it with DiskOptions::key",
+                    path.as_ref()
+                );
+                None
+            }
+        };
 
         DiskManager {
+            page_size,
             capacity: Mutex::new(capacity),
             used: Mutex::new(used),
             map: Mutex::new(map),
-            file: Mutex::new(f),
+            file: f,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            uring,
+            #[cfg(feature = "compression")]
+            compressed: Mutex::new(Bitmap::with_capacity(max_pages(page_size) as usize)),
+            #[cfg(feature = "compression")]
+            packed: Mutex::new(compression::Packed::open(path.as_ref(), page_size)),
+            #[cfg(feature = "encryption")]
+            cipher,
         }
     }
 
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    #[allow(dead_code)]
     pub fn read(&self, page_id: u32) -> Page {
-        let mut res: Page = Page::from([0; 4096]);
-        let offset = get_file_offset(page_id);
-        let mut file = self.file.lock().unwrap();
-        file.seek(offset).unwrap();
-        file.read_exact(&mut res).unwrap();
-        eprintln!("read page {}", page_id);
-        res
+        self.try_read(page_id).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // like read, but a page that fails its checksum or authentication, or
+    // isn't in the file at all, is an error instead of a panic
+    pub fn try_read(&self, page_id: u32) -> Result<Page, Corruption> {
+        if page_id >= *self.capacity.lock().unwrap() {
+            return Err(Corruption {
+                page_id,
+                reason: "page is past the end of the file",
+            });
+        }
+        let mut res = Page::zeroed(self.page_size);
+        self.read_raw(page_id, &mut res);
+        self.decode_page(page_id, &mut res)?;
+        Ok(res)
     }
 
     pub fn write(&self, page_id: u32, page_content: &Page) {
-        let mut file = self.file.lock().unwrap();
-        let offset = get_file_offset(page_id);
-        file.seek(offset).unwrap();
-        file.write(page_content).unwrap();
-        eprintln!("wrote page {} to disk", page_id);
+        assert_eq!(page_content.len(), self.page_size);
+        let encoded = self.encode_page(page_id, page_content);
+        let bytes = encoded.as_ref().unwrap_or(page_content);
+        self.write_raw(page_id, bytes);
+        self.after_write(page_id, bytes.len());
+    }
+
+    // the bytes in the page's slot of the file, or its packed copy, as they are
+    fn read_raw(&self, page_id: u32, page: &mut Page) {
+        #[cfg(feature = "compression")]
+        if self.read_packed(page_id, page) {
+            return;
+        }
+        let offset = get_file_offset(page_id, self.page_size);
+        read_exact_at(&self.file, page, offset).unwrap();
+    }
+
+    // bytes shorter than a page are a compressed page, which is packed elsewhere
+    fn write_raw(&self, page_id: u32, bytes: &Page) {
+        #[cfg(feature = "compression")]
+        if bytes.len() < self.page_size {
+            self.write_packed(page_id, bytes);
+            return;
+        }
+        let offset = get_file_offset(page_id, self.page_size);
+        write_all_at(&self.file, bytes, offset).unwrap();
+    }
+
+    // perform a batch of page reads and writes.
+    // with the io-uring feature these all go to the kernel in one submission,
+    // otherwise they are done one at a time.
+    // requests may complete in any order, so a batch shouldn't touch the same page twice
+    pub fn submit(&self, requests: &mut [IoRequest]) {
+        for request in requests.iter() {
+            match request {
+                IoRequest::Read(_, page) => assert_eq!(page.len(), self.page_size),
+                IoRequest::Write(_, page) => assert_eq!(page.len(), self.page_size),
+            }
+        }
+
+        // writes that go out as something other than the page itself
+        let encoded: Vec<Option<Page>> = requests
+            .iter()
+            .map(|request| match request {
+                IoRequest::Write(id, page) => self.encode_page(*id, page),
+                IoRequest::Read(..) => None,
+            })
+            .collect();
+        let mut raw: Vec<IoRequest> = requests
+            .iter_mut()
+            .zip(&encoded)
+            .map(|(request, encoded)| match request {
+                IoRequest::Read(id, page) => IoRequest::Read(*id, page),
+                IoRequest::Write(id, page) => {
+                    IoRequest::Write(*id, encoded.as_ref().unwrap_or(page))
+                }
+            })
+            .collect();
+        self.submit_raw(&mut raw);
+        drop(raw);
+
+        for (request, encoded) in requests.iter_mut().zip(&encoded) {
+            match request {
+                IoRequest::Read(id, page) => self
+                    .decode_page(*id, page)
+                    .unwrap_or_else(|e| panic!("{}", e)),
+                IoRequest::Write(id, page) => {
+                    self.after_write(*id, encoded.as_ref().unwrap_or(page).len())
+                }
+            }
+        }
+    }
+
+    fn submit_raw(&self, requests: &mut [IoRequest]) {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(uring) = &self.uring {
+            // packed pages aren't in the database file, so they're done on their own
+            let mut batch = Vec::with_capacity(requests.len());
+            for request in requests.iter_mut() {
+                match request {
+                    IoRequest::Read(id, page) if self.is_packed(*id) => self.read_raw(*id, page),
+                    IoRequest::Write(id, page) if page.len() < self.page_size => {
+                        self.write_raw(*id, page)
+                    }
+                    IoRequest::Read(id, page) => batch.push(IoRequest::Read(*id, page)),
+                    IoRequest::Write(id, page) => batch.push(IoRequest::Write(*id, page)),
+                }
+            }
+            let page_size = self.page_size;
+            uring
+                .submit(&mut batch, |id| get_file_offset(id, page_size))
+                .unwrap();
+            return;
+        }
+
+        for request in requests.iter_mut() {
+            match request {
+                IoRequest::Read(id, page) => self.read_raw(*id, page),
+                IoRequest::Write(id, page) => self.write_raw(*id, page),
+            }
+        }
     }
 
     pub fn new_page(&self) -> Option<u32> {
@@ -117,27 +529,28 @@ impl DiskManager {
         let mut used = self.used.lock().unwrap();
 
         // add new pages
-        eprintln!("capacity: {}, used: {}", *capacity, *used);
         if *capacity == *used {
-            if *capacity == 4096 * 8 {
+            let max = max_pages(self.page_size);
+            if *capacity == max {
                 return None;
             }
-            // add 64 pages at a time, capped at 4096 * 8 pages
-            let new_capacity = min(*capacity + 64, 4096 * 8);
+            // add 64 pages at a time, capped at what the free page bitmap can track
+            let new_capacity = min(*capacity + GROWTH, max);
             let added_pages = new_capacity - *capacity;
             *capacity = new_capacity;
 
-            let mut file = self.file.lock().unwrap();
-            file.seek(SeekFrom::End(0)).unwrap();
-            for _ in 0..added_pages {
-                file.write(&[0 as u8; 4096]).unwrap();
+            // the capacity lock keeps anyone else from growing the file at the same time
+            let empty = Page::zeroed(self.page_size);
+            for page in 0..added_pages {
+                let offset = get_file_offset(*capacity - added_pages + page, self.page_size);
+                write_all_at(&self.file, &empty, offset).unwrap();
             }
         }
         for i in 0..*capacity {
             if !map.check(i as usize) {
                 map.set(i as usize);
                 *used += 1;
-                return Some(i as u32);
+                return Some(i);
             }
         }
         // this path should never be ran, needed to compile
@@ -146,45 +559,52 @@ impl DiskManager {
 
     pub fn delete_page(&self, page_id: u32) {
         let mut map = self.map.lock().unwrap();
+        assert!(
+            map.check(page_id as usize),
+            "deleting page {} which isn't allocated",
+            page_id
+        );
         map.unset(page_id as usize);
+        *self.used.lock().unwrap() -= 1;
+        drop(map);
+        #[cfg(feature = "compression")]
+        self.set_compressed(page_id, false);
+
+        // new_page hands out zeroed pages, reused ones shouldn't be any different
+        self.write(page_id, &Page::zeroed(self.page_size));
+    }
 
-        // TODO calculate which disk page and clear on disk
+    // how many pages are allocated right now
+    #[allow(dead_code)]
+    pub fn used_pages(&self) -> u32 {
+        *self.used.lock().unwrap()
     }
 
     fn persist(&self) {
-        let mut data = Page::from([0; 4096]);
+        let mut data = Page::zeroed(SPECIAL_PAGES as usize * self.page_size);
 
         let capacity = self.capacity.lock().unwrap();
         let used = self.used.lock().unwrap();
 
-        // serialize u16 fields
-        let (cap1, cap2) = split_u16(*capacity);
-        let (used1, used2) = split_u16(*used);
+        write_header(&mut data, *capacity, *used, self.page_size);
 
         drop(capacity);
         drop(used);
 
-        data[0] = cap1;
-        data[1] = cap2;
-        data[2] = used1;
-        data[3] = used2;
-
         let map = self.map.lock().unwrap();
 
-        for i in 4..4096 {
+        for (i, byte) in data.iter_mut().enumerate().skip(HEADER_SIZE) {
             for bit in 0..8 {
-                let cur = ((i - 4) * 8) + bit;
+                let cur = ((i - HEADER_SIZE) * 8) + bit;
                 if map.check(cur) {
-                    data[i] |= 1 << bit;
+                    *byte |= 1 << bit;
                 }
             }
         }
         drop(map);
 
         // persist changes to database
-        let mut file = self.file.lock().unwrap();
-        file.rewind().unwrap();
-        file.write(&data).unwrap();
+        write_all_at(&self.file, &data, 0).unwrap();
     }
 }
 
@@ -193,3 +613,216 @@ impl Drop for DiskManager {
         self.persist();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{DiskManager, DiskOptions, IoRequest, PageSize};
+    use crate::utils::testing::{temp_db, temp_path};
+
+    #[test]
+    fn baseline_files_are_upgraded() {
+        // 64 pages with 0, 1 and 5 in use, and something written to 5
+        let path = temp_path("baseline_file");
+        let mut file = vec![0; (4 + 64) * 4096];
+        file[..4].copy_from_slice(&[0, 64, 0, 3]);
+        file[4] = 0b10_0011;
+        file[9 * 4096] = 42;
+        std::fs::write(&path, file).unwrap();
+
+        for _ in 0..2 {
+            let disk = DiskManager::open(&path);
+            assert_eq!(disk.page_size(), 4096);
+            assert_eq!(disk.used_pages(), 3);
+            assert_eq!(disk.read(5)[0], 42);
+        }
+        let disk = DiskManager::open(&path);
+        assert_eq!(disk.new_page(), Some(2));
+        assert_eq!(disk.new_page(), Some(3));
+        assert_eq!(disk.new_page(), Some(4));
+        assert_eq!(disk.new_page(), Some(6));
+
+        // a file of zeros is an empty baseline database
+        let path = temp_path("zeroed_file");
+        std::fs::write(&path, [0; 4096 * 4]).unwrap();
+        let disk = DiskManager::open(&path);
+        assert_eq!((disk.page_size(), disk.used_pages()), (4096, 0));
+    }
+
+    #[test]
+    fn files_that_arent_databases_are_refused() {
+        let path = temp_path("not_a_database");
+        std::fs::write(&path, [0xab; 4096 * 4]).unwrap();
+        assert!(std::panic::catch_unwind(|| DiskManager::open(&path)).is_err());
+
+        // nor are versions from the future
+        let path = temp_path("future_version");
+        drop(DiskManager::create(&path, PageSize::Size4K));
+        let mut file = std::fs::read(&path).unwrap();
+        file[11] = 2;
+        std::fs::write(&path, file).unwrap();
+        assert!(std::panic::catch_unwind(|| DiskManager::open(&path)).is_err());
+    }
+
+    #[test]
+    fn page_size_is_persisted() {
+        let path = temp_path("page_size_persisted");
+        let disk = DiskManager::create(&path, PageSize::Size16K);
+        let id = disk.new_page().unwrap();
+        let mut page = disk.read(id);
+        page[16383] = 7;
+        disk.write(id, &page);
+        drop(disk);
+
+        let disk = DiskManager::open(&path);
+        assert_eq!(disk.page_size(), 16384);
+        assert_eq!(disk.read(id)[16383], 7);
+        // the allocation survived too, so we get a different page back
+        assert_ne!(disk.new_page().unwrap(), id);
+    }
+
+    #[test]
+    fn deleted_pages_are_reused() {
+        let disk = DiskManager::open(temp_db("deleted_pages_reused"));
+        let a = disk.new_page().unwrap();
+        let b = disk.new_page().unwrap();
+        let mut page = disk.read(a);
+        page[0] = 1;
+        disk.write(a, &page);
+        assert_eq!(disk.used_pages(), 2);
+
+        disk.delete_page(a);
+        assert_eq!(disk.used_pages(), 1);
+        assert_eq!(disk.new_page(), Some(a));
+        assert_eq!(disk.read(a)[0], 0);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn batched_requests() {
+        let disk = DiskManager::open(temp_db("batched_requests"));
+        let ids: Vec<u32> = (0..100).map(|_| disk.new_page().unwrap()).collect();
+        let mut pages: Vec<_> = ids.iter().map(|&id| disk.read(id)).collect();
+        for (i, page) in pages.iter_mut().enumerate() {
+            page[0] = i as u8;
+            page[4095] = !(i as u8);
+        }
+
+        let mut writes: Vec<_> = ids
+            .iter()
+            .zip(pages.iter())
+            .map(|(&id, page)| IoRequest::Write(id, page))
+            .collect();
+        disk.submit(&mut writes);
+        drop(writes);
+
+        let mut read_back: Vec<_> = ids.iter().map(|_| disk.read(ids[0])).collect();
+        let mut reads: Vec<_> = ids
+            .iter()
+            .zip(read_back.iter_mut())
+            .map(|(&id, page)| IoRequest::Read(id, page))
+            .collect();
+        disk.submit(&mut reads);
+        drop(reads);
+        for (i, page) in read_back.iter().enumerate() {
+            assert_eq!((page[0], page[4095]), (i as u8, !(i as u8)));
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn direct_io_round_trip() {
+        use std::fs::OpenOptions;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let path = temp_db("direct_io");
+        // not every filesystem supports O_DIRECT (older tmpfs doesn't)
+        let probe = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(&path);
+        if probe.is_err() {
+            eprintln!("skipping, O_DIRECT not supported for {:?}", path);
+            return;
+        }
+
+        // the encryption feature adds a field
+        #[allow(clippy::needless_update)]
+        let options = DiskOptions {
+            direct_io: true,
+            ..Default::default()
+        };
+        let disk = DiskManager::create_with(&path, PageSize::Size8K, options.clone());
+        let id = disk.new_page().unwrap();
+        let mut page = disk.read(id);
+        page[0] = 1;
+        page[8191] = 2;
+        disk.write(id, &page);
+        drop(disk);
+
+        let disk = DiskManager::open_with(&path, options);
+        let page = disk.read(id);
+        assert_eq!((page[0], page[8191]), (1, 2));
+    }
+
+    // compares concurrent page reads against the old implementation, which
+    // locked the file to seek and then read. run with
+    // cargo test --release concurrent_read_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn concurrent_read_benchmark() {
+        use crate::bufferpool::Page;
+        use std::fs::File;
+        use std::io::{Read, Seek, SeekFrom};
+        use std::sync::Mutex;
+        use std::thread;
+        use std::time::Instant;
+
+        const PAGES: u32 = 1024;
+        const READS_PER_THREAD: u32 = 20_000;
+
+        let path = temp_db("concurrent_read_benchmark");
+        let disk = DiskManager::open(&path);
+        for _ in 0..PAGES {
+            disk.new_page().unwrap();
+        }
+        let locked = Mutex::new(File::open(&path).unwrap());
+
+        for threads in [1, 2, 4, 8] {
+            let start = Instant::now();
+            thread::scope(|scope| {
+                for t in 0..threads {
+                    let locked = &locked;
+                    scope.spawn(move || {
+                        let mut page = Page::zeroed(4096);
+                        for i in 0..READS_PER_THREAD {
+                            let id = (i * 7 + t) % PAGES;
+                            let mut file = locked.lock().unwrap();
+                            file.seek(SeekFrom::Start(super::get_file_offset(id, 4096)))
+                                .unwrap();
+                            file.read_exact(&mut page).unwrap();
+                        }
+                    });
+                }
+            });
+            let mutex_time = start.elapsed();
+
+            let start = Instant::now();
+            thread::scope(|scope| {
+                for t in 0..threads {
+                    let disk = &disk;
+                    scope.spawn(move || {
+                        for i in 0..READS_PER_THREAD {
+                            disk.read((i * 7 + t) % PAGES);
+                        }
+                    });
+                }
+            });
+            let positional_time = start.elapsed();
+
+            println!(
+                "{} thread(s), {} reads each: mutex + seek {:?}, positional {:?}",
+                threads, READS_PER_THREAD, mutex_time, positional_time
+            );
+        }
+    }
+}
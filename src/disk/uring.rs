@@ -0,0 +1,95 @@
+use super::IoRequest;
+
+use io_uring::{opcode, types, IoUring};
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+
+// how many requests we put in flight at once
+const RING_ENTRIES: u32 = 64;
+
+// batches page reads and writes into a single io_uring submission.
+// every request carries its own file offset, so nothing here needs to
+// seek, and we can work on the file without locking it
+pub struct Uring {
+    ring: Mutex<IoUring>,
+    file: File,
+}
+
+impl Uring {
+    pub fn new(file: &File) -> io::Result<Self> {
+        Ok(Uring {
+            ring: Mutex::new(IoUring::new(RING_ENTRIES)?),
+            file: file.try_clone()?,
+        })
+    }
+
+    // offsets(i) is the file offset for requests[i]
+    pub fn submit(
+        &self,
+        requests: &mut [IoRequest],
+        offsets: impl Fn(u32) -> u64,
+    ) -> io::Result<()> {
+        let fd = types::Fd(self.file.as_raw_fd());
+        let mut ring = self.ring.lock().unwrap();
+
+        for (chunk_idx, chunk) in requests.chunks_mut(RING_ENTRIES as usize).enumerate() {
+            let base = chunk_idx * RING_ENTRIES as usize;
+            {
+                let mut submission = ring.submission();
+                for (i, request) in chunk.iter_mut().enumerate() {
+                    let entry = match request {
+                        IoRequest::Read(id, page) => {
+                            opcode::Read::new(fd, page.as_mut_ptr(), page.len() as u32)
+                                .offset(offsets(*id))
+                                .build()
+                        }
+                        IoRequest::Write(id, page) => {
+                            opcode::Write::new(fd, page.as_ptr(), page.len() as u32)
+                                .offset(offsets(*id))
+                                .build()
+                        }
+                    };
+                    // SAFETY: the buffers are borrowed from requests, which outlives
+                    // this call, and we wait for every entry before returning
+                    unsafe {
+                        submission
+                            .push(&entry.user_data((base + i) as u64))
+                            .expect("submission queue is sized for a full chunk");
+                    }
+                }
+            }
+            ring.submit_and_wait(chunk.len())?;
+
+            let completions: Vec<(usize, i32)> = ring
+                .completion()
+                .map(|entry| (entry.user_data() as usize - base, entry.result()))
+                .collect();
+            for (i, result) in completions {
+                if result < 0 {
+                    return Err(io::Error::from_raw_os_error(-result));
+                }
+                // short transfers are rare for regular files, finish them with
+                // a plain positional call instead of resubmitting
+                let done = result as usize;
+                match &mut chunk[i] {
+                    IoRequest::Read(id, page) => {
+                        if done < page.len() {
+                            self.file
+                                .read_exact_at(&mut page[done..], offsets(*id) + done as u64)?;
+                        }
+                    }
+                    IoRequest::Write(id, page) => {
+                        if done < page.len() {
+                            self.file
+                                .write_all_at(&page[done..], offsets(*id) + done as u64)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 mod bufferpool;
+mod cli;
 mod disk;
 mod page_interpretation;
 mod utils;
@@ -8,9 +9,18 @@ use std::{
     thread::{self},
 };
 
-use crate::bufferpool::{EvictionStrategy, Page, Pool};
+use crate::bufferpool::{EvictionStrategy, Pool};
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = cli::run(&args) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let strat: Mutex<Box<dyn EvictionStrategy>> =
         Mutex::new(Box::new(bufferpool::eviction::LruK::new(10, 2)));
     let pool = Arc::new(Pool::new(10, strat));
@@ -23,7 +33,7 @@ fn main() {
                 let mut write_guard = result.1.write();
                 let cur = write_guard[0];
                 println!("got guard for page {}, contents is {:?}", id, cur);
-                *write_guard = Page::from([cur + 1 as u8; 4096]);
+                write_guard.fill(cur + 1);
                 drop(write_guard);
                 drop(result.1);
             } else {
@@ -0,0 +1,509 @@
+use super::convert::{Number, MICROS_PER_DAY};
+use super::{Decimal, TupleField};
+use std::cmp::Ordering;
+use std::ops::Not;
+
+// sql's three valued logic. comparing anything with NULL is neither true
+// nor false but unknown, and unknown spreads through and/or/not
+// unless the other side decides the result on its own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Truth {
+    True,
+    False,
+    Unknown,
+}
+
+impl Truth {
+    pub fn and(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::False, _) | (_, Truth::False) => Truth::False,
+            (Truth::True, Truth::True) => Truth::True,
+            _ => Truth::Unknown,
+        }
+    }
+
+    pub fn or(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::True, _) | (_, Truth::True) => Truth::True,
+            (Truth::False, Truth::False) => Truth::False,
+            _ => Truth::Unknown,
+        }
+    }
+
+    // a WHERE clause only keeps rows where the condition is true
+    pub fn is_true(self) -> bool {
+        self == Truth::True
+    }
+}
+
+impl Not for Truth {
+    type Output = Truth;
+
+    fn not(self) -> Truth {
+        match self {
+            Truth::True => Truth::False,
+            Truth::False => Truth::True,
+            Truth::Unknown => Truth::Unknown,
+        }
+    }
+}
+
+impl From<bool> for Truth {
+    fn from(value: bool) -> Self {
+        if value {
+            Truth::True
+        } else {
+            Truth::False
+        }
+    }
+}
+
+// decimals compare by value, so 1.5 and 1.50 are equal
+fn cmp_decimals(a: &Decimal, b: &Decimal) -> Ordering {
+    let scale = a.scale.max(b.scale);
+    match (a.rescale(scale), b.rescale(scale)) {
+        (Some(a), Some(b)) => a.unscaled.cmp(&b.unscaled),
+        // whichever didn't fit has the larger magnitude
+        (None, _) => a.unscaled.signum().cmp(&0),
+        (_, None) => 0.cmp(&b.unscaled.signum()),
+    }
+}
+
+// floats in the total order. -0.0 equals 0.0, and every NaN
+// equals every other NaN and sorts after all other numbers
+fn cmp_floats(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).unwrap(),
+    }
+}
+
+// exact, unlike converting the integer to a float
+fn cmp_float_integer(a: f64, b: i128) -> Ordering {
+    if a.is_nan() || a >= 2f64.powi(127) {
+        return Ordering::Greater;
+    }
+    if a < -(2f64.powi(127)) {
+        return Ordering::Less;
+    }
+    (a.trunc() as i128).cmp(&b).then(cmp_floats(a.fract(), 0.0))
+}
+
+// a non negative integer as little endian u64 limbs, as wide as it needs to be
+struct Wide(Vec<u64>);
+
+impl Wide {
+    fn new(value: u128) -> Self {
+        Wide(vec![value as u64, (value >> 64) as u64])
+    }
+
+    fn mul(mut self, factor: u64) -> Self {
+        let mut carry = 0;
+        for limb in &mut self.0 {
+            let product = *limb as u128 * factor as u128 + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        self.0.push(carry as u64);
+        self
+    }
+
+    fn shl(mut self, bits: u32) -> Self {
+        let (limbs, bits) = ((bits / 64) as usize, bits % 64);
+        self.0.push(0);
+        if bits > 0 {
+            for i in (1..self.0.len()).rev() {
+                self.0[i] = self.0[i] << bits | self.0[i - 1] >> (64 - bits);
+            }
+            self.0[0] <<= bits;
+        }
+        self.0.splice(0..0, std::iter::repeat_n(0, limbs));
+        self
+    }
+
+    fn cmp(&self, other: &Wide) -> Ordering {
+        let len = |wide: &Wide| {
+            wide.0
+                .iter()
+                .rposition(|&limb| limb != 0)
+                .map_or(0, |i| i + 1)
+        };
+        let (a, b) = (len(self), len(other));
+        a.cmp(&b)
+            .then_with(|| self.0[..a].iter().rev().cmp(other.0[..b].iter().rev()))
+    }
+}
+
+// exact, like cmp_float_integer. a finite float is a whole number times a
+// power of two, so both sides scale up to whole numbers to compare
+fn cmp_float_decimal(a: f64, b: &Decimal) -> Ordering {
+    if a.is_nan() {
+        return Ordering::Greater;
+    }
+    let sign = cmp_floats(a, 0.0);
+    if sign != b.unscaled.cmp(&0) || sign == Ordering::Equal {
+        return sign.cmp(&b.unscaled.cmp(&0));
+    }
+    if a.is_infinite() {
+        return sign;
+    }
+
+    let bits = a.to_bits();
+    let (mantissa, exponent) = match (bits >> 52) as i32 & 0x7ff {
+        // subnormal
+        0 => (bits & ((1 << 52) - 1), -1074),
+        biased => (bits & ((1 << 52) - 1) | 1 << 52, biased - 1075),
+    };
+    // |a| = mantissa * 2^exponent against |b| = unscaled / 10^scale
+    let mut float = Wide::new(mantissa as u128);
+    for _ in 0..b.scale {
+        float = float.mul(10);
+    }
+    let float = float.shl(exponent.max(0) as u32);
+    let decimal = Wide::new(b.unscaled.unsigned_abs()).shl((-exponent).max(0) as u32);
+    match sign {
+        Ordering::Greater => float.cmp(&decimal),
+        _ => decimal.cmp(&float),
+    }
+}
+
+fn cmp_numbers(a: Number, b: Number) -> Ordering {
+    match (a, b) {
+        (Number::Integer(a), Number::Integer(b)) => a.cmp(&b),
+        (Number::Float(a), Number::Float(b)) => cmp_floats(a, b),
+        (Number::Decimal(a), Number::Decimal(b)) => cmp_decimals(&a, &b),
+        (Number::Float(a), Number::Integer(b)) => cmp_float_integer(a, b),
+        (Number::Integer(a), Number::Float(b)) => cmp_float_integer(b, a).reverse(),
+        (Number::Float(a), Number::Decimal(b)) => cmp_float_decimal(a, &b),
+        (Number::Decimal(a), Number::Float(b)) => cmp_float_decimal(b, &a).reverse(),
+        (Number::Integer(a), Number::Decimal(b)) => cmp_decimals(&Decimal::new(a, 0), &b),
+        (Number::Decimal(a), Number::Integer(b)) => cmp_decimals(&a, &Decimal::new(b, 0)),
+    }
+}
+
+// values in different groups never compare equal, and sort by group
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Group {
+    Null,
+    Bool,
+    Number,
+    Text,
+    Blob,
+    Time,
+}
+
+impl TupleField {
+    fn group(&self) -> Group {
+        match self {
+            TupleField::Null => Group::Null,
+            TupleField::Bool(_) => Group::Bool,
+            TupleField::Int(_)
+            | TupleField::SignedInt(_)
+            | TupleField::Double(_)
+            | TupleField::BigInt(_)
+            | TupleField::SignedBigInt(_)
+            | TupleField::Double64(_)
+            | TupleField::Decimal(_) => Group::Number,
+            TupleField::Char(_) | TupleField::Text256(_) | TupleField::Varchar(_) => Group::Text,
+            TupleField::Blob(_) => Group::Blob,
+            TupleField::Date(_) | TupleField::Timestamp(_) => Group::Time,
+        }
+    }
+
+    fn text_chars(&self) -> Box<dyn Iterator<Item = char> + '_> {
+        match self {
+            TupleField::Char(c) => Box::new(std::iter::once(*c)),
+            TupleField::Text256(text) => Box::new(text.chars()),
+            TupleField::Varchar(text) => Box::new(text.chars()),
+            _ => unreachable!("not a text value"),
+        }
+    }
+
+    fn micros(&self) -> i128 {
+        match self {
+            TupleField::Date(days) => *days as i128 * MICROS_PER_DAY as i128,
+            TupleField::Timestamp(micros) => *micros as i128,
+            _ => unreachable!("not a time value"),
+        }
+    }
+
+    fn is_nan(&self) -> bool {
+        match self {
+            TupleField::Double(v) => v.is_nan(),
+            TupleField::Double64(v) => v.is_nan(),
+            _ => false,
+        }
+    }
+
+    // compare two non null values in sql. numbers compare with numbers,
+    // text with text, and DATEs with TIMESTAMPs, whatever their exact types.
+    // None if either is NULL or NaN, or they can't be compared
+    pub fn sql_cmp(&self, other: &TupleField) -> Option<Ordering> {
+        if self.is_null() || other.is_null() || self.is_nan() || other.is_nan() {
+            return None;
+        }
+        if self.group() != other.group() {
+            return None;
+        }
+        Some(self.cmp(other))
+    }
+
+    fn compare_with(&self, other: &TupleField, matches: fn(Ordering) -> bool) -> Truth {
+        match self.sql_cmp(other) {
+            Some(ordering) => matches(ordering).into(),
+            None => Truth::Unknown,
+        }
+    }
+
+    pub fn sql_eq(&self, other: &TupleField) -> Truth {
+        self.compare_with(other, Ordering::is_eq)
+    }
+
+    pub fn sql_ne(&self, other: &TupleField) -> Truth {
+        self.compare_with(other, Ordering::is_ne)
+    }
+
+    pub fn sql_lt(&self, other: &TupleField) -> Truth {
+        self.compare_with(other, Ordering::is_lt)
+    }
+
+    pub fn sql_le(&self, other: &TupleField) -> Truth {
+        self.compare_with(other, Ordering::is_le)
+    }
+
+    pub fn sql_gt(&self, other: &TupleField) -> Truth {
+        self.compare_with(other, Ordering::is_gt)
+    }
+
+    pub fn sql_ge(&self, other: &TupleField) -> Truth {
+        self.compare_with(other, Ordering::is_ge)
+    }
+
+    // IS NOT DISTINCT FROM, NULL equals NULL here
+    pub fn not_distinct(&self, other: &TupleField) -> bool {
+        match (self, other) {
+            (TupleField::Null, TupleField::Null) => true,
+            (TupleField::Null, _) | (_, TupleField::Null) => false,
+            _ => self.sql_cmp(other) == Some(Ordering::Equal),
+        }
+    }
+}
+
+// the total order used for index keys, where every value has to go somewhere.
+// NULLs equal each other and sort first, and otherwise values sort by group,
+// then within a group:
+// - numbers by value, whatever their types, with NaNs after everything else
+// - text by code point, which is the order of its utf-8 bytes
+// - blobs byte by byte
+// - dates and timestamps by the moment they start at
+impl Ord for TupleField {
+    fn cmp(&self, other: &TupleField) -> Ordering {
+        let group = self.group();
+        if group != other.group() {
+            return group.cmp(&other.group());
+        }
+        match (self, other) {
+            (TupleField::Null, TupleField::Null) => Ordering::Equal,
+            (TupleField::Bool(a), TupleField::Bool(b)) => a.cmp(b),
+            (TupleField::Blob(a), TupleField::Blob(b)) => a.cmp(b),
+            _ => match group {
+                Group::Number => cmp_numbers(self.as_number().unwrap(), other.as_number().unwrap()),
+                Group::Text => self.text_chars().cmp(other.text_chars()),
+                Group::Time => self.micros().cmp(&other.micros()),
+                _ => unreachable!("groups with one type are matched above"),
+            },
+        }
+    }
+}
+
+impl PartialOrd for TupleField {
+    fn partial_cmp(&self, other: &TupleField) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// equal in the total order, so NaN equals NaN and 1.5 equals 1.50
+impl PartialEq for TupleField {
+    fn eq(&self, other: &TupleField) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TupleField {}
+
+#[cfg(test)]
+mod tests {
+    use super::Truth;
+    use crate::page_interpretation::{Decimal, Text256, TupleField};
+    use crate::utils::testing::Rng;
+    use std::cmp::Ordering;
+
+    const ALL: [Truth; 3] = [Truth::True, Truth::False, Truth::Unknown];
+
+    #[test]
+    fn truth_tables() {
+        for a in ALL {
+            for b in ALL {
+                let and = a.and(b);
+                let or = a.or(b);
+                // de morgan holds in three valued logic too
+                assert_eq!(!and, (!a).or(!b));
+                assert_eq!(!or, (!a).and(!b));
+                assert_eq!(and, b.and(a));
+                assert_eq!(or, b.or(a));
+            }
+        }
+        assert_eq!(Truth::Unknown.and(Truth::False), Truth::False);
+        assert_eq!(Truth::Unknown.and(Truth::True), Truth::Unknown);
+        assert_eq!(Truth::Unknown.or(Truth::True), Truth::True);
+        assert_eq!(Truth::Unknown.or(Truth::False), Truth::Unknown);
+    }
+
+    #[test]
+    fn null_comparisons_are_unknown() {
+        let one = TupleField::Int(1);
+        let null = TupleField::Null;
+        assert_eq!(one.sql_eq(&one), Truth::True);
+        assert_eq!(one.sql_lt(&TupleField::Int(2)), Truth::True);
+        assert_eq!(one.sql_eq(&null), Truth::Unknown);
+        assert_eq!(null.sql_eq(&null), Truth::Unknown);
+        assert_eq!(null.sql_ne(&one), Truth::Unknown);
+        assert!(null.not_distinct(&null));
+        assert!(!null.not_distinct(&one));
+        assert_eq!(
+            TupleField::Double(f32::NAN).sql_eq(&TupleField::Double(1.0)),
+            Truth::Unknown
+        );
+    }
+
+    #[test]
+    fn nulls_sort_first_in_indexes() {
+        let null = TupleField::Null;
+        let min = TupleField::SignedInt(i32::MIN);
+        assert_eq!(null.cmp(&min), Ordering::Less);
+        assert_eq!(min.cmp(&null), Ordering::Greater);
+        assert_eq!(null.cmp(&null), Ordering::Equal);
+    }
+
+    #[test]
+    fn numbers_compare_across_types() {
+        let decimal = |unscaled, scale| TupleField::Decimal(Decimal::new(unscaled, scale));
+        assert_eq!(TupleField::Int(1), TupleField::SignedBigInt(1));
+        assert_eq!(
+            TupleField::BigInt(u64::MAX),
+            decimal(u64::MAX as i128 * 10, 1)
+        );
+        assert!(TupleField::SignedInt(-1) < TupleField::Int(0));
+        assert!(TupleField::BigInt(u64::MAX) > TupleField::SignedBigInt(i64::MAX));
+        assert_eq!(TupleField::Double(0.5), decimal(5, 1));
+        assert!(TupleField::Double64(-0.25) < decimal(-2, 1));
+        assert!(TupleField::Double64(-2.5) < TupleField::SignedInt(-2));
+        assert!(TupleField::Double64(2.5) > TupleField::Int(2));
+        assert_eq!(TupleField::Double64(-0.0), TupleField::Int(0));
+        // 2^53 + 1 has no f64, so a conversion would call these equal
+        assert!(TupleField::Double64(9007199254740992.0) < TupleField::BigInt((1 << 53) + 1));
+        assert_eq!(
+            TupleField::SignedInt(1).sql_eq(&TupleField::Double64(1.0)),
+            Truth::True
+        );
+    }
+
+    #[test]
+    fn floats_and_decimals_order_transitively() {
+        let decimal = |unscaled, scale| TupleField::Decimal(Decimal::new(unscaled, scale));
+        // 0.1 as an f64 is 0.1000000000000000055511151231257827...
+        let tenth = TupleField::Double64(0.1);
+        assert!(tenth > decimal(1, 1));
+        assert!(tenth > decimal(10000000000000000001, 20));
+        assert!(tenth < decimal(1000000000000000056, 19));
+        assert_eq!(TupleField::Double64(-0.375), decimal(-375_000, 6));
+        assert!(TupleField::Double64(5e-324) < decimal(1, 255));
+        assert!(TupleField::Double64(1e-250) > decimal(1, 255));
+        assert!(TupleField::Double64(-1e300) < decimal(i128::MIN, 0));
+        assert!(TupleField::Double64(f64::INFINITY) > decimal(i128::MAX, 0));
+
+        let mut rng = Rng::new(36);
+        let mut values = Vec::new();
+        for _ in 0..100 {
+            let scale = rng.below(39) as u8;
+            let unscaled = (rng.next_u64() as i64 as i128) << rng.below(64);
+            let near = Decimal::new(unscaled, scale).to_f64();
+            for float in [near, f64::from_bits(near.to_bits() + 1)] {
+                values.push(TupleField::Double64(float));
+            }
+            for unscaled in [unscaled - 1, unscaled, unscaled + 1] {
+                values.push(decimal(unscaled, scale));
+            }
+        }
+        values.sort();
+        for (i, a) in values.iter().enumerate() {
+            for b in &values[i..] {
+                assert_ne!(a.cmp(b), Ordering::Greater, "{:?} {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn nan_is_largest_and_equal_to_itself() {
+        let nan = TupleField::Double64(f64::NAN);
+        assert_eq!(nan, TupleField::Double(-f32::NAN));
+        assert!(nan > TupleField::Double64(f64::INFINITY));
+        assert!(nan > TupleField::BigInt(u64::MAX));
+        // but sql comparisons with NaN stay unknown
+        assert_eq!(nan.sql_eq(&nan), Truth::Unknown);
+    }
+
+    #[test]
+    fn groups() {
+        let mut values = [
+            TupleField::Date(0),
+            TupleField::Blob(vec![]),
+            TupleField::Varchar(String::new()),
+            TupleField::Double(f32::NEG_INFINITY),
+            TupleField::Bool(true),
+            TupleField::Null,
+        ];
+        values.sort();
+        assert_eq!(values[0], TupleField::Null);
+        assert_eq!(values[1], TupleField::Bool(true));
+        assert_eq!(values[5], TupleField::Date(0));
+        assert_eq!(
+            TupleField::Int(1).sql_eq(&TupleField::Bool(true)),
+            Truth::Unknown
+        );
+
+        let text = TupleField::Text256(Text256::new("a").unwrap());
+        assert_eq!(text, TupleField::Char('a'));
+        assert_eq!(text, TupleField::Varchar("a".to_string()));
+        assert!(TupleField::Varchar("a".to_string()) < TupleField::Varchar("a\0".to_string()));
+        assert_eq!(TupleField::Date(1), TupleField::Timestamp(86_400_000_000));
+        assert!(TupleField::Date(1) > TupleField::Timestamp(86_399_999_999));
+    }
+
+    #[test]
+    fn wider_types() {
+        let decimal = |unscaled, scale| TupleField::Decimal(Decimal::new(unscaled, scale));
+        assert_eq!(decimal(15, 1).sql_eq(&decimal(150, 2)), Truth::True);
+        assert_eq!(decimal(-15, 1).sql_lt(&decimal(-149, 2)), Truth::True);
+        assert_eq!(decimal(i128::MAX, 0).sql_gt(&decimal(1, 38)), Truth::True);
+        assert_eq!(decimal(i128::MIN, 0).sql_lt(&decimal(-1, 38)), Truth::True);
+        assert_eq!(
+            TupleField::Varchar("é".to_string()).sql_gt(&TupleField::Varchar("z".to_string())),
+            Truth::True
+        );
+        assert_eq!(
+            TupleField::Blob(vec![1]).sql_lt(&TupleField::Blob(vec![1, 0])),
+            Truth::True
+        );
+        assert_eq!(
+            TupleField::SignedBigInt(-1).sql_lt(&TupleField::SignedBigInt(0)),
+            Truth::True
+        );
+        assert_eq!(
+            TupleField::Date(0).sql_eq(&TupleField::Int(0)),
+            Truth::Unknown
+        );
+    }
+}
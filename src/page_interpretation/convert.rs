@@ -0,0 +1,621 @@
+use super::{Decimal, Text256, TupleField, TupleFieldTypes, MAX_DECIMAL_PRECISION};
+use std::fmt;
+
+// conversions between column types, like sql's CAST.
+// numbers convert to each other as long as the value fits, rounding half away
+// from zero when digits have to go. everything converts to and from text,
+// DATE and TIMESTAMP convert to each other, and VARCHAR to BLOB and back
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+pub(super) const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SECOND;
+// the furthest year from 0 parse_date takes
+const MAX_YEAR: i64 = 10_000_000;
+
+#[derive(Debug, PartialEq)]
+pub enum ConversionError {
+    // there's no conversion between these types at all
+    Unsupported {
+        from: TupleFieldTypes,
+        to: TupleFieldTypes,
+    },
+    // the value doesn't fit in the target type
+    OutOfRange(TupleFieldTypes),
+    // text that doesn't spell a value of the target type
+    Parse {
+        to: TupleFieldTypes,
+        text: String,
+    },
+    // a BLOB that isn't utf-8 can't become text
+    InvalidUtf8,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::Unsupported { from, to } => {
+                write!(f, "can't convert {:?} to {:?}", from, to)
+            }
+            ConversionError::OutOfRange(to) => write!(f, "value doesn't fit in {:?}", to),
+            ConversionError::Parse { to, text } => write!(f, "{:?} is not a valid {:?}", text, to),
+            ConversionError::InvalidUtf8 => write!(f, "blob is not valid utf-8"),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+// every numeric value, widened so nothing is lost before the target type is known
+pub(super) enum Number {
+    Integer(i128),
+    Float(f64),
+    Decimal(Decimal),
+}
+
+impl Decimal {
+    // the same value with a different number of digits after the point.
+    // None if it no longer fits in an i128
+    pub fn rescale(&self, scale: u8) -> Option<Decimal> {
+        if scale >= self.scale {
+            let factor = 10i128.checked_pow((scale - self.scale) as u32)?;
+            let unscaled = self.unscaled.checked_mul(factor)?;
+            return Some(Decimal::new(unscaled, scale));
+        }
+        let divisor = match 10i128.checked_pow((self.scale - scale) as u32) {
+            Some(divisor) => divisor,
+            // dropping more than 38 digits leaves nothing
+            None => return Some(Decimal::new(0, scale)),
+        };
+        let mut unscaled = self.unscaled / divisor;
+        let remainder = self.unscaled % divisor;
+        if remainder.unsigned_abs() >= divisor.unsigned_abs().div_ceil(2) {
+            unscaled += self.unscaled.signum();
+        }
+        Some(Decimal::new(unscaled, scale))
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.unscaled as f64 / 10f64.powi(self.scale as i32)
+    }
+}
+
+fn parse_decimal(text: &str) -> Option<Decimal> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    if !whole
+        .chars()
+        .chain(fraction.chars())
+        .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let scale = u8::try_from(fraction.len()).ok()?;
+    let mut unscaled: i128 = 0;
+    for c in whole.chars().chain(fraction.chars()) {
+        unscaled = unscaled
+            .checked_mul(10)?
+            .checked_add(c.to_digit(10)? as i128)?;
+    }
+    if negative {
+        unscaled = -unscaled;
+    }
+    Some(Decimal::new(unscaled, scale))
+}
+
+// days since 1970-01-01 to a (year, month, day) in the proleptic gregorian
+// calendar, from howard hinnant's chrono-compatible date algorithms
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn format_date(days: i64) -> String {
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn format_timestamp(micros: i64) -> String {
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    let of_day = micros.rem_euclid(MICROS_PER_DAY);
+    let seconds = of_day / MICROS_PER_SECOND;
+    let fraction = of_day % MICROS_PER_SECOND;
+    let mut text = format!(
+        "{} {:02}:{:02}:{:02}",
+        format_date(days),
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    if fraction != 0 {
+        text.push_str(&format!(".{:06}", fraction));
+    }
+    text
+}
+
+// YYYY-MM-DD, where the year may be negative or longer than 4 digits
+fn parse_date(text: &str) -> Option<i64> {
+    let (negative, rest) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let mut parts = rest.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    // an i32 of days only spans about 5.9 million years either way, so
+    // anything much further out can't be a date, and could overflow below
+    if year > MAX_YEAR {
+        return None;
+    }
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    let year = if negative { -year } else { year };
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if day == 0 || day > days_in_month {
+        return None;
+    }
+    Some(days_from_civil(year, month, day))
+}
+
+// YYYY-MM-DD HH:MM:SS with up to 6 digits of fractional seconds.
+// a T instead of the space is fine too, and a date alone means midnight
+fn parse_timestamp(text: &str) -> Option<i128> {
+    let (date, time) = match text.split_once([' ', 'T']) {
+        Some((date, time)) => (date, time),
+        None => (text, "00:00:00"),
+    };
+    let days = parse_date(date)?;
+    let (clock, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut parts = clock.splitn(3, ':');
+    let hours: u32 = parts.next()?.parse().ok()?;
+    let minutes: u32 = parts.next()?.parse().ok()?;
+    let seconds: u32 = parts.next()?.parse().ok()?;
+    if hours > 23 || minutes > 59 || seconds > 59 || fraction.len() > 6 {
+        return None;
+    }
+    let micros = if fraction.is_empty() {
+        0
+    } else if fraction.chars().all(|c| c.is_ascii_digit()) {
+        format!("{:0<6}", fraction).parse::<i64>().ok()?
+    } else {
+        return None;
+    };
+    let of_day =
+        ((hours as i64 * 60 + minutes as i64) * 60 + seconds as i64) * MICROS_PER_SECOND + micros;
+    Some(days as i128 * MICROS_PER_DAY as i128 + of_day as i128)
+}
+
+impl TupleField {
+    pub(super) fn as_number(&self) -> Option<Number> {
+        let number = match self {
+            TupleField::Int(v) => Number::Integer(*v as i128),
+            TupleField::SignedInt(v) => Number::Integer(*v as i128),
+            TupleField::BigInt(v) => Number::Integer(*v as i128),
+            TupleField::SignedBigInt(v) => Number::Integer(*v as i128),
+            TupleField::Bool(v) => Number::Integer(*v as i128),
+            TupleField::Double(v) => Number::Float(*v as f64),
+            TupleField::Double64(v) => Number::Float(*v),
+            TupleField::Decimal(v) => Number::Decimal(*v),
+            _ => return None,
+        };
+        Some(number)
+    }
+
+    // the value as text, the way a cast to VARCHAR spells it
+    fn to_text(&self) -> Result<String, ConversionError> {
+        let text = match self {
+            TupleField::Int(v) => v.to_string(),
+            TupleField::SignedInt(v) => v.to_string(),
+            TupleField::Double(v) => v.to_string(),
+            TupleField::Char(c) => c.to_string(),
+            TupleField::Text256(text) => text.to_string(),
+            TupleField::Bool(v) => v.to_string(),
+            TupleField::BigInt(v) => v.to_string(),
+            TupleField::SignedBigInt(v) => v.to_string(),
+            TupleField::Double64(v) => v.to_string(),
+            TupleField::Decimal(v) => v.to_string(),
+            TupleField::Date(days) => format_date(*days as i64),
+            TupleField::Timestamp(micros) => format_timestamp(*micros),
+            TupleField::Varchar(text) => text.clone(),
+            TupleField::Blob(bytes) => {
+                String::from_utf8(bytes.clone()).map_err(|_| ConversionError::InvalidUtf8)?
+            }
+            TupleField::Null => unreachable!("NULL converts to NULL without text"),
+        };
+        Ok(text)
+    }
+
+    fn is_text(&self) -> bool {
+        matches!(
+            self,
+            TupleField::Char(_) | TupleField::Text256(_) | TupleField::Varchar(_)
+        )
+    }
+
+    // convert to a value that fits a column of type to.
+    // NULL stays NULL, whatever the type
+    pub fn cast(&self, to: TupleFieldTypes) -> Result<TupleField, ConversionError> {
+        let from = match self.field_type() {
+            Some(from) => from,
+            None => return Ok(TupleField::Null),
+        };
+        if to.accepts(self) {
+            return Ok(self.clone());
+        }
+        let unsupported = ConversionError::Unsupported { from, to };
+        let parse_error = |text: &str| ConversionError::Parse {
+            to,
+            text: text.to_string(),
+        };
+
+        match to {
+            TupleFieldTypes::CHAR | TupleFieldTypes::TEXT256 | TupleFieldTypes::VARCHAR(_) => {
+                text_to(self.to_text()?, to)
+            }
+            TupleFieldTypes::BLOB => match self {
+                TupleField::Blob(_) => Ok(self.clone()),
+                _ if self.is_text() => Ok(TupleField::Blob(self.to_text()?.into_bytes())),
+                _ => Err(unsupported),
+            },
+            TupleFieldTypes::DATE => {
+                let days = match self {
+                    TupleField::Timestamp(micros) => micros.div_euclid(MICROS_PER_DAY),
+                    _ if self.is_text() => {
+                        let text = self.to_text()?;
+                        parse_date(text.trim()).ok_or_else(|| parse_error(&text))?
+                    }
+                    _ => return Err(unsupported),
+                };
+                i32::try_from(days)
+                    .map(TupleField::Date)
+                    .map_err(|_| ConversionError::OutOfRange(to))
+            }
+            TupleFieldTypes::TIMESTAMP => {
+                let micros = match self {
+                    TupleField::Date(days) => *days as i128 * MICROS_PER_DAY as i128,
+                    _ if self.is_text() => {
+                        let text = self.to_text()?;
+                        parse_timestamp(text.trim()).ok_or_else(|| parse_error(&text))?
+                    }
+                    _ => return Err(unsupported),
+                };
+                i64::try_from(micros)
+                    .map(TupleField::Timestamp)
+                    .map_err(|_| ConversionError::OutOfRange(to))
+            }
+            _ => {
+                let number = match self.as_number() {
+                    Some(number) => number,
+                    None if self.is_text() => {
+                        let text = self.to_text()?;
+                        parse_number(text.trim(), to).ok_or_else(|| parse_error(&text))?
+                    }
+                    None => return Err(unsupported),
+                };
+                number_to(number, to, unsupported)
+            }
+        }
+    }
+}
+
+fn parse_number(text: &str, to: TupleFieldTypes) -> Option<Number> {
+    match to {
+        TupleFieldTypes::DOUBLE | TupleFieldTypes::DOUBLE64 => text.parse().ok().map(Number::Float),
+        TupleFieldTypes::DECIMAL { .. } => parse_decimal(text).map(Number::Decimal),
+        TupleFieldTypes::BOOL => match text.to_ascii_lowercase().as_str() {
+            "true" => Some(Number::Integer(1)),
+            "false" => Some(Number::Integer(0)),
+            _ => None,
+        },
+        _ => text.parse().ok().map(Number::Integer),
+    }
+}
+
+fn text_to(text: String, to: TupleFieldTypes) -> Result<TupleField, ConversionError> {
+    let out_of_range = ConversionError::OutOfRange(to);
+    match to {
+        TupleFieldTypes::CHAR => {
+            let mut chars = text.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(TupleField::Char(c)),
+                _ => Err(out_of_range),
+            }
+        }
+        TupleFieldTypes::TEXT256 => Text256::new(&text)
+            .map(TupleField::Text256)
+            .map_err(|_| out_of_range),
+        TupleFieldTypes::VARCHAR(max) if text.chars().count() <= max as usize => {
+            Ok(TupleField::Varchar(text))
+        }
+        _ => Err(out_of_range),
+    }
+}
+
+fn number_to(
+    number: Number,
+    to: TupleFieldTypes,
+    unsupported: ConversionError,
+) -> Result<TupleField, ConversionError> {
+    let out_of_range = || ConversionError::OutOfRange(to);
+    match to {
+        TupleFieldTypes::DOUBLE | TupleFieldTypes::DOUBLE64 => {
+            let value = match number {
+                Number::Integer(v) => v as f64,
+                Number::Float(v) => v,
+                Number::Decimal(v) => v.to_f64(),
+            };
+            if to == TupleFieldTypes::DOUBLE64 {
+                return Ok(TupleField::Double64(value));
+            }
+            let narrowed = value as f32;
+            if narrowed.is_infinite() && value.is_finite() {
+                return Err(out_of_range());
+            }
+            Ok(TupleField::Double(narrowed))
+        }
+        TupleFieldTypes::DECIMAL { precision, scale } => {
+            let decimal = match number {
+                Number::Integer(v) => Decimal::new(v, 0),
+                Number::Decimal(v) => v,
+                Number::Float(v) => {
+                    let scaled = (v * 10f64.powi(scale as i32)).round();
+                    if !scaled.is_finite() || scaled.abs() >= 1e38 {
+                        return Err(out_of_range());
+                    }
+                    Decimal::new(scaled as i128, scale)
+                }
+            };
+            match decimal.rescale(scale) {
+                Some(d) if d.digits() <= precision.min(MAX_DECIMAL_PRECISION) => {
+                    Ok(TupleField::Decimal(d))
+                }
+                _ => Err(out_of_range()),
+            }
+        }
+        // only whole numbers have a truth value
+        TupleFieldTypes::BOOL => match number {
+            Number::Integer(v) => Ok(TupleField::Bool(v != 0)),
+            _ => Err(unsupported),
+        },
+        _ => {
+            let value = match number {
+                Number::Integer(v) => v,
+                Number::Float(v) => {
+                    let rounded = v.round();
+                    // anything this far out is out of range for every integer type anyway
+                    if !rounded.is_finite() || rounded.abs() >= 1e30 {
+                        return Err(out_of_range());
+                    }
+                    rounded as i128
+                }
+                Number::Decimal(v) => v.rescale(0).ok_or_else(out_of_range)?.unscaled,
+            };
+            let field = match to {
+                TupleFieldTypes::INT => u32::try_from(value).map(TupleField::Int),
+                TupleFieldTypes::SIGNEDINT => i32::try_from(value).map(TupleField::SignedInt),
+                TupleFieldTypes::BIGINT => u64::try_from(value).map(TupleField::BigInt),
+                TupleFieldTypes::SIGNEDBIGINT => i64::try_from(value).map(TupleField::SignedBigInt),
+                _ => return Err(unsupported),
+            };
+            field.map_err(|_| out_of_range())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{civil_from_days, days_from_civil, ConversionError};
+    use crate::page_interpretation::{Decimal, Text256, TupleField, TupleFieldTypes};
+
+    fn varchar(text: &str) -> TupleField {
+        TupleField::Varchar(text.to_string())
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(
+            TupleField::SignedInt(-1).cast(TupleFieldTypes::SIGNEDBIGINT),
+            Ok(TupleField::SignedBigInt(-1))
+        );
+        assert_eq!(
+            TupleField::SignedInt(-1).cast(TupleFieldTypes::INT),
+            Err(ConversionError::OutOfRange(TupleFieldTypes::INT))
+        );
+        assert_eq!(
+            TupleField::BigInt(u32::MAX as u64 + 1).cast(TupleFieldTypes::INT),
+            Err(ConversionError::OutOfRange(TupleFieldTypes::INT))
+        );
+        assert_eq!(
+            TupleField::Double64(-2.5).cast(TupleFieldTypes::SIGNEDINT),
+            Ok(TupleField::SignedInt(-3))
+        );
+        assert_eq!(
+            TupleField::Double(f32::NAN).cast(TupleFieldTypes::BIGINT),
+            Err(ConversionError::OutOfRange(TupleFieldTypes::BIGINT))
+        );
+        assert_eq!(
+            TupleField::Int(2).cast(TupleFieldTypes::BOOL),
+            Ok(TupleField::Bool(true))
+        );
+        assert_eq!(
+            TupleField::Null.cast(TupleFieldTypes::DATE),
+            Ok(TupleField::Null)
+        );
+    }
+
+    #[test]
+    fn decimals() {
+        let money = TupleFieldTypes::DECIMAL {
+            precision: 6,
+            scale: 2,
+        };
+        assert_eq!(
+            TupleField::Decimal(Decimal::new(12345, 3)).cast(money),
+            Ok(TupleField::Decimal(Decimal::new(1235, 2)))
+        );
+        assert_eq!(
+            TupleField::Decimal(Decimal::new(-12345, 3)).cast(money),
+            Ok(TupleField::Decimal(Decimal::new(-1235, 2)))
+        );
+        assert_eq!(
+            TupleField::Int(42).cast(money),
+            Ok(TupleField::Decimal(Decimal::new(4200, 2)))
+        );
+        assert_eq!(
+            TupleField::Int(10_000).cast(money),
+            Err(ConversionError::OutOfRange(money))
+        );
+        assert_eq!(
+            TupleField::Double64(0.125).cast(money),
+            Ok(TupleField::Decimal(Decimal::new(13, 2)))
+        );
+        assert_eq!(
+            TupleField::Decimal(Decimal::new(250, 2)).cast(TupleFieldTypes::SIGNEDINT),
+            Ok(TupleField::SignedInt(3))
+        );
+        assert_eq!(
+            TupleField::Decimal(Decimal::new(-5, 1)).cast(TupleFieldTypes::DOUBLE64),
+            Ok(TupleField::Double64(-0.5))
+        );
+        assert_eq!(
+            varchar(" -1.5 ").cast(money),
+            Ok(TupleField::Decimal(Decimal::new(-150, 2)))
+        );
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            TupleField::Decimal(Decimal::new(-150, 2)).cast(TupleFieldTypes::VARCHAR(10)),
+            Ok(varchar("-1.50"))
+        );
+        assert_eq!(
+            TupleField::BigInt(123456).cast(TupleFieldTypes::VARCHAR(3)),
+            Err(ConversionError::OutOfRange(TupleFieldTypes::VARCHAR(3)))
+        );
+        assert_eq!(
+            varchar("x").cast(TupleFieldTypes::CHAR),
+            Ok(TupleField::Char('x'))
+        );
+        assert_eq!(
+            varchar("12x").cast(TupleFieldTypes::INT),
+            Err(ConversionError::Parse {
+                to: TupleFieldTypes::INT,
+                text: "12x".to_string()
+            })
+        );
+        assert_eq!(
+            varchar("TRUE").cast(TupleFieldTypes::BOOL),
+            Ok(TupleField::Bool(true))
+        );
+
+        let hi = TupleField::Text256(Text256::new("hi").unwrap());
+        assert_eq!(hi.cast(TupleFieldTypes::VARCHAR(2)), Ok(varchar("hi")));
+        assert_eq!(varchar("hi").cast(TupleFieldTypes::TEXT256), Ok(hi));
+        assert_eq!(
+            varchar(&"é".repeat(129)).cast(TupleFieldTypes::TEXT256),
+            Err(ConversionError::OutOfRange(TupleFieldTypes::TEXT256))
+        );
+
+        assert_eq!(
+            varchar("hé").cast(TupleFieldTypes::BLOB),
+            Ok(TupleField::Blob("hé".as_bytes().to_vec()))
+        );
+        assert_eq!(
+            TupleField::Blob(vec![0xff]).cast(TupleFieldTypes::VARCHAR(5)),
+            Err(ConversionError::InvalidUtf8)
+        );
+        assert_eq!(
+            TupleField::Date(0).cast(TupleFieldTypes::BIGINT),
+            Err(ConversionError::Unsupported {
+                from: TupleFieldTypes::DATE,
+                to: TupleFieldTypes::BIGINT
+            })
+        );
+    }
+
+    #[test]
+    fn dates_and_timestamps() {
+        assert_eq!(
+            TupleField::Date(0).cast(TupleFieldTypes::VARCHAR(20)),
+            Ok(varchar("1970-01-01"))
+        );
+        assert_eq!(
+            varchar("2000-02-29").cast(TupleFieldTypes::DATE),
+            Ok(TupleField::Date(11_016))
+        );
+        assert!(matches!(
+            varchar("2001-02-29").cast(TupleFieldTypes::DATE),
+            Err(ConversionError::Parse { .. })
+        ));
+        assert_eq!(
+            TupleField::Timestamp(-1).cast(TupleFieldTypes::DATE),
+            Ok(TupleField::Date(-1))
+        );
+        assert_eq!(
+            TupleField::Date(1).cast(TupleFieldTypes::TIMESTAMP),
+            Ok(TupleField::Timestamp(86_400_000_000))
+        );
+        assert_eq!(
+            TupleField::Timestamp(-1).cast(TupleFieldTypes::VARCHAR(30)),
+            Ok(varchar("1969-12-31 23:59:59.999999"))
+        );
+        assert_eq!(
+            varchar("1969-12-31T23:59:59.5").cast(TupleFieldTypes::TIMESTAMP),
+            Ok(TupleField::Timestamp(-500_000))
+        );
+        // too far out for any date, rather than overflowing
+        for text in ["9223372036854775807-03-01", "-9223372036854775807-03-01"] {
+            for to in [TupleFieldTypes::DATE, TupleFieldTypes::TIMESTAMP] {
+                assert!(matches!(
+                    varchar(text).cast(to),
+                    Err(ConversionError::Parse { .. })
+                ));
+            }
+        }
+        assert_eq!(
+            varchar("5900000-01-01").cast(TupleFieldTypes::DATE),
+            Err(ConversionError::OutOfRange(TupleFieldTypes::DATE))
+        );
+        assert!(matches!(
+            varchar("2024-01-01 -5:-30:00").cast(TupleFieldTypes::TIMESTAMP),
+            Err(ConversionError::Parse { .. })
+        ));
+        for days in (-1_000_000..1_000_000).step_by(997) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+}
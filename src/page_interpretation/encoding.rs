@@ -0,0 +1,498 @@
+use super::{Column, Decimal, Text256, TupleField, TupleFieldTypes};
+use std::fmt;
+
+// byte layouts are documented in docs/tuples.md.
+// everything is big endian, like the database header
+
+#[derive(Debug, PartialEq)]
+pub enum EncodingError {
+    // the row has a different number of fields than the schema
+    ColumnCount {
+        expected: usize,
+        found: usize,
+    },
+    // NULL in a column that isn't nullable
+    UnexpectedNull(usize),
+    // a field doesn't have the type its column declares
+    TypeMismatch {
+        column: usize,
+        expected: TupleFieldTypes,
+        found: TupleFieldTypes,
+    },
+    // ran out of bytes while decoding
+    Truncated {
+        needed: usize,
+        remaining: usize,
+    },
+    // decoding finished with bytes left over
+    TrailingBytes(usize),
+    InvalidChar(u32),
+    InvalidBool(u8),
+    InvalidUtf8,
+    // a stored value is bigger than its column allows
+    OutOfRange(TupleFieldTypes),
+    // a key doesn't start with a NULL or value tag
+    InvalidKeyTag(u8),
+    // a 0 byte in an escaped key isn't followed by 0xff or the terminator
+    InvalidEscape(u8),
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodingError::ColumnCount { expected, found } => {
+                write!(f, "expected {} columns, found {}", expected, found)
+            }
+            EncodingError::UnexpectedNull(column) => {
+                write!(f, "column {} is not nullable", column)
+            }
+            EncodingError::TypeMismatch {
+                column,
+                expected,
+                found,
+            } => write!(
+                f,
+                "column {} should be {:?}, found {:?}",
+                column, expected, found
+            ),
+            EncodingError::Truncated { needed, remaining } => write!(
+                f,
+                "needed {} bytes to decode, only {} remaining",
+                needed, remaining
+            ),
+            EncodingError::TrailingBytes(count) => {
+                write!(f, "{} bytes left over after decoding", count)
+            }
+            EncodingError::InvalidChar(value) => write!(f, "{:#x} is not a valid char", value),
+            EncodingError::InvalidBool(value) => write!(f, "{} is not a valid bool", value),
+            EncodingError::InvalidUtf8 => write!(f, "text is not valid utf-8"),
+            EncodingError::OutOfRange(field_type) => {
+                write!(f, "stored value doesn't fit in {:?}", field_type)
+            }
+            EncodingError::InvalidKeyTag(tag) => write!(f, "{} is not a valid key tag", tag),
+            EncodingError::InvalidEscape(byte) => {
+                write!(f, "0 followed by {:#x} is not a valid key escape", byte)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EncodingError {}
+
+// variable length fields start with their length in bytes
+const LENGTH_PREFIX: usize = 4;
+// TEXT256 is never longer than 256 bytes, so two are enough for its length
+const TEXT256_LENGTH_PREFIX: usize = 2;
+
+impl TupleFieldTypes {
+    // None for the variable length types
+    pub fn encoded_size(&self) -> Option<usize> {
+        let size = match self {
+            TupleFieldTypes::INT => 4,
+            TupleFieldTypes::SIGNEDINT => 4,
+            TupleFieldTypes::DOUBLE => 4,
+            TupleFieldTypes::CHAR => 4,
+            TupleFieldTypes::BOOL => 1,
+            TupleFieldTypes::BIGINT => 8,
+            TupleFieldTypes::SIGNEDBIGINT => 8,
+            TupleFieldTypes::DOUBLE64 => 8,
+            TupleFieldTypes::DECIMAL { .. } => 16,
+            TupleFieldTypes::DATE => 4,
+            TupleFieldTypes::TIMESTAMP => 8,
+            TupleFieldTypes::TEXT256 | TupleFieldTypes::VARCHAR(_) | TupleFieldTypes::BLOB => {
+                return None
+            }
+        };
+        Some(size)
+    }
+
+    // the most bytes a value of this type can take, None if it's unbounded
+    pub fn max_encoded_size(&self) -> Option<usize> {
+        match self {
+            TupleFieldTypes::TEXT256 => Some(TEXT256_LENGTH_PREFIX + Text256::MAX_LEN),
+            // a char is at most 4 bytes of utf-8
+            TupleFieldTypes::VARCHAR(max) => Some(LENGTH_PREFIX + *max as usize * 4),
+            TupleFieldTypes::BLOB => None,
+            fixed => fixed.encoded_size(),
+        }
+    }
+}
+
+// big endian reads at an offset into a page
+pub fn get_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+pub fn get_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+pub(super) fn take<'a>(data: &mut &'a [u8], count: usize) -> Result<&'a [u8], EncodingError> {
+    if data.len() < count {
+        return Err(EncodingError::Truncated {
+            needed: count,
+            remaining: data.len(),
+        });
+    }
+    let (taken, rest) = data.split_at(count);
+    *data = rest;
+    Ok(taken)
+}
+
+fn take_u32(data: &mut &[u8]) -> Result<u32, EncodingError> {
+    let bytes = take(data, 4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn take_u64(data: &mut &[u8]) -> Result<u64, EncodingError> {
+    let bytes = take(data, 8)?;
+    Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn take_char(data: &mut &[u8]) -> Result<char, EncodingError> {
+    let value = take_u32(data)?;
+    char::from_u32(value).ok_or(EncodingError::InvalidChar(value))
+}
+
+fn take_bytes<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], EncodingError> {
+    let len = take_u32(data)? as usize;
+    take(data, len)
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    let len = u32::try_from(bytes.len()).expect("field is larger than 4GiB");
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+impl TupleField {
+    // NULLs have no bytes of their own, the row's null bitmap marks them
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            TupleField::Int(value) => out.extend_from_slice(&value.to_be_bytes()),
+            TupleField::SignedInt(value) => out.extend_from_slice(&value.to_be_bytes()),
+            TupleField::Double(value) => out.extend_from_slice(&value.to_bits().to_be_bytes()),
+            TupleField::Char(value) => out.extend_from_slice(&(*value as u32).to_be_bytes()),
+            TupleField::Text256(text) => {
+                out.extend_from_slice(&(text.len() as u16).to_be_bytes());
+                out.extend_from_slice(text.as_bytes());
+            }
+            TupleField::Bool(value) => out.push(*value as u8),
+            TupleField::BigInt(value) => out.extend_from_slice(&value.to_be_bytes()),
+            TupleField::SignedBigInt(value) => out.extend_from_slice(&value.to_be_bytes()),
+            TupleField::Double64(value) => out.extend_from_slice(&value.to_bits().to_be_bytes()),
+            // the scale belongs to the column, only the digits are stored
+            TupleField::Decimal(value) => out.extend_from_slice(&value.unscaled.to_be_bytes()),
+            TupleField::Date(days) => out.extend_from_slice(&days.to_be_bytes()),
+            TupleField::Timestamp(micros) => out.extend_from_slice(&micros.to_be_bytes()),
+            TupleField::Varchar(text) => put_bytes(out, text.as_bytes()),
+            TupleField::Blob(bytes) => put_bytes(out, bytes),
+            TupleField::Null => {}
+        }
+    }
+
+    // decode one field from the front of data, advancing data past it
+    pub fn decode(field_type: TupleFieldTypes, data: &mut &[u8]) -> Result<Self, EncodingError> {
+        let field = match field_type {
+            TupleFieldTypes::INT => TupleField::Int(take_u32(data)?),
+            TupleFieldTypes::SIGNEDINT => TupleField::SignedInt(take_u32(data)? as i32),
+            TupleFieldTypes::DOUBLE => TupleField::Double(f32::from_bits(take_u32(data)?)),
+            TupleFieldTypes::CHAR => TupleField::Char(take_char(data)?),
+            TupleFieldTypes::TEXT256 => {
+                let len = take(data, TEXT256_LENGTH_PREFIX)?;
+                let bytes = take(data, u16::from_be_bytes([len[0], len[1]]) as usize)?;
+                let text = std::str::from_utf8(bytes).map_err(|_| EncodingError::InvalidUtf8)?;
+                let text = Text256::new(text).map_err(|_| EncodingError::OutOfRange(field_type))?;
+                TupleField::Text256(text)
+            }
+            TupleFieldTypes::BOOL => match take(data, 1)?[0] {
+                0 => TupleField::Bool(false),
+                1 => TupleField::Bool(true),
+                other => return Err(EncodingError::InvalidBool(other)),
+            },
+            TupleFieldTypes::BIGINT => TupleField::BigInt(take_u64(data)?),
+            TupleFieldTypes::SIGNEDBIGINT => TupleField::SignedBigInt(take_u64(data)? as i64),
+            TupleFieldTypes::DOUBLE64 => TupleField::Double64(f64::from_bits(take_u64(data)?)),
+            TupleFieldTypes::DECIMAL { scale, .. } => {
+                let unscaled = i128::from_be_bytes(take(data, 16)?.try_into().unwrap());
+                TupleField::Decimal(Decimal::new(unscaled, scale))
+            }
+            TupleFieldTypes::DATE => TupleField::Date(take_u32(data)? as i32),
+            TupleFieldTypes::TIMESTAMP => TupleField::Timestamp(take_u64(data)? as i64),
+            TupleFieldTypes::VARCHAR(_) => {
+                let bytes = take_bytes(data)?;
+                let text = std::str::from_utf8(bytes).map_err(|_| EncodingError::InvalidUtf8)?;
+                TupleField::Varchar(text.to_string())
+            }
+            TupleFieldTypes::BLOB => TupleField::Blob(take_bytes(data)?.to_vec()),
+        };
+        // VARCHAR lengths and DECIMAL precision are limits the bytes alone can't enforce
+        if !field_type.accepts(&field) {
+            return Err(EncodingError::OutOfRange(field_type));
+        }
+        Ok(field)
+    }
+}
+
+fn null_bitmap_size(columns: usize) -> usize {
+    columns.div_ceil(8)
+}
+
+// the largest a row of this schema can be, when none of its fields are NULL.
+// None if it has a BLOB column, which has no upper bound
+pub fn max_row_size(schema: &[Column]) -> Option<usize> {
+    schema
+        .iter()
+        .map(|c| c.field_type.max_encoded_size())
+        .sum::<Option<usize>>()
+        .map(|fields| null_bitmap_size(schema.len()) + fields)
+}
+
+// a row starts with a null bitmap, one bit per column with the lowest bit of
+// the first byte for the first column, set when that column is NULL.
+// the non NULL fields follow back to back in schema order, with no padding
+pub fn encode_row(row: &[TupleField], schema: &[Column]) -> Result<Vec<u8>, EncodingError> {
+    if row.len() != schema.len() {
+        return Err(EncodingError::ColumnCount {
+            expected: schema.len(),
+            found: row.len(),
+        });
+    }
+    let mut out = vec![0; null_bitmap_size(schema.len())];
+    for (column, (field, expected)) in row.iter().zip(schema).enumerate() {
+        match field.field_type() {
+            None if expected.nullable => out[column / 8] |= 1 << (column % 8),
+            None => return Err(EncodingError::UnexpectedNull(column)),
+            Some(found) if !expected.field_type.accepts(field) => {
+                return Err(EncodingError::TypeMismatch {
+                    column,
+                    expected: expected.field_type,
+                    found,
+                })
+            }
+            Some(_) => field.encode(&mut out),
+        }
+    }
+    Ok(out)
+}
+
+pub fn decode_row(mut data: &[u8], schema: &[Column]) -> Result<Vec<TupleField>, EncodingError> {
+    let nulls = take(&mut data, null_bitmap_size(schema.len()))?;
+    let mut row = Vec::with_capacity(schema.len());
+    for (column, expected) in schema.iter().enumerate() {
+        if nulls[column / 8] & (1 << (column % 8)) != 0 {
+            if !expected.nullable {
+                return Err(EncodingError::UnexpectedNull(column));
+            }
+            row.push(TupleField::Null);
+        } else {
+            row.push(TupleField::decode(expected.field_type, &mut data)?);
+        }
+    }
+    if !data.is_empty() {
+        return Err(EncodingError::TrailingBytes(data.len()));
+    }
+    Ok(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_row, encode_row, max_row_size, EncodingError};
+    use crate::page_interpretation::{Column, Decimal, TupleField, TupleFieldTypes};
+    use crate::utils::testing::{random_field, Rng, TYPES};
+
+    #[test]
+    fn layouts() {
+        let mut out = Vec::new();
+        TupleField::Int(0x01020304).encode(&mut out);
+        TupleField::SignedInt(-2).encode(&mut out);
+        TupleField::Double(1.0).encode(&mut out);
+        TupleField::Char('é').encode(&mut out);
+        TupleField::Bool(true).encode(&mut out);
+        assert_eq!(
+            out,
+            [1, 2, 3, 4, 0xff, 0xff, 0xff, 0xfe, 0x3f, 0x80, 0, 0, 0, 0, 0, 0xe9, 1]
+        );
+
+        let mut out = Vec::new();
+        TupleField::SignedBigInt(-2).encode(&mut out);
+        TupleField::Date(1).encode(&mut out);
+        TupleField::Varchar("hé".to_string()).encode(&mut out);
+        TupleField::Blob(vec![7]).encode(&mut out);
+        assert_eq!(
+            out,
+            [
+                0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe, 0, 0, 0, 1, 0, 0, 0, 3, b'h', 0xc3,
+                0xa9, 0, 0, 0, 1, 7
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip_random_rows() {
+        for seed in 0..500 {
+            let mut rng = Rng::new(seed);
+            let columns = 1 + rng.below(20) as usize;
+            let schema: Vec<_> = (0..columns)
+                .map(|_| Column {
+                    field_type: TYPES[rng.below(TYPES.len() as u64) as usize],
+                    nullable: rng.bool(),
+                })
+                .collect();
+            let row: Vec<_> = schema
+                .iter()
+                .map(|c| {
+                    if c.nullable && rng.below(3) == 0 {
+                        TupleField::Null
+                    } else {
+                        random_field(&mut rng, c.field_type)
+                    }
+                })
+                .collect();
+
+            let encoded = encode_row(&row, &schema).unwrap();
+            if let Some(max) = max_row_size(&schema) {
+                assert!(encoded.len() <= max, "seed {}", seed);
+            }
+            let decoded = decode_row(&encoded, &schema).unwrap();
+            // NaN != NaN, so compare the bytes instead of the fields
+            assert_eq!(
+                encode_row(&decoded, &schema).unwrap(),
+                encoded,
+                "seed {}",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn null_bitmap() {
+        let schema: Vec<_> = (0..9)
+            .map(|_| Column::nullable(TupleFieldTypes::BOOL))
+            .collect();
+        let mut row = vec![TupleField::Bool(true); 9];
+        row[1] = TupleField::Null;
+        row[8] = TupleField::Null;
+        let encoded = encode_row(&row, &schema).unwrap();
+        assert_eq!(encoded, [0b10, 1, 1, 1, 1, 1, 1, 1, 1]);
+        assert_eq!(decode_row(&encoded, &schema).unwrap(), row);
+    }
+
+    #[test]
+    fn schema_mismatch() {
+        let schema = [
+            Column::not_null(TupleFieldTypes::INT),
+            Column::not_null(TupleFieldTypes::BOOL),
+        ];
+        assert_eq!(
+            encode_row(&[TupleField::Int(1)], &schema),
+            Err(EncodingError::ColumnCount {
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            encode_row(&[TupleField::Int(1), TupleField::Int(2)], &schema),
+            Err(EncodingError::TypeMismatch {
+                column: 1,
+                expected: TupleFieldTypes::BOOL,
+                found: TupleFieldTypes::INT
+            })
+        );
+        assert_eq!(
+            encode_row(&[TupleField::Int(1), TupleField::Null], &schema),
+            Err(EncodingError::UnexpectedNull(1))
+        );
+        // a NULL bit set for a column that can't be NULL is corruption
+        assert_eq!(
+            decode_row(&[0b10, 0, 0, 0, 1], &schema),
+            Err(EncodingError::UnexpectedNull(1))
+        );
+    }
+
+    #[test]
+    fn bad_bytes() {
+        let int = [Column::not_null(TupleFieldTypes::INT)];
+        assert_eq!(
+            decode_row(&[0, 0, 0, 1], &int),
+            Err(EncodingError::Truncated {
+                needed: 4,
+                remaining: 3
+            })
+        );
+        assert_eq!(
+            decode_row(&[0, 0, 0, 0, 1, 9], &int),
+            Err(EncodingError::TrailingBytes(1))
+        );
+        assert_eq!(
+            decode_row(&[0, 2], &[Column::not_null(TupleFieldTypes::BOOL)]),
+            Err(EncodingError::InvalidBool(2))
+        );
+        assert_eq!(
+            decode_row(
+                &[0, 0, 0, 0xd8, 0],
+                &[Column::not_null(TupleFieldTypes::CHAR)]
+            ),
+            Err(EncodingError::InvalidChar(0xd800))
+        );
+        assert_eq!(
+            decode_row(
+                &[0, 0, 0, 0, 1, 0xff],
+                &[Column::not_null(TupleFieldTypes::VARCHAR(4))]
+            ),
+            Err(EncodingError::InvalidUtf8)
+        );
+        assert_eq!(
+            decode_row(
+                &[0, 0, 0, 0, 2, b'h', b'i'],
+                &[Column::not_null(TupleFieldTypes::VARCHAR(1))]
+            ),
+            Err(EncodingError::OutOfRange(TupleFieldTypes::VARCHAR(1)))
+        );
+    }
+
+    #[test]
+    fn column_limits() {
+        let schema = [
+            Column::not_null(TupleFieldTypes::VARCHAR(2)),
+            Column::not_null(TupleFieldTypes::DECIMAL {
+                precision: 4,
+                scale: 2,
+            }),
+        ];
+        let row = |text: &str, unscaled, scale| {
+            [
+                TupleField::Varchar(text.to_string()),
+                TupleField::Decimal(Decimal::new(unscaled, scale)),
+            ]
+        };
+        assert!(encode_row(&row("ab", 9999, 2), &schema).is_ok());
+        assert_eq!(
+            encode_row(&row("abc", 1, 2), &schema),
+            Err(EncodingError::TypeMismatch {
+                column: 0,
+                expected: TupleFieldTypes::VARCHAR(2),
+                found: TupleFieldTypes::VARCHAR(3)
+            })
+        );
+        assert!(matches!(
+            encode_row(&row("a", 10000, 2), &schema),
+            Err(EncodingError::TypeMismatch { column: 1, .. })
+        ));
+        // a scale that doesn't match the column needs a cast first
+        assert!(matches!(
+            encode_row(&row("a", 1, 1), &schema),
+            Err(EncodingError::TypeMismatch { column: 1, .. })
+        ));
+        assert_eq!(max_row_size(&schema), Some(1 + 4 + 8 + 16));
+        assert_eq!(
+            max_row_size(&[Column::not_null(TupleFieldTypes::BLOB)]),
+            None
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use super::encoding::{take, EncodingError};
+use super::{Column, Decimal, Text256, TupleField, TupleFieldTypes};
+
+// memcomparable keys: encodings whose bytes compare the same way the values do,
+// so index pages can compare keys with memcmp instead of decoding them.
+//
+// a key is a tag byte, 0 for NULL and 1 for anything else, then the value:
+// - unsigned integers and chars big endian
+// - signed integers, dates, timestamps and decimals big endian with the sign bit flipped,
+//   so negatives sort below positives
+// - floats with the sign bit flipped if positive and every bit flipped if negative.
+//   -0.0 becomes 0.0 and every NaN the same NaN first, so they sort like Ord says
+// - text and blobs byte by byte, with every 0 byte escaped as 0 0xff and a 0 0
+//   terminator, so a value sorts before anything it is a prefix of
+//
+// a descending key is the same with every bit flipped, so it sorts the other way,
+// NULLs last. it's still self delimiting, so it can sit between ascending keys.
+//
+// keys only agree with Ord between values of the same column type.
+// the encodings of equal values of different types, like INT 1 and BIGINT 1, differ,
+// and so do decimals of different scales
+
+const NULL_TAG: u8 = 0;
+const VALUE_TAG: u8 = 1;
+
+const ESCAPE: u8 = 0;
+const ESCAPED_ZERO: u8 = 0xff;
+const TERMINATOR: u8 = 0;
+
+fn put_escaped(out: &mut Vec<u8>, bytes: &[u8]) {
+    for byte in bytes {
+        out.push(*byte);
+        if *byte == ESCAPE {
+            out.push(ESCAPED_ZERO);
+        }
+    }
+    out.extend_from_slice(&[ESCAPE, TERMINATOR]);
+}
+
+// take N bytes off the front of a key, xored with flip. descending keys
+// are flipped back as they're read, since we don't know where they end
+fn take_flipped<const N: usize>(data: &mut &[u8], flip: u8) -> Result<[u8; N], EncodingError> {
+    let bytes = take(data, N)?;
+    Ok(std::array::from_fn(|i| bytes[i] ^ flip))
+}
+
+fn take_escaped(data: &mut &[u8], flip: u8) -> Result<Vec<u8>, EncodingError> {
+    let mut bytes = Vec::new();
+    loop {
+        let [byte] = take_flipped(data, flip)?;
+        if byte != ESCAPE {
+            bytes.push(byte);
+            continue;
+        }
+        match take_flipped::<1>(data, flip)?[0] {
+            ESCAPED_ZERO => bytes.push(0),
+            TERMINATOR => return Ok(bytes),
+            other => return Err(EncodingError::InvalidEscape(other)),
+        }
+    }
+}
+
+fn f32_key(value: f32) -> u32 {
+    let value = if value.is_nan() {
+        f32::NAN
+    } else if value == 0.0 {
+        0.0
+    } else {
+        value
+    };
+    let bits = value.to_bits();
+    if bits >> 31 == 1 {
+        !bits
+    } else {
+        bits ^ (1 << 31)
+    }
+}
+
+fn f32_from_key(key: u32) -> f32 {
+    if key >> 31 == 1 {
+        f32::from_bits(key ^ (1 << 31))
+    } else {
+        f32::from_bits(!key)
+    }
+}
+
+fn f64_key(value: f64) -> u64 {
+    let value = if value.is_nan() {
+        f64::NAN
+    } else if value == 0.0 {
+        0.0
+    } else {
+        value
+    };
+    let bits = value.to_bits();
+    if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits ^ (1 << 63)
+    }
+}
+
+fn f64_from_key(key: u64) -> f64 {
+    if key >> 63 == 1 {
+        f64::from_bits(key ^ (1 << 63))
+    } else {
+        f64::from_bits(!key)
+    }
+}
+
+// check field can go in a key column, the same way encode_row checks a row
+pub fn check_key_field(
+    column: usize,
+    field: &TupleField,
+    expected: &Column,
+) -> Result<(), EncodingError> {
+    match field.field_type() {
+        None if expected.nullable => Ok(()),
+        None => Err(EncodingError::UnexpectedNull(column)),
+        Some(found) if !expected.field_type.accepts(field) => Err(EncodingError::TypeMismatch {
+            column,
+            expected: expected.field_type,
+            found,
+        }),
+        Some(_) => Ok(()),
+    }
+}
+
+// append the key for field to out. keys are self delimiting, so
+// several can be appended back to back and still compare column by column
+pub fn encode_key(field: &TupleField, out: &mut Vec<u8>) {
+    if field.is_null() {
+        out.push(NULL_TAG);
+        return;
+    }
+    out.push(VALUE_TAG);
+    match field {
+        TupleField::Int(v) => out.extend_from_slice(&v.to_be_bytes()),
+        TupleField::SignedInt(v) => out.extend_from_slice(&(*v as u32 ^ 1 << 31).to_be_bytes()),
+        TupleField::Double(v) => out.extend_from_slice(&f32_key(*v).to_be_bytes()),
+        TupleField::Char(c) => out.extend_from_slice(&(*c as u32).to_be_bytes()),
+        TupleField::Text256(text) => put_escaped(out, text.as_bytes()),
+        TupleField::Bool(v) => out.push(*v as u8),
+        TupleField::BigInt(v) => out.extend_from_slice(&v.to_be_bytes()),
+        TupleField::SignedBigInt(v) => out.extend_from_slice(&(*v as u64 ^ 1 << 63).to_be_bytes()),
+        TupleField::Double64(v) => out.extend_from_slice(&f64_key(*v).to_be_bytes()),
+        TupleField::Decimal(d) => {
+            out.extend_from_slice(&(d.unscaled as u128 ^ 1 << 127).to_be_bytes())
+        }
+        TupleField::Date(days) => out.extend_from_slice(&(*days as u32 ^ 1 << 31).to_be_bytes()),
+        TupleField::Timestamp(micros) => {
+            out.extend_from_slice(&(*micros as u64 ^ 1 << 63).to_be_bytes())
+        }
+        TupleField::Varchar(text) => put_escaped(out, text.as_bytes()),
+        TupleField::Blob(bytes) => put_escaped(out, bytes),
+        TupleField::Null => unreachable!(),
+    }
+}
+
+// decode one key from the front of data, advancing data past it.
+// floats come back canonical, without their NaN payloads or negative zeros
+pub fn decode_key(
+    field_type: TupleFieldTypes,
+    data: &mut &[u8],
+) -> Result<TupleField, EncodingError> {
+    decode_flipped(field_type, data, 0)
+}
+
+// decode_key, with every byte xored with flip on the way
+fn decode_flipped(
+    field_type: TupleFieldTypes,
+    data: &mut &[u8],
+    flip: u8,
+) -> Result<TupleField, EncodingError> {
+    let take_u32 = |data: &mut &[u8]| take_flipped(data, flip).map(u32::from_be_bytes);
+    let take_u64 = |data: &mut &[u8]| take_flipped(data, flip).map(u64::from_be_bytes);
+    match take_flipped::<1>(data, flip)?[0] {
+        NULL_TAG => return Ok(TupleField::Null),
+        VALUE_TAG => {}
+        other => return Err(EncodingError::InvalidKeyTag(other)),
+    }
+    let field = match field_type {
+        TupleFieldTypes::INT => TupleField::Int(take_u32(data)?),
+        TupleFieldTypes::SIGNEDINT => TupleField::SignedInt((take_u32(data)? ^ 1 << 31) as i32),
+        TupleFieldTypes::DOUBLE => TupleField::Double(f32_from_key(take_u32(data)?)),
+        TupleFieldTypes::CHAR => {
+            let value = take_u32(data)?;
+            TupleField::Char(char::from_u32(value).ok_or(EncodingError::InvalidChar(value))?)
+        }
+        TupleFieldTypes::BOOL => match take_flipped::<1>(data, flip)?[0] {
+            0 => TupleField::Bool(false),
+            1 => TupleField::Bool(true),
+            other => return Err(EncodingError::InvalidBool(other)),
+        },
+        TupleFieldTypes::BIGINT => TupleField::BigInt(take_u64(data)?),
+        TupleFieldTypes::SIGNEDBIGINT => {
+            TupleField::SignedBigInt((take_u64(data)? ^ 1 << 63) as i64)
+        }
+        TupleFieldTypes::DOUBLE64 => TupleField::Double64(f64_from_key(take_u64(data)?)),
+        TupleFieldTypes::DECIMAL { scale, .. } => {
+            let unscaled = (u128::from_be_bytes(take_flipped(data, flip)?) ^ 1 << 127) as i128;
+            TupleField::Decimal(Decimal::new(unscaled, scale))
+        }
+        TupleFieldTypes::DATE => TupleField::Date((take_u32(data)? ^ 1 << 31) as i32),
+        TupleFieldTypes::TIMESTAMP => TupleField::Timestamp((take_u64(data)? ^ 1 << 63) as i64),
+        TupleFieldTypes::TEXT256 | TupleFieldTypes::VARCHAR(_) => {
+            let text = String::from_utf8(take_escaped(data, flip)?)
+                .map_err(|_| EncodingError::InvalidUtf8)?;
+            if field_type == TupleFieldTypes::TEXT256 {
+                let text =
+                    Text256::new(&text).map_err(|_| EncodingError::OutOfRange(field_type))?;
+                TupleField::Text256(text)
+            } else {
+                TupleField::Varchar(text)
+            }
+        }
+        TupleFieldTypes::BLOB => TupleField::Blob(take_escaped(data, flip)?),
+    };
+    if !field_type.accepts(&field) {
+        return Err(EncodingError::OutOfRange(field_type));
+    }
+    Ok(field)
+}
+
+// append the key for field to out, sorting from biggest to smallest
+pub fn encode_descending_key(field: &TupleField, out: &mut Vec<u8>) {
+    let start = out.len();
+    encode_key(field, out);
+    for byte in &mut out[start..] {
+        *byte = !*byte;
+    }
+}
+
+// decode one descending key from the front of data, advancing data past it
+pub fn decode_descending_key(
+    field_type: TupleFieldTypes,
+    data: &mut &[u8],
+) -> Result<TupleField, EncodingError> {
+    decode_flipped(field_type, data, 0xff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_descending_key, decode_key, encode_descending_key, encode_key};
+    use crate::page_interpretation::{TupleField, TupleFieldTypes};
+    use crate::utils::testing::{random_field, Rng, TYPES};
+
+    fn key(field: &TupleField) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_key(field, &mut out);
+        out
+    }
+
+    fn check_pair(a: &TupleField, b: &TupleField) {
+        assert_eq!(key(a).cmp(&key(b)), a.cmp(b), "{:?} vs {:?}", a, b);
+    }
+
+    #[test]
+    fn keys_sort_like_values() {
+        let mut rng = Rng::new(36);
+        for field_type in TYPES {
+            let mut values: Vec<_> = (0..200)
+                .map(|_| {
+                    if rng.below(20) == 0 {
+                        TupleField::Null
+                    } else {
+                        random_field(&mut rng, field_type)
+                    }
+                })
+                .collect();
+            if field_type == TupleFieldTypes::DOUBLE64 {
+                values.extend(
+                    [0.0, -0.0, f64::NAN, -f64::NAN, f64::INFINITY, -1.0, 1.0]
+                        .map(TupleField::Double64),
+                );
+            }
+            if field_type == TupleFieldTypes::BLOB {
+                values.extend(
+                    [vec![], vec![0], vec![0, 0], vec![0, 1], vec![1]].map(TupleField::Blob),
+                );
+            }
+            for a in &values {
+                for b in &values {
+                    check_pair(a, b);
+                }
+                let encoded = key(a);
+                let mut data = &encoded[..];
+                assert_eq!(&decode_key(field_type, &mut data).unwrap(), a);
+                assert!(data.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn keys_concatenate() {
+        let row = |text: &str, n| {
+            let mut out = key(&TupleField::Varchar(text.to_string()));
+            encode_key(&TupleField::SignedInt(n), &mut out);
+            out
+        };
+        // the shorter text wins even though the next column's bytes are bigger
+        assert!(row("a", i32::MAX) < row("a\0", i32::MIN));
+        assert!(row("a", -1) < row("a", 1));
+        assert!(row("", 5) < row("a", -5));
+
+        let encoded = row("x\0y", 7);
+        let mut data = &encoded[..];
+        assert_eq!(
+            decode_key(TupleFieldTypes::VARCHAR(3), &mut data).unwrap(),
+            TupleField::Varchar("x\0y".to_string())
+        );
+        assert_eq!(
+            decode_key(TupleFieldTypes::SIGNEDINT, &mut data).unwrap(),
+            TupleField::SignedInt(7)
+        );
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn descending_keys_sort_backwards() {
+        // ascending text, then descending ints
+        let row = |text: &str, n: Option<i32>| {
+            let mut out = key(&TupleField::Varchar(text.to_string()));
+            encode_descending_key(&n.map_or(TupleField::Null, TupleField::SignedInt), &mut out);
+            out
+        };
+        assert!(row("a", Some(5)) < row("a", Some(-5)));
+        assert!(row("a", Some(i32::MIN)) < row("a", None));
+        assert!(row("a", None) < row("a\0", Some(i32::MAX)));
+        assert!(row("a", Some(1)) < row("b", Some(2)));
+
+        let mut rng = Rng::new(45);
+        for field_type in TYPES {
+            let values: Vec<_> = (0..50)
+                .map(|_| random_field(&mut rng, field_type))
+                .collect();
+            for a in &values {
+                let mut encoded = Vec::new();
+                encode_descending_key(a, &mut encoded);
+                encoded.push(0xab);
+                let mut data = &encoded[..];
+                assert_eq!(&decode_descending_key(field_type, &mut data).unwrap(), a);
+                assert_eq!(data, [0xab]);
+                for b in &values {
+                    let mut other = Vec::new();
+                    encode_descending_key(b, &mut other);
+                    assert_eq!(encoded[..encoded.len() - 1].cmp(&other), b.cmp(a));
+                }
+            }
+        }
+    }
+}
@@ -1,3 +1,25 @@
+// rows and keys are only read and written by the index and heap so far
+#[allow(dead_code)]
+pub mod compare;
+pub mod convert;
+#[allow(dead_code)]
+pub mod encoding;
+#[allow(dead_code)]
+pub mod keys;
+#[allow(dead_code)]
+pub mod slotted;
+pub mod text;
+
+pub use text::Text256;
+
+use std::fmt;
+
+// DECIMAL values are stored as an i128 count of their smallest unit,
+// which holds any 38 digit number
+pub const MAX_DECIMAL_PRECISION: u8 = 38;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TupleFieldTypes {
     INT,
     SIGNEDINT,
@@ -5,13 +27,183 @@ pub enum TupleFieldTypes {
     CHAR,
     TEXT256,
     BOOL,
+    BIGINT,
+    SIGNEDBIGINT,
+    DOUBLE64,
+    // total digits, and how many of them come after the decimal point
+    DECIMAL { precision: u8, scale: u8 },
+    DATE,
+    TIMESTAMP,
+    // at most this many chars
+    VARCHAR(u32),
+    BLOB,
+}
+
+// one column of a schema
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Column {
+    pub field_type: TupleFieldTypes,
+    pub nullable: bool,
+}
+
+impl Column {
+    pub fn not_null(field_type: TupleFieldTypes) -> Self {
+        Column {
+            field_type,
+            nullable: false,
+        }
+    }
+
+    pub fn nullable(field_type: TupleFieldTypes) -> Self {
+        Column {
+            field_type,
+            nullable: true,
+        }
+    }
+}
+
+// a fixed point number, unscaled / 10^scale
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decimal {
+    pub unscaled: i128,
+    pub scale: u8,
+}
+
+impl Decimal {
+    pub fn new(unscaled: i128, scale: u8) -> Self {
+        Decimal { unscaled, scale }
+    }
+
+    // how many digits the value has, ignoring the sign
+    pub fn digits(&self) -> u8 {
+        let mut digits = 1;
+        let mut rest = self.unscaled.unsigned_abs() / 10;
+        while rest > 0 {
+            digits += 1;
+            rest /= 10;
+        }
+        digits
+    }
 }
 
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.unscaled < 0 { "-" } else { "" };
+        let digits = self.unscaled.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        let padded = format!("{:0>width$}", digits, width = scale + 1);
+        let (whole, fraction) = padded.split_at(padded.len() - scale);
+        write!(f, "{}{}.{}", sign, whole, fraction)
+    }
+}
+
+// PartialEq and Ord are implemented in compare.
+// Text256 is inline, so it's still the biggest variant by far
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
 pub enum TupleField {
     Int(u32),
     SignedInt(i32),
     Double(f32),
     Char(char),
-    Text256([char; 256]),
+    Text256(Text256),
     Bool(bool),
+    BigInt(u64),
+    SignedBigInt(i64),
+    Double64(f64),
+    Decimal(Decimal),
+    // days since 1970-01-01
+    Date(i32),
+    // microseconds since 1970-01-01 00:00:00 UTC
+    Timestamp(i64),
+    Varchar(String),
+    Blob(Vec<u8>),
+    // a missing value, allowed in any nullable column
+    Null,
+}
+
+impl TupleField {
+    // None for Null, which fits any nullable column.
+    // values don't know the length limit of the column they came from,
+    // so VARCHARs report their own length and DECIMALs their own digits
+    pub fn field_type(&self) -> Option<TupleFieldTypes> {
+        let field_type = match self {
+            TupleField::Int(_) => TupleFieldTypes::INT,
+            TupleField::SignedInt(_) => TupleFieldTypes::SIGNEDINT,
+            TupleField::Double(_) => TupleFieldTypes::DOUBLE,
+            TupleField::Char(_) => TupleFieldTypes::CHAR,
+            TupleField::Text256(_) => TupleFieldTypes::TEXT256,
+            TupleField::Bool(_) => TupleFieldTypes::BOOL,
+            TupleField::BigInt(_) => TupleFieldTypes::BIGINT,
+            TupleField::SignedBigInt(_) => TupleFieldTypes::SIGNEDBIGINT,
+            TupleField::Double64(_) => TupleFieldTypes::DOUBLE64,
+            TupleField::Decimal(d) => TupleFieldTypes::DECIMAL {
+                precision: d.digits().max(d.scale),
+                scale: d.scale,
+            },
+            TupleField::Date(_) => TupleFieldTypes::DATE,
+            TupleField::Timestamp(_) => TupleFieldTypes::TIMESTAMP,
+            TupleField::Varchar(s) => TupleFieldTypes::VARCHAR(s.chars().count() as u32),
+            TupleField::Blob(_) => TupleFieldTypes::BLOB,
+            TupleField::Null => return None,
+        };
+        Some(field_type)
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, TupleField::Null)
+    }
+}
+
+impl TupleFieldTypes {
+    // whether a value can be stored in a column of this type as is, without converting it
+    pub fn accepts(&self, field: &TupleField) -> bool {
+        match (self, field) {
+            (TupleFieldTypes::DECIMAL { precision, scale }, TupleField::Decimal(d)) => {
+                d.scale == *scale && d.digits() <= *precision
+            }
+            (TupleFieldTypes::VARCHAR(max), TupleField::Varchar(s)) => {
+                s.chars().count() <= *max as usize
+            }
+            (_, field) => field.field_type() == Some(*self),
+        }
+    }
+
+    // the same kind of type, ignoring length limits, precision and scale
+    #[allow(dead_code)]
+    pub fn same_kind(&self, other: &TupleFieldTypes) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decimal, TupleField, TupleFieldTypes};
+
+    #[test]
+    fn decimal_display() {
+        assert_eq!(Decimal::new(12345, 2).to_string(), "123.45");
+        assert_eq!(Decimal::new(-5, 3).to_string(), "-0.005");
+        assert_eq!(Decimal::new(7, 0).to_string(), "7");
+        assert_eq!(Decimal::new(0, 2).to_string(), "0.00");
+    }
+
+    #[test]
+    fn accepts_checks_limits() {
+        let decimal = TupleFieldTypes::DECIMAL {
+            precision: 5,
+            scale: 2,
+        };
+        assert!(decimal.accepts(&TupleField::Decimal(Decimal::new(99999, 2))));
+        assert!(!decimal.accepts(&TupleField::Decimal(Decimal::new(100000, 2))));
+        assert!(!decimal.accepts(&TupleField::Decimal(Decimal::new(1, 3))));
+
+        let varchar = TupleFieldTypes::VARCHAR(3);
+        assert!(varchar.accepts(&TupleField::Varchar("héé".to_string())));
+        assert!(!varchar.accepts(&TupleField::Varchar("four".to_string())));
+        assert!(!varchar.accepts(&TupleField::Blob(Vec::new())));
+    }
 }
@@ -0,0 +1,361 @@
+use super::encoding::get_u16;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+// slotted page layout, all fields are big endian u16s
+//
+// | slot count | free end | fragmented | slot 0 | slot 1 | ... free space ... | records |
+//
+// each slot is an (offset, length) pair pointing at its record.
+// records are packed from the end of the page towards the front,
+// and free end is the offset of the lowest record.
+// an offset of 0 marks an empty slot, since no record can live inside the header.
+// fragmented counts the bytes freed by deletes and shrinking updates,
+// which are only reclaimed when the page is compacted.
+//
+// slot numbers never change while a record is alive, compaction moves
+// the record bytes but keeps their slots, so (page, slot) can identify a record
+const HEADER_SIZE: usize = 6;
+const SLOT_SIZE: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum SlotError {
+    NoSuchSlot(u16),
+    PageFull,
+}
+
+impl fmt::Display for SlotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlotError::NoSuchSlot(slot) => write!(f, "slot {} is empty", slot),
+            SlotError::PageFull => write!(f, "not enough free space in page"),
+        }
+    }
+}
+
+impl std::error::Error for SlotError {}
+
+// a view of a page's bytes as a slotted page.
+// works over anything that derefs to bytes, ex. &[u8] for reading
+// or &mut [u8] borrowed from a PageGuard::write for updates
+pub struct SlottedPage<B> {
+    data: B,
+}
+
+fn set_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+fn slot_offset(slot: u16) -> usize {
+    HEADER_SIZE + slot as usize * SLOT_SIZE
+}
+
+impl<B: Deref<Target = [u8]>> SlottedPage<B> {
+    // interpret a page that was already set up with SlottedPage::init
+    pub fn from(data: B) -> Self {
+        SlottedPage { data }
+    }
+
+    pub fn slot_count(&self) -> u16 {
+        get_u16(&self.data, 0)
+    }
+
+    fn free_end(&self) -> usize {
+        // a freshly zeroed page has 0 here, and is an empty slotted page
+        match get_u16(&self.data, 2) {
+            0 => self.data.len(),
+            end => end as usize,
+        }
+    }
+
+    fn fragmented(&self) -> usize {
+        get_u16(&self.data, 4) as usize
+    }
+
+    fn slot(&self, slot: u16) -> Option<(usize, usize)> {
+        if slot >= self.slot_count() {
+            return None;
+        }
+        let offset = get_u16(&self.data, slot_offset(slot)) as usize;
+        let length = get_u16(&self.data, slot_offset(slot) + 2) as usize;
+        if offset == 0 {
+            None
+        } else {
+            Some((offset, length))
+        }
+    }
+
+    pub fn get(&self, slot: u16) -> Option<&[u8]> {
+        let (offset, length) = self.slot(slot)?;
+        Some(&self.data[offset..offset + length])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &[u8])> + '_ {
+        (0..self.slot_count()).filter_map(move |slot| Some((slot, self.get(slot)?)))
+    }
+
+    fn free_slot(&self) -> Option<u16> {
+        (0..self.slot_count()).find(|&slot| self.slot(slot).is_none())
+    }
+
+    // bytes between the slot directory and the records
+    fn contiguous_free(&self) -> usize {
+        self.free_end() - slot_offset(self.slot_count())
+    }
+
+    // the largest record an insert could take right now, compacting if needed
+    pub fn free_space(&self) -> usize {
+        let free = self.contiguous_free() + self.fragmented();
+        if self.free_slot().is_some() {
+            free
+        } else {
+            free.saturating_sub(SLOT_SIZE)
+        }
+    }
+}
+
+impl<B: DerefMut<Target = [u8]>> SlottedPage<B> {
+    // format the bytes as an empty slotted page
+    pub fn init(mut data: B) -> Self {
+        assert!(data.len() <= u16::MAX as usize + 1);
+        data[..HEADER_SIZE].fill(0);
+        SlottedPage { data }
+    }
+
+    fn set_slot_count(&mut self, count: u16) {
+        set_u16(&mut self.data, 0, count);
+    }
+
+    fn set_free_end(&mut self, end: usize) {
+        let stored = if end == self.data.len() {
+            0
+        } else {
+            end as u16
+        };
+        set_u16(&mut self.data, 2, stored);
+    }
+
+    fn set_fragmented(&mut self, bytes: usize) {
+        set_u16(&mut self.data, 4, bytes as u16);
+    }
+
+    fn set_slot(&mut self, slot: u16, offset: usize, length: usize) {
+        set_u16(&mut self.data, slot_offset(slot), offset as u16);
+        set_u16(&mut self.data, slot_offset(slot) + 2, length as u16);
+    }
+
+    // copy a record into contiguous free space, the caller checks it fits
+    fn place(&mut self, slot: u16, record: &[u8]) {
+        let offset = self.free_end() - record.len();
+        self.data[offset..offset + record.len()].copy_from_slice(record);
+        self.set_free_end(offset);
+        self.set_slot(slot, offset, record.len());
+    }
+
+    // returns the slot the record was stored in, or None if it doesn't fit
+    pub fn insert(&mut self, record: &[u8]) -> Option<u16> {
+        if record.len() > self.free_space() {
+            return None;
+        }
+        let free_slot = self.free_slot();
+        let needed = match free_slot {
+            Some(_) => record.len(),
+            None => record.len() + SLOT_SIZE,
+        };
+        // compact first, a new slot could otherwise grow over record bytes
+        if needed > self.contiguous_free() {
+            self.compact();
+        }
+        let slot = match free_slot {
+            Some(slot) => slot,
+            None => {
+                let slot = self.slot_count();
+                self.set_slot_count(slot + 1);
+                // the directory grew over bytes that may hold anything
+                self.set_slot(slot, 0, 0);
+                slot
+            }
+        };
+        self.place(slot, record);
+        Some(slot)
+    }
+
+    pub fn delete(&mut self, slot: u16) -> Result<(), SlotError> {
+        let (_, length) = self.slot(slot).ok_or(SlotError::NoSuchSlot(slot))?;
+        self.set_slot(slot, 0, 0);
+        self.set_fragmented(self.fragmented() + length);
+
+        // trailing empty slots can be given back to free space
+        let mut count = self.slot_count();
+        while count > 0 && self.slot(count - 1).is_none() {
+            count -= 1;
+            self.set_slot_count(count);
+        }
+        Ok(())
+    }
+
+    // replace a record, keeping its slot number
+    pub fn update(&mut self, slot: u16, record: &[u8]) -> Result<(), SlotError> {
+        let (offset, length) = self.slot(slot).ok_or(SlotError::NoSuchSlot(slot))?;
+
+        // shrinking or same size, overwrite in place
+        if record.len() <= length {
+            self.data[offset..offset + record.len()].copy_from_slice(record);
+            self.set_slot(slot, offset, record.len());
+            self.set_fragmented(self.fragmented() + length - record.len());
+            return Ok(());
+        }
+
+        // growing, the old bytes can be reused once the page is compacted
+        if record.len() > self.contiguous_free() + self.fragmented() + length {
+            return Err(SlotError::PageFull);
+        }
+        self.set_slot(slot, 0, 0);
+        self.set_fragmented(self.fragmented() + length);
+        if record.len() > self.contiguous_free() {
+            self.compact();
+        }
+        self.place(slot, record);
+        Ok(())
+    }
+
+    // pack every record against the end of the page, reclaiming fragmented space
+    pub fn compact(&mut self) {
+        let mut records: Vec<(u16, Vec<u8>)> = self
+            .iter()
+            .map(|(slot, record)| (slot, record.to_vec()))
+            .collect();
+        // keep the records in their current order, so this is stable for the caller
+        records.sort_by_key(|(slot, _)| std::cmp::Reverse(self.slot(*slot).unwrap().0));
+
+        let end = self.data.len();
+        self.set_free_end(end);
+        self.set_fragmented(0);
+        for (slot, record) in records {
+            self.place(slot, &record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SlotError, SlottedPage};
+    use crate::utils::testing::Rng;
+    use std::collections::HashMap;
+
+    #[test]
+    fn insert_and_get() {
+        let mut data = vec![0; 4096];
+        let mut page = SlottedPage::init(&mut data[..]);
+        let a = page.insert(b"hello").unwrap();
+        let b = page.insert(b"").unwrap();
+        let c = page.insert(b"world!").unwrap();
+        assert_eq!((a, b, c), (0, 1, 2));
+        assert_eq!(page.get(a), Some(&b"hello"[..]));
+        assert_eq!(page.get(b), Some(&b""[..]));
+        assert_eq!(page.get(c), Some(&b"world!"[..]));
+        assert_eq!(page.get(3), None);
+
+        let page = SlottedPage::from(&data[..]);
+        assert_eq!(page.iter().count(), 3);
+    }
+
+    #[test]
+    fn slots_are_stable() {
+        let mut data = vec![0; 4096];
+        let mut page = SlottedPage::init(&mut data[..]);
+        let slots: Vec<u16> = (0..10u8).map(|i| page.insert(&[i; 10]).unwrap()).collect();
+        page.delete(slots[3]).unwrap();
+        page.update(slots[5], &[5; 100]).unwrap();
+        page.compact();
+        for (i, &slot) in slots.iter().enumerate() {
+            match i {
+                3 => assert_eq!(page.get(slot), None),
+                5 => assert_eq!(page.get(slot), Some(&[5; 100][..])),
+                _ => assert_eq!(page.get(slot), Some(&[i as u8; 10][..])),
+            }
+        }
+        // the deleted slot gets reused
+        assert_eq!(page.insert(b"new"), Some(slots[3]));
+        assert_eq!(page.delete(42), Err(SlotError::NoSuchSlot(42)));
+    }
+
+    #[test]
+    fn fills_whole_page() {
+        for size in [4096, 32768] {
+            let mut data = vec![0; size];
+            let mut page = SlottedPage::init(&mut data[..]);
+            let mut count = 0;
+            while page.insert(&[1; 100]).is_some() {
+                count += 1;
+            }
+            assert_eq!(count, (size - 6) / 104);
+            assert!(page.free_space() < 100);
+        }
+    }
+
+    #[test]
+    fn delete_then_compact_reclaims_space() {
+        let mut data = vec![0; 4096];
+        let mut page = SlottedPage::init(&mut data[..]);
+        let big = page.insert(&[7; 4000]).unwrap();
+        assert_eq!(page.insert(&[8; 500]), None);
+        page.delete(big).unwrap();
+        // free space counts the deleted bytes, and insert compacts to use them
+        assert_eq!(page.free_space(), 4096 - 6 - 4);
+        let slot = page.insert(&[8; 4000]).unwrap();
+        assert_eq!(page.get(slot), Some(&[8; 4000][..]));
+    }
+
+    #[test]
+    fn update_grows_and_shrinks() {
+        let mut data = vec![0; 4096];
+        let mut page = SlottedPage::init(&mut data[..]);
+        let a = page.insert(&[1; 2000]).unwrap();
+        let b = page.insert(&[2; 2000]).unwrap();
+        assert_eq!(page.update(a, &[3; 2100]), Err(SlotError::PageFull));
+        assert_eq!(page.get(a), Some(&[1; 2000][..]));
+        page.update(b, &[4; 10]).unwrap();
+        // only fits once b's old bytes are compacted away
+        page.update(a, &[3; 2100]).unwrap();
+        assert_eq!(page.get(a), Some(&[3; 2100][..]));
+        assert_eq!(page.get(b), Some(&[4; 10][..]));
+    }
+
+    #[test]
+    fn random_operations_match_model() {
+        for seed in 0..50 {
+            let mut rng = Rng::new(seed);
+            let mut data = vec![0; 4096];
+            let mut page = SlottedPage::init(&mut data[..]);
+            let mut model: HashMap<u16, Vec<u8>> = HashMap::new();
+            for step in 0..500 {
+                let record = vec![step as u8; rng.below(300) as usize];
+                let live: Vec<u16> = model.keys().copied().collect();
+                match rng.below(3) {
+                    0 => {
+                        if let Some(slot) = page.insert(&record) {
+                            assert!(model.insert(slot, record).is_none());
+                        }
+                    }
+                    1 if !live.is_empty() => {
+                        let slot = live[rng.below(live.len() as u64) as usize];
+                        page.delete(slot).unwrap();
+                        model.remove(&slot);
+                    }
+                    _ if !live.is_empty() => {
+                        let slot = live[rng.below(live.len() as u64) as usize];
+                        if page.update(slot, &record).is_ok() {
+                            model.insert(slot, record);
+                        }
+                    }
+                    _ => {}
+                }
+                for (slot, record) in &model {
+                    assert_eq!(page.get(*slot), Some(&record[..]), "seed {}", seed);
+                }
+                assert_eq!(page.iter().count(), model.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+use std::ops::Deref;
+
+// up to 256 bytes of utf-8 text, kept inline with its length.
+// this is what a TEXT256 column holds, and what the btree uses for column names
+#[derive(Clone, Copy)]
+pub struct Text256 {
+    len: u16,
+    bytes: [u8; Text256::MAX_LEN],
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TextTooLong(pub usize);
+
+impl fmt::Display for TextTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "text is {} bytes, at most {} fit in a TEXT256",
+            self.0,
+            Text256::MAX_LEN
+        )
+    }
+}
+
+impl std::error::Error for TextTooLong {}
+
+impl Text256 {
+    pub const MAX_LEN: usize = 256;
+
+    pub fn new(text: &str) -> Result<Self, TextTooLong> {
+        if text.len() > Self::MAX_LEN {
+            return Err(TextTooLong(text.len()));
+        }
+        let mut bytes = [0; Self::MAX_LEN];
+        bytes[..text.len()].copy_from_slice(text.as_bytes());
+        Ok(Text256 {
+            len: text.len() as u16,
+            bytes,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only ever filled from a &str, and never cut short
+        std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap()
+    }
+}
+
+impl Default for Text256 {
+    fn default() -> Self {
+        Text256 {
+            len: 0,
+            bytes: [0; Self::MAX_LEN],
+        }
+    }
+}
+
+impl Deref for Text256 {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl TryFrom<&str> for Text256 {
+    type Error = TextTooLong;
+
+    fn try_from(text: &str) -> Result<Self, TextTooLong> {
+        Text256::new(text)
+    }
+}
+
+impl From<Text256> for String {
+    fn from(text: Text256) -> String {
+        text.as_str().to_string()
+    }
+}
+
+impl PartialEq for Text256 {
+    fn eq(&self, other: &Text256) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Text256 {}
+
+impl fmt::Debug for Text256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Text256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Text256, TextTooLong};
+
+    #[test]
+    fn limit_is_in_bytes() {
+        let full = "a".repeat(256);
+        assert_eq!(Text256::new(&full).unwrap().as_str(), full);
+        assert_eq!(Text256::new(&"a".repeat(257)), Err(TextTooLong(257)));
+        // 128 two byte chars fit, 129 don't
+        assert!(Text256::new(&"é".repeat(128)).is_ok());
+        assert_eq!(Text256::new(&"é".repeat(129)), Err(TextTooLong(258)));
+        assert_eq!(Text256::default().as_str(), "");
+    }
+
+    #[test]
+    fn stays_small() {
+        assert!(std::mem::size_of::<Text256>() <= 258);
+    }
+}
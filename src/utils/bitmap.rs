@@ -4,6 +4,7 @@ pub struct Bitmap {
 }
 
 impl Bitmap {
+    #[allow(clippy::manual_is_multiple_of)]
     pub fn with_capacity(capacity: usize) -> Self {
         assert!(capacity > 0);
         let size = {
@@ -43,12 +44,14 @@ impl Bitmap {
         (self.data[offset] & mask) == mask
     }
 
+    #[allow(dead_code)]
     pub fn len(&self) -> usize {
         self.capacity
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::unnecessary_cast)]
 mod tests {
     use super::Bitmap;
 
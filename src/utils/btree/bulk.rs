@@ -0,0 +1,239 @@
+use super::node::{
+    internal_entry_size, leaf_entry_size, separator, shared_prefix, BTreeInternal, BTreeLeaf,
+    BTreeNode, NO_PAGE,
+};
+use super::{BTree, BTreeError, Entry, KeyColumn};
+use crate::bufferpool::{Pool, ID};
+use crate::page_interpretation::encoding::encode_row;
+use crate::page_interpretation::Column;
+
+// a node that's been given a page but not written to it yet
+struct Packed {
+    page: ID,
+    // what the node's parent separates it from the one before by, no greater
+    // than the smallest key under it
+    first: Vec<u8>,
+    node: BTreeNode,
+}
+
+// fills one level of the tree from left to right. the last two nodes are held
+// back, so if the level ends with too little for the last one, the two can be
+// evened out before they're written
+struct Level<'t, 'a> {
+    tree: &'t BTree<'a>,
+    target: usize,
+    // room kept in each internal node for its high key, which isn't known until
+    // the next node starts. it's one of the separators from the level below
+    high_room: usize,
+    previous: Option<Packed>,
+    current: Option<Packed>,
+    // the first key and page of every node written, for the level above
+    written: Vec<(Vec<u8>, ID)>,
+    // every page this level still owns, to give back if the load fails
+    pages: &'t mut Vec<ID>,
+}
+
+impl<'t, 'a> Level<'t, 'a> {
+    fn new(tree: &'t BTree<'a>, target: usize, high_room: usize, pages: &'t mut Vec<ID>) -> Self {
+        Level {
+            tree,
+            target,
+            high_room,
+            previous: None,
+            current: None,
+            written: Vec::new(),
+            pages,
+        }
+    }
+
+    fn push_record(&mut self, key: Vec<u8>, record: Vec<u8>) -> Result<(), BTreeError> {
+        // the key only takes up what it doesn't share with the last one
+        let last = match &self.current {
+            Some(Packed {
+                node: BTreeNode::Leaf(leaf),
+                ..
+            }) => leaf.keys.last(),
+            _ => None,
+        };
+        let size =
+            leaf_entry_size(&key, &record) - last.map_or(0, |last| shared_prefix(last, &key));
+        // a leaf's high key is the separator after its last key, which is at
+        // most one byte longer than it
+        if !self.has_room(size + key.len() + 1) {
+            let first = last.map_or_else(|| key.clone(), |last| separator(last, &key));
+            self.start_node(first, || BTreeNode::Leaf(BTreeLeaf::new()))?;
+        }
+        let Some(BTreeNode::Leaf(leaf)) = self.current.as_mut().map(|c| &mut c.node) else {
+            unreachable!("records go in leaves");
+        };
+        leaf.keys.push(key);
+        leaf.records.push(record);
+        Ok(())
+    }
+
+    fn push_child(&mut self, key: Vec<u8>, child: ID) -> Result<(), BTreeError> {
+        if !self.has_room(internal_entry_size(&key) + self.high_room) {
+            self.start_node(key.clone(), || BTreeNode::Internal(BTreeInternal::new()))?;
+        }
+        let Some(BTreeNode::Internal(internal)) = self.current.as_mut().map(|c| &mut c.node) else {
+            unreachable!("children go in internal nodes");
+        };
+        // the first child's key is the node's own, it goes in the parent instead
+        if !internal.children.is_empty() {
+            internal.keys.push(key);
+        }
+        internal.children.push(child);
+        Ok(())
+    }
+
+    // whether the current node can take this many more bytes without passing the target
+    fn has_room(&self, size: usize) -> bool {
+        self.current
+            .as_ref()
+            .is_some_and(|current| current.node.size() + size <= self.target)
+    }
+
+    fn start_node(
+        &mut self,
+        first: Vec<u8>,
+        empty: impl FnOnce() -> BTreeNode,
+    ) -> Result<(), BTreeError> {
+        // nothing can reach the page until the load is done, so it stays blank until then
+        let (page, _) = self.tree.pool.new_page().ok_or(BTreeError::OutOfPages)?;
+        self.pages.push(page);
+        if let Some(previous) = self.previous.take() {
+            let current = self.current.as_ref().unwrap();
+            let next = (current.page, current.first.clone());
+            self.write(previous, Some(next));
+        }
+        self.previous = self.current.replace(Packed {
+            page,
+            first,
+            node: empty(),
+        });
+        Ok(())
+    }
+
+    // write the node, pointing it at the next one on the level and taking
+    // that one's separator as its high key, or None if it's the last
+    fn write(&mut self, mut packed: Packed, next: Option<(ID, Vec<u8>)>) {
+        let (next, high) = next.unzip();
+        packed.node.set_next(next.unwrap_or(NO_PAGE));
+        match &mut packed.node {
+            BTreeNode::Leaf(leaf) => leaf.high = high,
+            BTreeNode::Internal(internal) => internal.high = high,
+        }
+        packed
+            .node
+            .write_to(&mut self.tree.pool.get_page(packed.page).write());
+        self.written.push((packed.first, packed.page));
+    }
+
+    // write out the last two nodes, returning every node of the level in order
+    fn finish(mut self) -> Vec<(Vec<u8>, ID)> {
+        match (self.previous.take(), self.current.take()) {
+            (Some(mut previous), Some(last)) if last.node.size() < self.tree.min_node_size() => {
+                previous.node.merge(last.first, last.node);
+                if previous.node.size() <= self.tree.pool.page_size() {
+                    self.tree.free(last.page);
+                    self.pages.retain(|&page| page != last.page);
+                    self.write(previous, None);
+                } else {
+                    let (first, node) = previous.node.split();
+                    self.write(previous, Some((last.page, first.clone())));
+                    let last = Packed {
+                        page: last.page,
+                        first,
+                        node,
+                    };
+                    self.write(last, None);
+                }
+            }
+            (Some(previous), Some(last)) => {
+                let next = (last.page, last.first.clone());
+                self.write(previous, Some(next));
+                self.write(last, None);
+            }
+            (None, Some(last)) => self.write(last, None),
+            _ => {}
+        }
+        self.written
+    }
+}
+
+impl<'a> BTree<'a> {
+    // build a tree bottom up from entries sorted by strictly increasing key,
+    // which is much quicker than inserting them one by one. nodes are filled
+    // to fill of a page, from 0.5 to 1.0, leaving the rest for later inserts.
+    // if the load fails, every page it took is given back
+    pub fn bulk_load<I>(
+        pool: &'a Pool,
+        key: Vec<KeyColumn>,
+        schema: Vec<Column>,
+        entries: I,
+        fill: f64,
+    ) -> Result<Self, BTreeError>
+    where
+        I: IntoIterator<Item = Entry>,
+    {
+        assert!(
+            (0.5..=1.0).contains(&fill),
+            "fill factor {} isn't between 0.5 and 1.0",
+            fill
+        );
+        let mut tree = BTree::open(pool, NO_PAGE, key, schema);
+        let target = (fill * pool.page_size() as f64) as usize;
+        let mut pages = Vec::new();
+        match tree.load(entries, target, &mut pages) {
+            Ok(Some(root)) => {
+                tree.root = root;
+                Ok(tree)
+            }
+            Ok(None) => BTree::create(pool, tree.key, tree.schema).ok_or(BTreeError::OutOfPages),
+            Err(e) => {
+                for page in pages {
+                    tree.free(page);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    // the root's page, or None if there were no entries
+    fn load<I>(
+        &self,
+        entries: I,
+        target: usize,
+        pages: &mut Vec<ID>,
+    ) -> Result<Option<ID>, BTreeError>
+    where
+        I: IntoIterator<Item = Entry>,
+    {
+        let mut leaves = Level::new(self, target, 0, pages);
+        let mut last: Option<Vec<u8>> = None;
+        for (key, record) in entries {
+            let key = self.encode_key(&key)?;
+            let record = encode_row(&record, &self.schema)?;
+            let size = leaf_entry_size(&key, &record);
+            if size > self.max_entry_size() {
+                return Err(BTreeError::TooLarge(size));
+            }
+            if last.as_ref().is_some_and(|last| *last >= key) {
+                return Err(BTreeError::Unsorted);
+            }
+            last = Some(key.clone());
+            leaves.push_record(key, record)?;
+        }
+
+        let mut level = leaves.finish();
+        while level.len() > 1 {
+            let high_room = level.iter().map(|(first, _)| first.len()).max().unwrap();
+            let mut parents = Level::new(self, target, high_room, pages);
+            for (key, child) in level {
+                parents.push_child(key, child)?;
+            }
+            level = parents.finish();
+        }
+        Ok(level.pop().map(|(_, root)| root))
+    }
+}
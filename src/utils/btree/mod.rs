@@ -0,0 +1,1310 @@
+mod bulk;
+mod node;
+mod range;
+pub mod secondary;
+mod verify;
+
+pub use self::range::Range;
+
+use self::node::{leaf_entry_size, BTreeInternal, BTreeLeaf, BTreeNode};
+use crate::bufferpool::{Page, Pool, ReadLatch, WriteLatch, ID};
+use crate::page_interpretation::encoding::{decode_row, encode_row, EncodingError};
+use crate::page_interpretation::keys::{
+    check_key_field, decode_descending_key, decode_key, encode_descending_key, encode_key,
+};
+use crate::page_interpretation::{Column, TupleField};
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+// a b+ tree from unique keys to records, one node per page (see node.rs).
+// keys can be made of several columns, each sorted either way, and are
+// encoded back to back into one memcomparable key.
+//
+// the root never moves, so a tree is found by its root page. when the root
+// splits both halves move out to new pages and the root becomes their parent,
+// and when it's left with a single child that child moves up into it.
+//
+// writers crab down the tree, latching a child before letting go of its parent.
+// readers only ever hold one latch, and follow the right links every node has
+// to catch up with splits their parent doesn't know about yet, see docs/btree.md
+pub struct BTree<'a> {
+    pool: &'a Pool,
+    root: ID,
+    key: Vec<KeyColumn>,
+    schema: Vec<Column>,
+    // bumped by every split, merge and evening out, once the nodes involved are
+    // latched. a range that sees it unchanged knows the leaf it's moving to is
+    // still the one after the leaf it came from. it lives in memory, so a tree
+    // should only be open once at a time
+    version: AtomicU64,
+    // bumped by every merge and evening out, and when the root swallows its only
+    // child. those can move keys left, or free pages, where a reader moving
+    // right can't find them, so readers that see it change start over
+    merges: AtomicU64,
+}
+
+// a key and its record
+pub type Entry = (Vec<TupleField>, Vec<TupleField>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Ascending,
+    // NULLs sort last in a descending column
+    Descending,
+}
+
+// one column of a tree's key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyColumn {
+    pub column: Column,
+    pub order: Order,
+}
+
+impl KeyColumn {
+    pub fn asc(column: Column) -> Self {
+        KeyColumn {
+            column,
+            order: Order::Ascending,
+        }
+    }
+
+    pub fn desc(column: Column) -> Self {
+        KeyColumn {
+            column,
+            order: Order::Descending,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BTreeError {
+    // the key or record doesn't match the tree's schema
+    Encoding(EncodingError),
+    // the key and record together need this many bytes, more than max_entry_size
+    TooLarge(usize),
+    OutOfPages,
+    // bulk loaded keys have to be strictly increasing
+    Unsorted,
+}
+
+impl fmt::Display for BTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BTreeError::Encoding(e) => write!(f, "{}", e),
+            BTreeError::TooLarge(size) => {
+                write!(f, "b+ tree entry of {} bytes is too large", size)
+            }
+            BTreeError::OutOfPages => write!(f, "ran out of pages"),
+            BTreeError::Unsorted => write!(f, "bulk loaded keys aren't sorted"),
+        }
+    }
+}
+
+impl std::error::Error for BTreeError {}
+
+impl From<EncodingError> for BTreeError {
+    fn from(e: EncodingError) -> Self {
+        BTreeError::Encoding(e)
+    }
+}
+
+// what changing a node means for its parent
+#[derive(Default)]
+struct Change {
+    // the node split, and this key and page are its new sibling to the right
+    split: Option<(Vec<u8>, ID)>,
+    // the node is less than a quarter full, so it should merge with a sibling
+    underfull: bool,
+}
+
+// a write latched node on the way down to a leaf, and which child we went to
+struct Step<'a> {
+    page: ID,
+    latch: WriteLatch<'a>,
+    node: BTreeNode,
+    child: usize,
+}
+
+impl<'a> BTree<'a> {
+    // None if we ran out of pages
+    pub fn create(pool: &'a Pool, key: Vec<KeyColumn>, schema: Vec<Column>) -> Option<Self> {
+        let (root, guard) = pool.new_page()?;
+        BTreeNode::Leaf(BTreeLeaf::new()).write_to(&mut guard.write());
+        drop(guard);
+        Some(BTree::open(pool, root, key, schema))
+    }
+
+    pub fn open(pool: &'a Pool, root: ID, key: Vec<KeyColumn>, schema: Vec<Column>) -> Self {
+        BTree {
+            pool,
+            root,
+            key,
+            schema,
+            version: AtomicU64::new(0),
+            merges: AtomicU64::new(0),
+        }
+    }
+
+    // the page that identifies this tree, pass it to open later
+    pub fn root_page(&self) -> ID {
+        self.root
+    }
+
+    // a node always has room for a few entries, so splitting one
+    // leaves both halves with something in them
+    pub fn max_entry_size(&self) -> usize {
+        self.pool.page_size() / 4
+    }
+
+    // nodes other than the root hold at least this many bytes
+    fn min_node_size(&self) -> usize {
+        self.pool.page_size() / 4
+    }
+
+    fn encode_key(&self, key: &[TupleField]) -> Result<Vec<u8>, EncodingError> {
+        if key.len() != self.key.len() {
+            return Err(EncodingError::ColumnCount {
+                expected: self.key.len(),
+                found: key.len(),
+            });
+        }
+        self.encode_prefix(key)
+    }
+
+    // the leading columns of a key. its encoding is a prefix of the
+    // encoding of every key that starts with the same fields
+    fn encode_prefix(&self, prefix: &[TupleField]) -> Result<Vec<u8>, EncodingError> {
+        if prefix.len() > self.key.len() {
+            return Err(EncodingError::ColumnCount {
+                expected: self.key.len(),
+                found: prefix.len(),
+            });
+        }
+        let mut bytes = Vec::new();
+        for (i, (field, key)) in prefix.iter().zip(&self.key).enumerate() {
+            check_key_field(i, field, &key.column)?;
+            match key.order {
+                Order::Ascending => encode_key(field, &mut bytes),
+                Order::Descending => encode_descending_key(field, &mut bytes),
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn decode_key(&self, mut bytes: &[u8]) -> Vec<TupleField> {
+        self.key
+            .iter()
+            .map(|key| {
+                let field_type = key.column.field_type;
+                match key.order {
+                    Order::Ascending => decode_key(field_type, &mut bytes),
+                    Order::Descending => decode_descending_key(field_type, &mut bytes),
+                }
+                .expect("b+ tree key is corrupt")
+            })
+            .collect()
+    }
+
+    fn decode_record(&self, bytes: &[u8]) -> Vec<TupleField> {
+        decode_row(bytes, &self.schema).expect("b+ tree record is corrupt")
+    }
+
+    fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    // the nodes about to change shape must already be latched
+    fn bump_version(&self) {
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn merges(&self) -> u64 {
+        self.merges.load(Ordering::SeqCst)
+    }
+
+    // like bump_version, before anything moves left or is freed
+    fn bump_merges(&self) {
+        self.merges.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn read_latch(&self, page: ID) -> ReadLatch<'a> {
+        self.pool.get_page(page).read_latch()
+    }
+
+    fn write_latch(&self, page: ID) -> WriteLatch<'a> {
+        self.pool.get_page(page).write_latch()
+    }
+
+    // new pages can't be reached until their parent points at them, so they don't need a latch
+    fn allocate(&self, node: &BTreeNode) -> Result<ID, BTreeError> {
+        let (page, guard) = self.pool.new_page().ok_or(BTreeError::OutOfPages)?;
+        node.write_to(&mut guard.write());
+        Ok(page)
+    }
+
+    // a reader lets go of a page's latch a moment before its pin,
+    // so a page we just unlinked may still be pinned for a bit
+    fn free(&self, page: ID) {
+        while !self.pool.try_delete_page(page) {
+            thread::yield_now();
+        }
+    }
+
+    pub fn search(&self, key: &[TupleField]) -> Option<Vec<TupleField>> {
+        let key = self.encode_key(key).ok()?;
+        let (leaf, _, _) = self.seek(|k| k <= key.as_slice());
+        let at = leaf.find(&key).ok()?;
+        Some(self.decode_record(&leaf.records[at]))
+    }
+
+    // crab down with read latches like a search, but write latch the leaf
+    fn latch_leaf(&self, key: &[u8]) -> (ID, WriteLatch<'a>, BTreeLeaf) {
+        let mut parent = None;
+        let mut page = self.root;
+        loop {
+            let latch = self.read_latch(page);
+            if let BTreeNode::Internal(internal) = BTreeNode::from_bytes(&latch) {
+                page = internal.children[internal.child_index(key)];
+                parent = Some(latch);
+                continue;
+            }
+            // the parent's read latch keeps the leaf from splitting or
+            // merging while we trade our read latch for a write latch
+            drop(latch);
+            let latch = self.write_latch(page);
+            match BTreeNode::from_bytes(&latch) {
+                BTreeNode::Leaf(leaf) => return (page, latch, leaf),
+                // only a root without a parent could have split in the meantime
+                BTreeNode::Internal(_) => {
+                    debug_assert!(parent.is_none());
+                    page = self.root;
+                }
+            }
+        }
+    }
+
+    // write latch every node down to the key's leaf, letting go of everything
+    // above a safe node, since the change won't reach past it
+    fn latch_path(&self, key: &[u8], safe: impl Fn(&BTreeNode, bool) -> bool) -> Vec<Step<'a>> {
+        let mut path: Vec<Step> = Vec::new();
+        let mut page = self.root;
+        loop {
+            let latch = self.write_latch(page);
+            let node = BTreeNode::from_bytes(&latch);
+            if safe(&node, page == self.root) {
+                path.clear();
+            }
+            let child = match &node {
+                BTreeNode::Internal(internal) => Some(internal.child_index(key)),
+                BTreeNode::Leaf(_) => None,
+            };
+            let next = match (&node, child) {
+                (BTreeNode::Internal(internal), Some(child)) => Some(internal.children[child]),
+                _ => None,
+            };
+            path.push(Step {
+                page,
+                latch,
+                node,
+                child: child.unwrap_or(0),
+            });
+            match next {
+                Some(next) => page = next,
+                None => return path,
+            }
+        }
+    }
+
+    // an insert below can add at most one entry to this node
+    fn safe_for_insert(&self, node: &BTreeNode) -> bool {
+        node.size() + self.max_entry_size() <= self.pool.page_size()
+    }
+
+    // a delete below can take an entry out of this node, or swap one of its
+    // keys for a bigger one when children are evened out
+    fn safe_for_delete(&self, node: &BTreeNode, is_root: bool) -> bool {
+        let size = node.size();
+        match node {
+            BTreeNode::Leaf(_) => is_root || size >= self.min_node_size() + self.max_entry_size(),
+            BTreeNode::Internal(internal) => {
+                let can_shrink = match is_root {
+                    true => internal.keys.len() > 1,
+                    false => size >= self.min_node_size() + self.max_entry_size(),
+                };
+                can_shrink && size + self.max_entry_size() <= self.pool.page_size()
+            }
+        }
+    }
+
+    // false if the key is already in the tree, which leaves its record alone
+    pub fn insert(&self, key: &[TupleField], record: &[TupleField]) -> Result<bool, BTreeError> {
+        let key = self.encode_key(key)?;
+        let record = encode_row(record, &self.schema)?;
+        let size = leaf_entry_size(&key, &record);
+        if size > self.max_entry_size() {
+            return Err(BTreeError::TooLarge(size));
+        }
+        match self.optimistic_insert(&key, &record) {
+            Some(inserted) => Ok(inserted),
+            None => self.pessimistic_insert(key, record),
+        }
+    }
+
+    // None if the leaf would split, which takes a pessimistic insert
+    fn optimistic_insert(&self, key: &[u8], record: &[u8]) -> Option<bool> {
+        let (_, mut latch, mut leaf) = self.latch_leaf(key);
+        let at = match leaf.find(key) {
+            Ok(_) => return Some(false),
+            Err(at) => at,
+        };
+        // how much the key takes up depends on its neighbours, so try it and see
+        leaf.keys.insert(at, key.to_vec());
+        leaf.records.insert(at, record.to_vec());
+        if leaf.size() > self.pool.page_size() {
+            return None;
+        }
+        BTreeNode::Leaf(leaf).write_to(&mut latch);
+        Some(true)
+    }
+
+    fn pessimistic_insert(&self, key: Vec<u8>, record: Vec<u8>) -> Result<bool, BTreeError> {
+        let mut path = self.latch_path(&key, |node, _| self.safe_for_insert(node));
+        let mut step = path.pop().unwrap();
+        let BTreeNode::Leaf(leaf) = &mut step.node else {
+            unreachable!("paths end at a leaf");
+        };
+        match leaf.find(&key) {
+            Ok(_) => return Ok(false),
+            Err(at) => {
+                leaf.keys.insert(at, key);
+                leaf.records.insert(at, record);
+            }
+        }
+
+        let mut change = self.finish(step.page, &mut step.latch, step.node)?;
+        while let Some((separator, right)) = change.split {
+            let mut step = path.pop().expect("a node that was safe split");
+            let BTreeNode::Internal(internal) = &mut step.node else {
+                unreachable!("leaves have no children");
+            };
+            internal.keys.insert(step.child, separator);
+            internal.children.insert(step.child + 1, right);
+            change = self.finish(step.page, &mut step.latch, step.node)?;
+        }
+        Ok(true)
+    }
+
+    // false if the key wasn't in the tree. deletes only need new pages when
+    // rebalancing grows a parent enough to split it
+    pub fn delete(&self, key: &[TupleField]) -> Result<bool, BTreeError> {
+        let key = self.encode_key(key)?;
+        match self.optimistic_delete(&key) {
+            Some(deleted) => Ok(deleted),
+            None => self.pessimistic_delete(&key),
+        }
+    }
+
+    // None if the leaf would need to merge, which takes a pessimistic delete
+    fn optimistic_delete(&self, key: &[u8]) -> Option<bool> {
+        let (page, mut latch, mut leaf) = self.latch_leaf(key);
+        let at = match leaf.find(key) {
+            Ok(at) => at,
+            Err(_) => return Some(false),
+        };
+        leaf.keys.remove(at);
+        leaf.records.remove(at);
+        if page != self.root && leaf.size() < self.min_node_size() {
+            return None;
+        }
+        BTreeNode::Leaf(leaf).write_to(&mut latch);
+        Some(true)
+    }
+
+    fn pessimistic_delete(&self, key: &[u8]) -> Result<bool, BTreeError> {
+        let mut path = self.latch_path(key, |node, is_root| self.safe_for_delete(node, is_root));
+        let mut step = path.pop().unwrap();
+        let BTreeNode::Leaf(leaf) = &mut step.node else {
+            unreachable!("paths end at a leaf");
+        };
+        match leaf.find(key) {
+            Ok(at) => {
+                leaf.keys.remove(at);
+                leaf.records.remove(at);
+            }
+            Err(_) => return Ok(false),
+        }
+
+        let mut change = self.finish(step.page, &mut step.latch, step.node)?;
+        drop(step.latch);
+        while change.split.is_some() || change.underfull {
+            let mut step = path.pop().expect("a node that was safe changed shape");
+            let BTreeNode::Internal(internal) = &mut step.node else {
+                unreachable!("leaves have no children");
+            };
+            match change.split {
+                Some((separator, right)) => {
+                    internal.keys.insert(step.child, separator);
+                    internal.children.insert(step.child + 1, right);
+                }
+                None => self.rebalance(internal, step.child),
+            }
+
+            // the root's only child takes its place
+            if step.page == self.root && internal.keys.is_empty() {
+                let child = internal.children[0];
+                let child_latch = self.write_latch(child);
+                self.bump_merges();
+                step.latch.copy_from_slice(&child_latch);
+                drop(child_latch);
+                self.free(child);
+                return Ok(true);
+            }
+            change = self.finish(step.page, &mut step.latch, step.node)?;
+        }
+        Ok(true)
+    }
+
+    // merge an underfull child with a sibling, or if the two don't fit in
+    // one page, even them out. the parent is latched, so nobody else can
+    // get to either of them
+    fn rebalance(&self, parent: &mut BTreeInternal, child: usize) {
+        let at = child.saturating_sub(1);
+        let (left_page, right_page) = (parent.children[at], parent.children[at + 1]);
+        let mut left_latch = self.write_latch(left_page);
+        let mut right_latch = self.write_latch(right_page);
+        self.bump_version();
+        self.bump_merges();
+        let mut left = BTreeNode::from_bytes(&left_latch);
+        left.merge(parent.keys.remove(at), BTreeNode::from_bytes(&right_latch));
+
+        if left.size() <= self.pool.page_size() {
+            parent.children.remove(at + 1);
+            left.write_to(&mut left_latch);
+            drop(right_latch);
+            self.free(right_page);
+        } else {
+            let (separator, right) = left.split();
+            parent.keys.insert(at, separator);
+            left.set_next(right_page);
+            left.write_to(&mut left_latch);
+            right.write_to(&mut right_latch);
+        }
+    }
+
+    // write a changed node back to its latched page, splitting it if it no longer fits
+    fn finish(
+        &self,
+        page: ID,
+        latch: &mut Page,
+        mut node: BTreeNode,
+    ) -> Result<Change, BTreeError> {
+        if node.size() > self.pool.page_size() {
+            self.bump_version();
+            let (separator, right) = node.split();
+            // the new sibling is written before anything points at it, so a
+            // reader that follows the node's next pointer always finds it
+            let right = self.allocate(&right)?;
+            node.set_next(right);
+            if page == self.root {
+                let left = self.allocate(&node)?;
+                let root = BTreeInternal {
+                    keys: vec![separator],
+                    children: vec![left, right],
+                    ..BTreeInternal::new()
+                };
+                BTreeNode::Internal(root).write_to(latch);
+                return Ok(Change::default());
+            }
+            node.write_to(latch);
+            return Ok(Change {
+                split: Some((separator, right)),
+                underfull: false,
+            });
+        }
+
+        node.write_to(latch);
+        Ok(Change {
+            split: None,
+            underfull: page != self.root && node.size() < self.min_node_size(),
+        })
+    }
+
+    // go down to a leaf holding one read latch at a time. past says whether what
+    // we're after is at or after a key, which picks the child at each internal
+    // node, and says to follow the next pointer of a node that split after we
+    // read the pointer to it, when what we're after is at or past its high key.
+    // returns a copy of the leaf, the separator to its left (None for the first
+    // leaf), and the tree's version while the leaf was latched
+    fn seek(&self, past: impl Fn(&[u8]) -> bool) -> (BTreeLeaf, Option<Vec<u8>>, u64) {
+        'restart: loop {
+            let merges = self.merges();
+            let mut fence = None;
+            let mut page = self.root;
+            loop {
+                let latch = self.read_latch(page);
+                // what we're after might have moved left, or the page might not
+                // even be part of the tree anymore
+                if self.merges() != merges {
+                    continue 'restart;
+                }
+                let node = BTreeNode::from_bytes(&latch);
+                if let Some(high) = node.high().filter(|high| past(high)) {
+                    fence = Some(high.to_vec());
+                    page = node.next();
+                    continue;
+                }
+                match node {
+                    BTreeNode::Internal(internal) => {
+                        let child = internal.keys.partition_point(|k| past(k));
+                        if child > 0 {
+                            fence = Some(internal.keys[child - 1].clone());
+                        }
+                        page = internal.children[child];
+                    }
+                    BTreeNode::Leaf(leaf) => return (leaf, fence, self.version()),
+                }
+            }
+        }
+    }
+
+    // the entries with keys in the range, in order. reverse it to go from the top,
+    // see range.rs for what happens when the tree changes while iterating.
+    // bounds can leave off trailing key columns, and sort before every key they're a prefix of
+    pub fn range<R: RangeBounds<Vec<TupleField>>>(
+        &self,
+        range: R,
+    ) -> Result<Range<'_, 'a>, BTreeError> {
+        let encode = |bound: Bound<&Vec<TupleField>>| -> Result<_, EncodingError> {
+            Ok(match bound {
+                Bound::Included(key) => Bound::Included(self.encode_prefix(key)?),
+                Bound::Excluded(key) => Bound::Excluded(self.encode_prefix(key)?),
+                Bound::Unbounded => Bound::Unbounded,
+            })
+        };
+        let lower = encode(range.start_bound())?;
+        let upper = encode(range.end_bound())?;
+        Ok(Range::new(self, lower, upper))
+    }
+
+    // the entries whose keys start with the prefix's fields, in order
+    pub fn prefix(&self, prefix: &[TupleField]) -> Result<Range<'_, 'a>, BTreeError> {
+        let lower = self.encode_prefix(prefix)?;
+        // the smallest key past everything starting with lower
+        let mut upper = lower.clone();
+        let upper = loop {
+            match upper.pop() {
+                Some(0xff) => continue,
+                Some(byte) => {
+                    upper.push(byte + 1);
+                    break Bound::Excluded(upper);
+                }
+                None => break Bound::Unbounded,
+            }
+        };
+        Ok(Range::new(self, Bound::Included(lower), upper))
+    }
+
+    // every key in the tree, in order
+    pub fn all_keys(&self) -> Vec<Vec<TupleField>> {
+        self.range(..)
+            .expect("an unbounded range has no keys to encode")
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    // how many levels the tree has, 1 when the root is a leaf
+    pub fn depth(&self) -> usize {
+        let mut depth = 1;
+        let mut latch = self.read_latch(self.root);
+        while let BTreeNode::Internal(internal) = BTreeNode::from_bytes(&latch) {
+            latch = self.read_latch(internal.children[0]);
+            depth += 1;
+        }
+        depth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::node::{leaf_entry_size, BTreeNode};
+    use super::{BTree, BTreeError, KeyColumn};
+    use crate::page_interpretation::encoding::EncodingError;
+    use crate::page_interpretation::{Column, TupleField, TupleFieldTypes};
+    use crate::utils::testing::{pool, Rng};
+    use std::cmp::Reverse;
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Instant;
+
+    fn schema() -> Vec<Column> {
+        vec![
+            Column::not_null(TupleFieldTypes::VARCHAR(2000)),
+            Column::nullable(TupleFieldTypes::INT),
+        ]
+    }
+
+    fn record(text: &str, n: u32) -> Vec<TupleField> {
+        vec![TupleField::Varchar(text.to_string()), TupleField::Int(n)]
+    }
+
+    #[test]
+    fn insert_search_delete() {
+        let pool = pool("btree_basics", 8);
+        let tree = BTree::create(
+            &pool,
+            vec![KeyColumn::asc(Column::not_null(TupleFieldTypes::INT))],
+            schema(),
+        )
+        .unwrap();
+        assert!(tree
+            .insert(&[TupleField::Int(5)], &record("five", 5))
+            .unwrap());
+        assert!(tree
+            .insert(&[TupleField::Int(1)], &record("one", 1))
+            .unwrap());
+        assert!(!tree
+            .insert(&[TupleField::Int(5)], &record("again", 0))
+            .unwrap());
+        assert_eq!(tree.search(&[TupleField::Int(5)]), Some(record("five", 5)));
+        assert_eq!(tree.search(&[TupleField::Int(2)]), None);
+        assert_eq!(
+            tree.all_keys(),
+            vec![vec![TupleField::Int(1)], vec![TupleField::Int(5)]]
+        );
+
+        assert!(tree.delete(&[TupleField::Int(1)]).unwrap());
+        assert!(!tree.delete(&[TupleField::Int(1)]).unwrap());
+        assert_eq!(tree.search(&[TupleField::Int(1)]), None);
+
+        // keys and records have to match the schema
+        assert!(matches!(
+            tree.delete(&[TupleField::Bool(true)]),
+            Err(BTreeError::Encoding(EncodingError::TypeMismatch { .. }))
+        ));
+        assert_eq!(
+            tree.delete(&[]),
+            Err(BTreeError::Encoding(EncodingError::ColumnCount {
+                expected: 1,
+                found: 0
+            }))
+        );
+        assert_eq!(
+            tree.insert(&[TupleField::Null], &record("null", 0)),
+            Err(BTreeError::Encoding(EncodingError::UnexpectedNull(0)))
+        );
+        assert!(matches!(
+            tree.insert(&[TupleField::Bool(true)], &record("bool", 0)),
+            Err(BTreeError::Encoding(EncodingError::TypeMismatch { .. }))
+        ));
+        assert_eq!(
+            tree.insert(&[TupleField::Int(9)], &record(&"x".repeat(1010), 0)),
+            Err(BTreeError::TooLarge(6 + 5 + 1 + 4 + 1010 + 4))
+        );
+    }
+
+    #[test]
+    fn root_stays_put_through_splits_and_reopening() {
+        let pool = pool("btree_root", 16);
+        // long keys that only differ at the end, so separators can't be cut
+        // short and internal nodes fill up quickly too
+        let key = vec![KeyColumn::asc(Column::not_null(TupleFieldTypes::VARCHAR(
+            300,
+        )))];
+        let long_key = |i: u32| TupleField::Varchar(format!("{}{:04}", "k".repeat(200), i));
+        let payload = "p".repeat(400);
+        let tree = BTree::create(&pool, key.clone(), schema()).unwrap();
+        let root = tree.root_page();
+        for i in 0..2000 {
+            assert!(tree
+                .insert(&[long_key(i * 7919 % 2000)], &record(&payload, i))
+                .unwrap());
+        }
+        assert!(tree.depth() >= 3);
+        assert_eq!(tree.root_page(), root);
+        tree.verify().unwrap();
+        drop(tree);
+
+        let tree = BTree::open(&pool, root, key.clone(), schema());
+        let keys = tree.all_keys();
+        assert_eq!(keys.len(), 2000);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        for i in 0..2000 {
+            assert!(tree.delete(&[long_key(i)]).unwrap());
+        }
+        // back down to just the root, as an empty leaf
+        assert_eq!(tree.depth(), 1);
+        assert_eq!(pool.used_pages(), 1);
+        assert!(tree.all_keys().is_empty());
+    }
+
+    #[test]
+    fn random_operations_match_btreemap() {
+        let pool = pool("btree_random", 8);
+        let key = vec![KeyColumn::asc(Column::nullable(TupleFieldTypes::VARCHAR(
+            200,
+        )))];
+        let tree = BTree::create(&pool, key.clone(), schema()).unwrap();
+        let mut model = BTreeMap::new();
+        let mut rng = Rng::new(41);
+        for step in 0..6000u32 {
+            // variable sized keys and records, so nodes split by bytes rather than counts
+            let key = vec![match rng.below(50) {
+                0 => TupleField::Null,
+                _ => {
+                    let n = rng.below(300);
+                    let padding = "k".repeat(rng.below(10) as usize * 15);
+                    TupleField::Varchar(format!("{:03}{}", n, padding))
+                }
+            }];
+            let text = "r".repeat(rng.below(300) as usize);
+            match rng.below(3) {
+                0 | 1 => {
+                    let inserted = tree.insert(&key, &record(&text, step)).unwrap();
+                    assert_eq!(inserted, !model.contains_key(&key));
+                    model.entry(key).or_insert_with(|| record(&text, step));
+                }
+                _ => {
+                    assert_eq!(tree.delete(&key).unwrap(), model.remove(&key).is_some());
+                }
+            }
+            if step % 500 == 0 {
+                assert_eq!(tree.all_keys(), model.keys().cloned().collect::<Vec<_>>());
+                tree.verify().unwrap();
+            }
+        }
+        assert_eq!(tree.all_keys(), model.keys().cloned().collect::<Vec<_>>());
+        for (key, record) in &model {
+            assert_eq!(tree.search(key).as_ref(), Some(record));
+        }
+
+        for key in model.keys() {
+            assert!(tree.delete(key).unwrap());
+        }
+        assert_eq!(pool.used_pages(), 1);
+    }
+
+    #[test]
+    fn bulk_loads_fill_nodes_and_keep_working() {
+        let pool = pool("btree_bulk", 32);
+        let key = vec![KeyColumn::asc(Column::not_null(TupleFieldTypes::INT))];
+        let entries = |n: u32| {
+            (0..n).map(|i| {
+                (
+                    vec![TupleField::Int(i * 3)],
+                    record(&"b".repeat(i as usize % 50), i),
+                )
+            })
+        };
+
+        let empty = BTree::bulk_load(&pool, key.clone(), schema(), entries(0), 1.0).unwrap();
+        assert!(empty.all_keys().is_empty());
+        let single = BTree::bulk_load(&pool, key.clone(), schema(), entries(1), 1.0).unwrap();
+        assert_eq!(single.search(&[TupleField::Int(0)]), Some(record("", 0)));
+
+        for (n, fill) in [(40, 0.5), (3000, 0.5), (3000, 0.7), (3000, 1.0)] {
+            let before = pool.used_pages();
+            let tree = BTree::bulk_load(&pool, key.clone(), schema(), entries(n), fill).unwrap();
+            tree.verify().unwrap();
+            assert_eq!(
+                tree.all_keys(),
+                entries(n).map(|(k, _)| k).collect::<Vec<_>>()
+            );
+            for (k, record) in entries(n).step_by(7) {
+                assert_eq!(tree.search(&k), Some(record));
+            }
+            let pages = pool.used_pages() - before;
+
+            // inserting the same entries one at a time leaves nodes around half full
+            let inserted = BTree::create(&pool, key.clone(), schema()).unwrap();
+            for (k, record) in entries(n) {
+                inserted.insert(&k, &record).unwrap();
+            }
+            let inserted_pages = pool.used_pages() - before - pages;
+            if n > 100 && fill == 1.0 {
+                assert!(
+                    pages * 3 < inserted_pages * 2,
+                    "{} vs {} pages",
+                    pages,
+                    inserted_pages
+                );
+            }
+
+            // a loaded tree is an ordinary tree afterwards
+            for i in 0..n {
+                tree.insert(&[TupleField::Int(i * 3 + 1)], &record("later", i))
+                    .unwrap();
+                if i % 2 == 0 {
+                    assert!(tree.delete(&[TupleField::Int(i * 3)]).unwrap());
+                }
+            }
+            tree.verify().unwrap();
+            assert_eq!(tree.all_keys().len() as u32, n + n / 2);
+        }
+
+        // a load that fails hands its pages back
+        let before = pool.used_pages();
+        let backwards = entries(500).chain(entries(10));
+        assert_eq!(
+            BTree::bulk_load(&pool, key.clone(), schema(), backwards, 0.8).err(),
+            Some(BTreeError::Unsorted)
+        );
+        let too_large =
+            entries(500).chain([(vec![TupleField::Int(9000)], record(&"x".repeat(1010), 0))]);
+        assert!(matches!(
+            BTree::bulk_load(&pool, key.clone(), schema(), too_large, 0.8),
+            Err(BTreeError::TooLarge(_))
+        ));
+        assert_eq!(pool.used_pages(), before);
+    }
+
+    // every separator in the tree's internal nodes, and how many leaves it has
+    fn separators(tree: &BTree) -> (Vec<Vec<u8>>, usize) {
+        let (mut separators, mut leaves) = (Vec::new(), 0);
+        let mut pages = vec![tree.root];
+        while let Some(page) = pages.pop() {
+            match BTreeNode::from_bytes(&tree.pool.get_page(page).read()) {
+                BTreeNode::Internal(internal) => {
+                    separators.extend(internal.keys);
+                    pages.extend(internal.children);
+                }
+                BTreeNode::Leaf(_) => leaves += 1,
+            }
+        }
+        (separators, leaves)
+    }
+
+    // keys like urls, which share most of their bytes with their neighbours
+    fn url(i: u32) -> Vec<TupleField> {
+        vec![TupleField::Varchar(format!(
+            "https://shop.example.com/customers/{:06}/orders/{:04}",
+            i / 16,
+            i % 16
+        ))]
+    }
+
+    fn url_key() -> Vec<KeyColumn> {
+        vec![KeyColumn::asc(Column::not_null(TupleFieldTypes::VARCHAR(
+            100,
+        )))]
+    }
+
+    #[test]
+    fn text_keys_pack_into_fewer_pages() {
+        let pool = pool("btree_compression", 32);
+        let tree = BTree::create(&pool, url_key(), schema()).unwrap();
+        let mut uncompressed = 0;
+        for i in 0..5000 {
+            let j = i * 7919 % 5000;
+            let key = url(j);
+            let record = record("", j);
+            uncompressed += leaf_entry_size(&tree.encode_key(&key).unwrap(), &[0; 10]);
+            tree.insert(&key, &record).unwrap();
+        }
+        tree.verify().unwrap();
+        assert_eq!(tree.search(&url(1234)), Some(record("", 1234)));
+
+        // even with nodes only partly full, the leaves take less room than
+        // the keys would if every one was stored whole
+        let (separators, leaves) = separators(&tree);
+        assert!(
+            leaves * pool.page_size() < uncompressed,
+            "{} leaves for {} bytes of entries",
+            leaves,
+            uncompressed
+        );
+        // separators nearly always stop at the customer number, without the
+        // order's, unless there's no new customer close enough to the middle
+        let full = tree.encode_key(&url(0)).unwrap().len();
+        let short = separators.iter().filter(|s| s.len() < full - 8).count();
+        assert!(short * 10 >= separators.len() * 9);
+    }
+
+    // how prefix compression and short separators do on text keys. run with
+    // cargo test --release text_key_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn text_key_benchmark() {
+        const KEYS: u32 = 200_000;
+        let pool = pool("btree_text_benchmark", 256);
+
+        let tree = BTree::create(&pool, url_key(), schema()).unwrap();
+        let start = Instant::now();
+        for i in 0..KEYS {
+            tree.insert(&url(i * 7919 % KEYS), &record("", i)).unwrap();
+        }
+        let insert_time = start.elapsed();
+        let start = Instant::now();
+        for i in 0..KEYS {
+            assert!(tree.search(&url(i * 104_729 % KEYS)).is_some());
+        }
+        let search_time = start.elapsed();
+
+        let loaded = BTree::bulk_load(
+            &pool,
+            url_key(),
+            schema(),
+            (0..KEYS).map(|i| (url(i), record("", i))),
+            1.0,
+        )
+        .unwrap();
+
+        let full = tree.encode_key(&url(0)).unwrap().len();
+        let whole_leaves =
+            KEYS as usize * leaf_entry_size(&vec![0; full], &[0; 10]) / pool.page_size() + 1;
+        println!(
+            "{} keys of {} bytes, {} full leaves if stored whole",
+            KEYS, full, whole_leaves
+        );
+        for (name, tree) in [("inserted", &tree), ("bulk loaded", &loaded)] {
+            let (separators, leaves) = separators(tree);
+            let average = separators.iter().map(Vec::len).sum::<usize>() as f64
+                / separators.len().max(1) as f64;
+            println!(
+                "{}: depth {}, {} leaves, {:.1} keys per leaf, {} separators of {:.1} bytes on average",
+                name,
+                tree.depth(),
+                leaves,
+                KEYS as f64 / leaves as f64,
+                separators.len(),
+                average
+            );
+        }
+        println!(
+            "inserts {:?} ({:?} each), searches {:?} ({:?} each)",
+            insert_time,
+            insert_time / KEYS,
+            search_time,
+            search_time / KEYS
+        );
+    }
+
+    #[test]
+    fn composite_keys_sort_by_column_and_direction() {
+        let pool = pool("btree_composite", 16);
+        // (tenant_id, created_at), newest first within each tenant
+        let key = vec![
+            KeyColumn::asc(Column::not_null(TupleFieldTypes::INT)),
+            KeyColumn::desc(Column::nullable(TupleFieldTypes::TIMESTAMP)),
+        ];
+        let tree = BTree::create(&pool, key, schema()).unwrap();
+        let fields = |tenant: u32, created: Option<i64>| {
+            vec![
+                TupleField::Int(tenant),
+                created.map_or(TupleField::Null, TupleField::Timestamp),
+            ]
+        };
+        let mut model = BTreeMap::new();
+        let mut rng = Rng::new(45);
+        for i in 0..3000 {
+            let tenant = rng.below(20) as u32;
+            let created = match rng.below(30) {
+                0 => None,
+                _ => Some(rng.below(1 << 40) as i64 - (1 << 39)),
+            };
+            let inserted = tree
+                .insert(&fields(tenant, created), &record("row", i))
+                .unwrap();
+            assert_eq!(
+                inserted,
+                model.insert((tenant, Reverse(created)), i).is_none()
+            );
+        }
+        tree.verify().unwrap();
+        // NULLs sort last in the descending column
+        let expected: Vec<_> = model
+            .keys()
+            .map(|&(tenant, Reverse(created))| fields(tenant, created))
+            .collect();
+        assert_eq!(tree.all_keys(), expected);
+
+        for tenant in 0..21 {
+            let rows: Vec<_> = tree
+                .prefix(&[TupleField::Int(tenant)])
+                .unwrap()
+                .map(|(k, _)| k)
+                .collect();
+            let expected: Vec<_> = expected
+                .iter()
+                .filter(|k| k[0] == TupleField::Int(tenant))
+                .cloned()
+                .collect();
+            assert_eq!(rows, expected);
+            // a whole key is a prefix too, even one that encodes to a run of 0xff
+            if let Some(last) = expected.last() {
+                assert_eq!(tree.prefix(last).unwrap().count(), 1);
+            }
+        }
+        assert_eq!(tree.prefix(&[]).unwrap().count(), model.len());
+
+        // shorter bounds sort before every key they start
+        let between: Vec<_> = tree
+            .range(vec![TupleField::Int(3)]..vec![TupleField::Int(6)])
+            .unwrap()
+            .map(|(k, _)| k)
+            .collect();
+        let expected: Vec<_> = expected
+            .iter()
+            .filter(|k| (3..6).any(|t| k[0] == TupleField::Int(t)))
+            .cloned()
+            .collect();
+        assert_eq!(between, expected);
+
+        assert_eq!(
+            tree.insert(&[TupleField::Int(1)], &record("short", 0)),
+            Err(BTreeError::Encoding(EncodingError::ColumnCount {
+                expected: 2,
+                found: 1
+            }))
+        );
+        assert_eq!(tree.search(&[TupleField::Int(1)]), None);
+        assert!(matches!(
+            tree.prefix(&[TupleField::Int(1), TupleField::Null, TupleField::Null]),
+            Err(BTreeError::Encoding(EncodingError::ColumnCount { .. }))
+        ));
+        assert!(matches!(
+            tree.prefix(&[TupleField::Int(1), TupleField::Int(1)]),
+            Err(BTreeError::Encoding(EncodingError::TypeMismatch {
+                column: 1,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn ranges_match_btreemap_in_both_directions() {
+        let pool = pool("btree_ranges", 16);
+        let tree = BTree::create(
+            &pool,
+            vec![KeyColumn::asc(Column::not_null(TupleFieldTypes::INT))],
+            schema(),
+        )
+        .unwrap();
+        let mut model = BTreeMap::new();
+        // every other key, with records big enough to spread them over plenty of leaves
+        for i in (50..1250).step_by(2) {
+            let record = record(&"r".repeat(i as usize % 90), i);
+            tree.insert(&[TupleField::Int(i)], &record).unwrap();
+            model.insert(i, record);
+        }
+        assert!(tree.depth() >= 2);
+        tree.verify().unwrap();
+
+        let mut rng = Rng::new(43);
+        let bound = |rng: &mut Rng| {
+            let key = rng.below(1300) as u32;
+            match rng.below(3) {
+                0 => Bound::Included(key),
+                1 => Bound::Excluded(key),
+                _ => Bound::Unbounded,
+            }
+        };
+        for _ in 0..300 {
+            let (lower, upper) = (bound(&mut rng), bound(&mut rng));
+            // BTreeMap panics on backwards ranges, the tree just returns nothing
+            let empty = match (lower, upper) {
+                (Bound::Included(l), Bound::Included(u)) => l > u,
+                (
+                    Bound::Included(l) | Bound::Excluded(l),
+                    Bound::Included(u) | Bound::Excluded(u),
+                ) => l >= u,
+                _ => false,
+            };
+            let expected: Vec<_> = match empty {
+                true => Vec::new(),
+                false => model
+                    .range((lower, upper))
+                    .map(|(&k, r)| (vec![TupleField::Int(k)], r.clone()))
+                    .collect(),
+            };
+            let key = |k| vec![TupleField::Int(k)];
+            let bounds = (lower.map(key), upper.map(key));
+            let forwards: Vec<_> = tree.range(bounds.clone()).unwrap().collect();
+            assert_eq!(forwards, expected);
+            let backwards: Vec<_> = tree.range(bounds.clone()).unwrap().rev().collect();
+            assert!(backwards.iter().eq(expected.iter().rev()));
+
+            // both ends at once meet in the middle without repeating anything
+            let mut range = tree.range(bounds).unwrap();
+            let (mut front, mut back) = (Vec::new(), Vec::new());
+            loop {
+                let entry = match rng.below(2) {
+                    0 => range.next().map(|e| front.push(e)),
+                    _ => range.next_back().map(|e| back.push(e)),
+                };
+                if entry.is_none() {
+                    break;
+                }
+            }
+            front.extend(back.into_iter().rev());
+            assert_eq!(front, expected);
+        }
+
+        assert!(matches!(
+            tree.range(vec![TupleField::Bool(false)]..),
+            Err(BTreeError::Encoding(EncodingError::TypeMismatch { .. }))
+        ));
+    }
+
+    #[test]
+    fn ranges_survive_splits_and_merges_underneath() {
+        const KEYS: u64 = 6000;
+
+        let pool = pool("btree_range_concurrent", 128);
+        let key = vec![KeyColumn::asc(Column::not_null(TupleFieldTypes::BIGINT))];
+        let tree = BTree::create(&pool, key.clone(), schema()).unwrap();
+        // multiples of 4 stay put, everything else comes and goes while we scan
+        for k in (0..KEYS).step_by(4) {
+            tree.insert(&[TupleField::BigInt(k)], &record("stays", 0))
+                .unwrap();
+        }
+        let stable: Vec<_> = (0..KEYS)
+            .step_by(4)
+            .map(|k| vec![TupleField::BigInt(k)])
+            .collect();
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            for t in 0..4 {
+                let (tree, done) = (&tree, &done);
+                scope.spawn(move || {
+                    let mut rng = Rng::new(t);
+                    while !done.load(Ordering::Relaxed) {
+                        let k = rng.below(KEYS) | 1;
+                        let text = "v".repeat(rng.below(150) as usize);
+                        match rng.below(2) {
+                            0 => tree.insert(&[TupleField::BigInt(k)], &record(&text, 0)),
+                            _ => tree.delete(&[TupleField::BigInt(k)]),
+                        }
+                        .unwrap();
+                    }
+                });
+            }
+            for reverse in [false, true] {
+                let (tree, stable, done) = (&tree, &stable, &done);
+                scope.spawn(move || {
+                    for _ in 0..15 {
+                        let range = tree.range(..).unwrap();
+                        let mut keys: Vec<_> = match reverse {
+                            false => range.map(|(k, _)| k).collect(),
+                            true => range.rev().map(|(k, _)| k).collect(),
+                        };
+                        if reverse {
+                            keys.reverse();
+                        }
+                        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+                        // whatever came and went, everything that was there the whole time shows up
+                        let kept: Vec<_> = keys
+                            .into_iter()
+                            .filter(|k| matches!(k[..], [TupleField::BigInt(k)] if k % 4 == 0))
+                            .collect();
+                        assert_eq!(&kept, stable);
+                    }
+                    done.store(true, Ordering::Relaxed);
+                });
+            }
+        });
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn readers_move_right_past_an_unfinished_split() {
+        let pool = pool("btree_blink", 32);
+        let key = vec![KeyColumn::asc(Column::not_null(TupleFieldTypes::INT))];
+        let tree = BTree::create(&pool, key, schema()).unwrap();
+        for i in 0..400 {
+            tree.insert(&[TupleField::Int(i)], &record(&"r".repeat(40), i))
+                .unwrap();
+        }
+        assert_eq!(tree.depth(), 2);
+
+        // split the first leaf the way a writer does, but stop before telling
+        // the parent, like a reader that gets there in between would see it
+        let BTreeNode::Internal(root) = BTreeNode::from_bytes(&pool.get_page(tree.root).read())
+        else {
+            unreachable!("the tree is two levels deep");
+        };
+        let leaf = root.children[0];
+        let guard = pool.get_page(leaf);
+        let mut node = BTreeNode::from_bytes(&guard.read());
+        let (_, right) = node.split();
+        let (BTreeNode::Leaf(left), BTreeNode::Leaf(moved)) = (&node, &right) else {
+            unreachable!();
+        };
+        let moved = left.keys.len() as u32..(left.keys.len() + moved.keys.len()) as u32;
+        node.set_next(tree.allocate(&right).unwrap());
+        node.write_to(&mut guard.write());
+        drop(guard);
+
+        // the parent still sends these keys to the left half, which sends them on
+        for i in moved {
+            assert_eq!(
+                tree.search(&[TupleField::Int(i)]),
+                Some(record(&"r".repeat(40), i))
+            );
+        }
+        let expected: Vec<_> = (0..400).map(|i| vec![TupleField::Int(i)]).collect();
+        assert_eq!(tree.all_keys(), expected);
+        let backwards: Vec<_> = tree.range(..).unwrap().rev().map(|(k, _)| k).collect();
+        assert!(backwards.iter().eq(expected.iter().rev()));
+        assert_eq!(
+            tree.verify().unwrap_err().reason,
+            "high key isn't the separator to the node's right"
+        );
+    }
+
+    #[test]
+    fn concurrent_writers_keep_the_tree_sound() {
+        const THREADS: u64 = 8;
+        const KEYS_PER_THREAD: u64 = 1500;
+
+        let pool = pool("btree_concurrent", 128);
+        let key = vec![KeyColumn::asc(Column::not_null(TupleFieldTypes::BIGINT))];
+        let tree = BTree::create(&pool, key.clone(), schema()).unwrap();
+        thread::scope(|scope| {
+            for t in 0..THREADS {
+                let tree = &tree;
+                scope.spawn(move || {
+                    let mut rng = Rng::new(t);
+                    // each thread has its own keys, interleaved with everyone else's
+                    let keys: Vec<u64> = (0..KEYS_PER_THREAD).map(|i| i * THREADS + t).collect();
+                    for &k in &keys {
+                        let text = "v".repeat(rng.below(200) as usize);
+                        assert!(tree
+                            .insert(&[TupleField::BigInt(k)], &record(&text, k as u32))
+                            .unwrap());
+                        if k % 7 == 0 {
+                            assert!(tree.search(&[TupleField::BigInt(k)]).is_some());
+                        }
+                    }
+                    for &k in keys.iter().filter(|&&k| k % 3 != 0) {
+                        assert!(tree.delete(&[TupleField::BigInt(k)]).unwrap());
+                    }
+                    for &k in &keys {
+                        assert_eq!(tree.search(&[TupleField::BigInt(k)]).is_some(), k % 3 == 0);
+                    }
+                });
+            }
+            // readers walking the whole tree while it changes underneath them
+            for _ in 0..2 {
+                let tree = &tree;
+                scope.spawn(move || {
+                    for _ in 0..20 {
+                        let keys = tree.all_keys();
+                        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+                        tree.verify().unwrap();
+                    }
+                });
+            }
+        });
+
+        tree.verify().unwrap();
+        let expected: Vec<_> = (0..THREADS * KEYS_PER_THREAD)
+            .filter(|k| k % 3 == 0)
+            .map(|k| vec![TupleField::BigInt(k)])
+            .collect();
+        assert_eq!(tree.all_keys(), expected);
+    }
+}
@@ -0,0 +1,504 @@
+use crate::bufferpool::ID;
+use crate::page_interpretation::encoding::{get_u16, get_u32};
+
+// every node starts with its kind, how many keys it holds, the next node to its
+// right on the same level, or NO_PAGE for the last one, and its high key. every
+// key under the node is below its high key, which is the same as the separator
+// to its right in its parent. the last node on each level has none, stored as
+// a length of 0, since separators are never empty:
+//
+// | kind (u8) | key count (u16) | next node (u32) | high key length (u16) | high key |
+//
+// leaves follow that with their entries, each a key and the record it maps to.
+// keys are prefix compressed, each only storing what comes after the bytes it
+// shares with the key before it:
+//
+// | shared length (u16) | suffix length (u16) | record length (u16) | suffix | record | ...
+//
+// and internal nodes with their first child, then each key and the child to its right:
+//
+// | first child (u32) | key length (u16) | key | child (u32) | ...
+//
+// the next pointers and high keys make this a b-link tree (lehman and yao), so a
+// reader who gets to a node after it split, but before its parent heard about
+// it, can tell and follow the next pointer to the half it's after
+//
+// keys are memcomparable (see page_interpretation/keys.rs), so they are ordered
+// by comparing their bytes. a key belongs under the last child whose key is <= it.
+// the keys in internal nodes are separators rather than keys in the tree, as
+// short as they can be while still falling between the two children
+pub(super) const LEAF: u8 = 1;
+pub(super) const INTERNAL: u8 = 2;
+pub(super) const HEADER_SIZE: usize = 9;
+
+pub(super) const NO_PAGE: ID = u32::MAX;
+
+const LEAF_ENTRY_OVERHEAD: usize = 6;
+const INTERNAL_ENTRY_OVERHEAD: usize = 6;
+
+// how many bytes the two keys start with in common
+pub(super) fn shared_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+// the shortest key that's greater than left and no greater than right, to
+// separate two nodes by. it's the start of right, up to and including the first
+// byte that differs from left, so it usually isn't a whole key
+pub(super) fn separator(left: &[u8], right: &[u8]) -> Vec<u8> {
+    debug_assert!(left < right);
+    right[..shared_prefix(left, right) + 1].to_vec()
+}
+
+// how much room a key and its record take up in a leaf at most, when the key
+// shares nothing with the one before it
+pub(super) fn leaf_entry_size(key: &[u8], record: &[u8]) -> usize {
+    LEAF_ENTRY_OVERHEAD + key.len() + record.len()
+}
+
+// how much room a key and the child to its right take up in an internal node
+pub(super) fn internal_entry_size(key: &[u8]) -> usize {
+    INTERNAL_ENTRY_OVERHEAD + key.len()
+}
+
+pub(super) struct BTreeLeaf {
+    pub keys: Vec<Vec<u8>>,
+    pub records: Vec<Vec<u8>>,
+    pub next: ID,
+    pub high: Option<Vec<u8>>,
+}
+
+pub(super) struct BTreeInternal {
+    pub keys: Vec<Vec<u8>>,
+    // one more than there are keys
+    pub children: Vec<ID>,
+    pub next: ID,
+    pub high: Option<Vec<u8>>,
+}
+
+pub(super) enum BTreeNode {
+    Leaf(BTreeLeaf),
+    Internal(BTreeInternal),
+}
+
+impl BTreeLeaf {
+    pub fn new() -> Self {
+        BTreeLeaf {
+            keys: Vec::new(),
+            records: Vec::new(),
+            next: NO_PAGE,
+            high: None,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        let shared: usize = self
+            .keys
+            .windows(2)
+            .map(|pair| shared_prefix(&pair[0], &pair[1]))
+            .sum();
+        HEADER_SIZE
+            + self.high.as_ref().map_or(0, Vec::len)
+            + self
+                .keys
+                .iter()
+                .zip(&self.records)
+                .map(|(key, record)| leaf_entry_size(key, record))
+                .sum::<usize>()
+            - shared
+    }
+
+    // Ok with the key's index, or Err with where it would be inserted
+    pub fn find(&self, key: &[u8]) -> Result<usize, usize> {
+        self.keys.binary_search_by(|k| k.as_slice().cmp(key))
+    }
+}
+
+impl BTreeInternal {
+    pub fn new() -> Self {
+        BTreeInternal {
+            keys: Vec::new(),
+            children: Vec::new(),
+            next: NO_PAGE,
+            high: None,
+        }
+    }
+
+    // which child the key belongs under
+    pub fn child_index(&self, key: &[u8]) -> usize {
+        self.keys.partition_point(|k| k.as_slice() <= key)
+    }
+}
+
+impl BTreeNode {
+    pub fn from_bytes(page: &[u8]) -> Self {
+        match BTreeNode::try_from_bytes(page) {
+            Ok(node) => node,
+            Err(reason) => panic!("page isn't a valid b+ tree node: {}", reason),
+        }
+    }
+
+    // like from_bytes, but reports a mangled page instead of panicking
+    pub fn try_from_bytes(page: &[u8]) -> Result<Self, &'static str> {
+        let take = |offset: &mut usize, len: usize| -> Result<&[u8], &'static str> {
+            let bytes = page
+                .get(*offset..*offset + len)
+                .ok_or("node runs past the end of its page")?;
+            *offset += len;
+            Ok(bytes)
+        };
+        let take_u16 = |offset: &mut usize| take(offset, 2).map(|b| get_u16(b, 0));
+        let take_u32 = |offset: &mut usize| take(offset, 4).map(|b| get_u32(b, 0));
+
+        let mut offset = 1;
+        let count = take_u16(&mut offset)? as usize;
+        let next = take_u32(&mut offset)?;
+        let high_len = take_u16(&mut offset)? as usize;
+        let high = (high_len > 0)
+            .then(|| take(&mut offset, high_len).map(<[u8]>::to_vec))
+            .transpose()?;
+        match page[0] {
+            LEAF => {
+                let mut leaf = BTreeLeaf {
+                    next,
+                    high,
+                    ..BTreeLeaf::new()
+                };
+                for _ in 0..count {
+                    let shared = take_u16(&mut offset)? as usize;
+                    let suffix_len = take_u16(&mut offset)? as usize;
+                    let record_len = take_u16(&mut offset)? as usize;
+                    let mut key = match leaf.keys.last() {
+                        Some(last) if shared <= last.len() => last[..shared].to_vec(),
+                        None if shared == 0 => Vec::new(),
+                        _ => return Err("key shares more than the key before it has"),
+                    };
+                    key.extend_from_slice(take(&mut offset, suffix_len)?);
+                    leaf.keys.push(key);
+                    leaf.records.push(take(&mut offset, record_len)?.to_vec());
+                }
+                Ok(BTreeNode::Leaf(leaf))
+            }
+            INTERNAL => {
+                let mut children = vec![take_u32(&mut offset)?];
+                let mut keys = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let key_len = take_u16(&mut offset)? as usize;
+                    keys.push(take(&mut offset, key_len)?.to_vec());
+                    children.push(take_u32(&mut offset)?);
+                }
+                Ok(BTreeNode::Internal(BTreeInternal {
+                    keys,
+                    children,
+                    next,
+                    high,
+                }))
+            }
+            _ => Err("page isn't a b+ tree node"),
+        }
+    }
+
+    pub fn write_to(&self, page: &mut [u8]) {
+        assert!(
+            self.size() <= page.len(),
+            "b+ tree node of {} bytes doesn't fit in a page",
+            self.size()
+        );
+        let mut out = Vec::with_capacity(self.size());
+        let (kind, count) = match self {
+            BTreeNode::Leaf(leaf) => (LEAF, leaf.keys.len()),
+            BTreeNode::Internal(internal) => (INTERNAL, internal.keys.len()),
+        };
+        out.push(kind);
+        out.extend_from_slice(&(count as u16).to_be_bytes());
+        out.extend_from_slice(&self.next().to_be_bytes());
+        let high = self.high().unwrap_or_default();
+        out.extend_from_slice(&(high.len() as u16).to_be_bytes());
+        out.extend_from_slice(high);
+        match self {
+            BTreeNode::Leaf(leaf) => {
+                let mut last: &[u8] = &[];
+                for (key, record) in leaf.keys.iter().zip(&leaf.records) {
+                    let shared = shared_prefix(last, key);
+                    out.extend_from_slice(&(shared as u16).to_be_bytes());
+                    out.extend_from_slice(&((key.len() - shared) as u16).to_be_bytes());
+                    out.extend_from_slice(&(record.len() as u16).to_be_bytes());
+                    out.extend_from_slice(&key[shared..]);
+                    out.extend_from_slice(record);
+                    last = key;
+                }
+            }
+            BTreeNode::Internal(internal) => {
+                out.extend_from_slice(&internal.children[0].to_be_bytes());
+                for (key, child) in internal.keys.iter().zip(&internal.children[1..]) {
+                    out.extend_from_slice(&(key.len() as u16).to_be_bytes());
+                    out.extend_from_slice(key);
+                    out.extend_from_slice(&child.to_be_bytes());
+                }
+            }
+        }
+        page[..out.len()].copy_from_slice(&out);
+        // leftovers from bigger versions of the node would only get in the way of compression
+        page[out.len()..].fill(0);
+    }
+
+    // how many bytes the node takes up in its page
+    pub fn size(&self) -> usize {
+        match self {
+            BTreeNode::Leaf(leaf) => leaf.size(),
+            BTreeNode::Internal(internal) => {
+                HEADER_SIZE
+                    + internal.high.as_ref().map_or(0, Vec::len)
+                    + 4
+                    + internal
+                        .keys
+                        .iter()
+                        .map(|key| internal_entry_size(key))
+                        .sum::<usize>()
+            }
+        }
+    }
+
+    pub fn next(&self) -> ID {
+        match self {
+            BTreeNode::Leaf(leaf) => leaf.next,
+            BTreeNode::Internal(internal) => internal.next,
+        }
+    }
+
+    pub fn set_next(&mut self, next: ID) {
+        match self {
+            BTreeNode::Leaf(leaf) => leaf.next = next,
+            BTreeNode::Internal(internal) => internal.next = next,
+        }
+    }
+
+    pub fn high(&self) -> Option<&[u8]> {
+        match self {
+            BTreeNode::Leaf(leaf) => leaf.high.as_deref(),
+            BTreeNode::Internal(internal) => internal.high.as_deref(),
+        }
+    }
+
+    // move the upper half of the node, by bytes, into a new right sibling.
+    // returns the key separating the two and the sibling. the sibling takes
+    // over the node's next pointer and high key, and the separator becomes the
+    // node's high key. pointing the node at its sibling is up to the caller
+    pub fn split(&mut self) -> (Vec<u8>, BTreeNode) {
+        let half = (self.size() - HEADER_SIZE) / 2;
+        match self {
+            BTreeNode::Leaf(leaf) => {
+                // split within 5% of the middle, wherever the separator comes out
+                // shortest, and closest to the middle out of those. going any
+                // further from the middle leaves noticeably emptier leaves
+                let mut best = None;
+                let mut middle = None;
+                let mut bytes = 0;
+                for at in 1..leaf.keys.len() {
+                    bytes += leaf_entry_size(&leaf.keys[at - 1], &leaf.records[at - 1]);
+                    if at > 1 {
+                        bytes -= shared_prefix(&leaf.keys[at - 2], &leaf.keys[at - 1]);
+                    }
+                    if bytes >= half && middle.is_none() {
+                        middle = Some(at);
+                    }
+                    if bytes * 20 >= half * 19 && bytes * 20 <= half * 21 {
+                        let shared = shared_prefix(&leaf.keys[at - 1], &leaf.keys[at]);
+                        let candidate = (shared, bytes.abs_diff(half), at);
+                        best = Some(best.map_or(candidate, |best: (_, _, _)| best.min(candidate)));
+                    }
+                }
+                let at = match best {
+                    Some((_, _, at)) => at,
+                    None => middle.unwrap_or(leaf.keys.len() - 1).max(1),
+                };
+                let right = BTreeLeaf {
+                    keys: leaf.keys.split_off(at),
+                    records: leaf.records.split_off(at),
+                    next: leaf.next,
+                    high: leaf.high.take(),
+                };
+                let separator = separator(leaf.keys.last().unwrap(), &right.keys[0]);
+                leaf.high = Some(separator.clone());
+                (separator, BTreeNode::Leaf(right))
+            }
+            BTreeNode::Internal(internal) => {
+                // the middle key moves up to the parent instead of into either half
+                let mut at = 0;
+                let mut bytes = 0;
+                while at < internal.keys.len() - 1 && bytes < half {
+                    bytes += internal_entry_size(&internal.keys[at]);
+                    at += 1;
+                }
+                let mut keys = internal.keys.split_off(at);
+                let separator = keys.remove(0);
+                let right = BTreeInternal {
+                    keys,
+                    children: internal.children.split_off(at + 1),
+                    next: internal.next,
+                    high: internal.high.take(),
+                };
+                internal.high = Some(separator.clone());
+                (separator, BTreeNode::Internal(right))
+            }
+        }
+    }
+
+    // absorb the node's right sibling, along with its next pointer and high key.
+    // internal nodes pull the key that separated them down from the parent
+    pub fn merge(&mut self, separator: Vec<u8>, right: BTreeNode) {
+        match (self, right) {
+            (BTreeNode::Leaf(leaf), BTreeNode::Leaf(right)) => {
+                leaf.keys.extend(right.keys);
+                leaf.records.extend(right.records);
+                leaf.next = right.next;
+                leaf.high = right.high;
+            }
+            (BTreeNode::Internal(internal), BTreeNode::Internal(right)) => {
+                internal.keys.push(separator);
+                internal.keys.extend(right.keys);
+                internal.children.extend(right.children);
+                internal.next = right.next;
+                internal.high = right.high;
+            }
+            _ => panic!("merging b+ tree nodes from different levels"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{separator, BTreeInternal, BTreeLeaf, BTreeNode};
+
+    fn leaf(keys: &[&[u8]]) -> BTreeNode {
+        BTreeNode::Leaf(BTreeLeaf {
+            keys: keys.iter().map(|k| k.to_vec()).collect(),
+            records: keys.iter().map(|k| k.repeat(2)).collect(),
+            next: 77,
+            high: None,
+        })
+    }
+
+    #[test]
+    fn nodes_round_trip_through_pages() {
+        let mut page = vec![0xaa; 256];
+        let node = leaf(&[b"a", b"bb", b"ccc"]);
+        node.write_to(&mut page);
+        assert_eq!(node.size(), 9 + 3 * 6 + 6 + 12);
+        assert!(page[node.size()..].iter().all(|&b| b == 0));
+        match BTreeNode::from_bytes(&page) {
+            BTreeNode::Leaf(leaf) => {
+                assert_eq!(
+                    leaf.keys,
+                    vec![b"a".to_vec(), b"bb".to_vec(), b"ccc".to_vec()]
+                );
+                assert_eq!(leaf.records[2], b"cccccc");
+                assert_eq!(leaf.next, 77);
+                assert_eq!(leaf.high, None);
+            }
+            BTreeNode::Internal(_) => panic!("leaf came back as an internal node"),
+        }
+
+        let node = BTreeNode::Internal(BTreeInternal {
+            keys: vec![b"m".to_vec(), b"t".to_vec()],
+            children: vec![1, 2, 3],
+            next: 4,
+            high: Some(b"x".to_vec()),
+        });
+        node.write_to(&mut page);
+        assert_eq!(node.size(), 9 + 1 + 4 + 2 * 7);
+        match BTreeNode::from_bytes(&page) {
+            BTreeNode::Internal(internal) => {
+                assert_eq!(internal.children, vec![1, 2, 3]);
+                assert_eq!(
+                    (internal.next, internal.high.as_deref()),
+                    (4, Some(&b"x"[..]))
+                );
+                assert_eq!(internal.child_index(b"a"), 0);
+                assert_eq!(internal.child_index(b"m"), 1);
+                assert_eq!(internal.child_index(b"z"), 2);
+            }
+            BTreeNode::Leaf(_) => panic!("internal node came back as a leaf"),
+        }
+    }
+
+    #[test]
+    fn leaf_keys_only_store_what_they_dont_share() {
+        let mut page = vec![0; 256];
+        let node = leaf(&[b"apple", b"applesauce", b"apricot", b"b"]);
+        node.write_to(&mut page);
+        // "applesauce" shares 5 bytes with "apple", "apricot" 2 with "applesauce"
+        assert_eq!(
+            node.size(),
+            9 + 4 * 6 + (5 + 5 + 5 + 1) + (10 + 20 + 14 + 2)
+        );
+        match BTreeNode::from_bytes(&page) {
+            BTreeNode::Leaf(leaf) => {
+                assert_eq!(leaf.keys[1], b"applesauce");
+                assert_eq!(leaf.keys[2], b"apricot");
+                assert_eq!(leaf.records[2], b"apricotapricot");
+            }
+            BTreeNode::Internal(_) => unreachable!(),
+        }
+
+        // the first key can't share anything, there's nothing before it
+        page[9] = 1;
+        assert_eq!(
+            BTreeNode::try_from_bytes(&page).err(),
+            Some("key shares more than the key before it has")
+        );
+
+        assert_eq!(separator(b"apple", b"apricot"), b"apr");
+        assert_eq!(separator(b"app", b"apple"), b"appl");
+        assert_eq!(separator(b"a", b"b"), b"b");
+    }
+
+    #[test]
+    fn split_and_merge_are_inverses() {
+        let keys: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 1 + i as usize]).collect();
+        let mut node = leaf(&keys.iter().map(|k| k.as_slice()).collect::<Vec<_>>());
+        let size = node.size();
+        let (separator, right) = node.split();
+        // halves by bytes, so the left half has more of the smaller keys
+        assert!(node.size() * 2 >= size - 3 - 40 && right.size() * 2 >= size - 3 - 40);
+        match (&node, &right) {
+            (BTreeNode::Leaf(left), BTreeNode::Leaf(right)) => {
+                // the keys are runs of one byte, so the first byte tells them apart
+                assert_eq!(separator, right.keys[0][..1]);
+                assert!(left.keys.last().unwrap() < &separator && separator <= right.keys[0]);
+                assert_eq!(left.high.as_ref(), Some(&separator));
+                assert_eq!((right.next, &right.high), (77, &None));
+            }
+            _ => unreachable!(),
+        }
+        node.merge(separator, right);
+        assert_eq!(node.size(), size);
+
+        let mut node = BTreeNode::Internal(BTreeInternal {
+            keys: keys.clone(),
+            children: (0..11).collect(),
+            next: 12,
+            high: Some(vec![200]),
+        });
+        let (separator, right) = node.split();
+        match (&node, &right) {
+            (BTreeNode::Internal(left), BTreeNode::Internal(right)) => {
+                assert_eq!(left.high.as_ref(), Some(&separator));
+                assert_eq!((right.next, &right.high), (12, &Some(vec![200])));
+                assert_eq!(left.children.len(), left.keys.len() + 1);
+                assert_eq!(right.children.len(), right.keys.len() + 1);
+                assert_eq!(left.keys.len() + right.keys.len() + 1, 10);
+                assert!(left.keys.last().unwrap() < &separator && separator < right.keys[0]);
+            }
+            _ => unreachable!(),
+        }
+        node.merge(separator, right);
+        match node {
+            BTreeNode::Internal(internal) => {
+                assert_eq!(internal.keys, keys);
+                assert_eq!(internal.children, (0..11).collect::<Vec<_>>());
+                assert_eq!((internal.next, internal.high), (12, Some(vec![200])));
+            }
+            BTreeNode::Leaf(_) => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use super::node::{BTreeNode, NO_PAGE};
+use super::{BTree, Entry};
+use crate::bufferpool::ID;
+use std::collections::VecDeque;
+use std::ops::Bound;
+
+// where the front of a range finds its next leaf
+enum Link {
+    // crab down from the root to the leaf holding the lower bound
+    Seek,
+    // follow the last leaf's next pointer, as long as the tree's version
+    // is still the one we saw it at
+    Next(ID, u64),
+}
+
+// the entries of a b+ tree between two keys, in order from either end.
+//
+// one leaf at a time is copied out and unlatched, so nothing stays pinned
+// between calls. going forwards follows the leaves' next pointers, unless
+// the tree split or merged anything since the pointer was read, in which case
+// it seeks down from the root again. going backwards always seeks, for the
+// last leaf with keys below the lowest one we've seen.
+//
+// entries inserted or deleted while iterating may or may not show up,
+// but every entry is returned at most once, in order
+pub struct Range<'t, 'a> {
+    tree: &'t BTree<'a>,
+    // everything left to return lies between these, they close in
+    // as entries are returned from either end
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    front: VecDeque<(Vec<u8>, Vec<u8>)>,
+    // None once the front has reached the upper bound
+    front_leaf: Option<Link>,
+    back: Vec<(Vec<u8>, Vec<u8>)>,
+    // the next leaf for the back holds keys in this bound, None once it has reached the lower bound
+    back_leaf: Option<Bound<Vec<u8>>>,
+}
+
+fn above(lower: &Bound<Vec<u8>>, key: &[u8]) -> bool {
+    match lower {
+        Bound::Included(lower) => key >= lower.as_slice(),
+        Bound::Excluded(lower) => key > lower.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+
+fn below(upper: &Bound<Vec<u8>>, key: &[u8]) -> bool {
+    match upper {
+        Bound::Included(upper) => key <= upper.as_slice(),
+        Bound::Excluded(upper) => key < upper.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+
+impl<'t, 'a> Range<'t, 'a> {
+    pub(super) fn new(tree: &'t BTree<'a>, lower: Bound<Vec<u8>>, upper: Bound<Vec<u8>>) -> Self {
+        Range {
+            tree,
+            back_leaf: Some(upper.clone()),
+            lower,
+            upper,
+            front: VecDeque::new(),
+            front_leaf: Some(Link::Seek),
+            back: Vec::new(),
+        }
+    }
+
+    fn fill_front(&mut self) {
+        while self.front.is_empty() {
+            let (leaf, version) = match self.front_leaf.take() {
+                None => return,
+                Some(Link::Seek) => {
+                    let lower = &self.lower;
+                    let (leaf, _, version) = self.tree.seek(|k| match lower {
+                        Bound::Included(key) | Bound::Excluded(key) => k <= key.as_slice(),
+                        Bound::Unbounded => false,
+                    });
+                    (leaf, version)
+                }
+                Some(Link::Next(page, version)) => {
+                    let latch = self.tree.read_latch(page);
+                    // something split or merged since we read the pointer, so the
+                    // page might not follow our last leaf anymore, or even be a leaf
+                    if self.tree.version() != version {
+                        self.front_leaf = Some(Link::Seek);
+                        continue;
+                    }
+                    let BTreeNode::Leaf(leaf) = BTreeNode::from_bytes(&latch) else {
+                        unreachable!("leaves only point at leaves");
+                    };
+                    (leaf, version)
+                }
+            };
+
+            self.front_leaf = (leaf.next != NO_PAGE).then_some(Link::Next(leaf.next, version));
+            for (key, record) in leaf.keys.into_iter().zip(leaf.records) {
+                if !below(&self.upper, &key) {
+                    self.front_leaf = None;
+                    break;
+                }
+                if above(&self.lower, &key) {
+                    self.front.push_back((key, record));
+                }
+            }
+        }
+    }
+
+    fn fill_back(&mut self) {
+        while self.back.is_empty() {
+            let Some(upper) = self.back_leaf.take() else {
+                return;
+            };
+            let (leaf, fence, _) = self.tree.seek(|k| match &upper {
+                Bound::Included(key) => k <= key.as_slice(),
+                Bound::Excluded(key) => k < key.as_slice(),
+                Bound::Unbounded => true,
+            });
+
+            // anything before this leaf comes before its fence
+            self.back_leaf = fence.map(Bound::Excluded);
+            for (key, record) in leaf.keys.into_iter().zip(leaf.records) {
+                if !above(&self.lower, &key) {
+                    self.back_leaf = None;
+                } else if below(&self.upper, &key) {
+                    self.back.push((key, record));
+                }
+            }
+        }
+    }
+
+    fn entry(&self, key: &[u8], record: &[u8]) -> Entry {
+        (self.tree.decode_key(key), self.tree.decode_record(record))
+    }
+}
+
+impl Iterator for Range<'_, '_> {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.fill_front();
+        let (key, record) = self.front.pop_front()?;
+        // the back already returned it
+        if !below(&self.upper, &key) {
+            self.front.clear();
+            self.front_leaf = None;
+            return None;
+        }
+        let entry = self.entry(&key, &record);
+        self.lower = Bound::Excluded(key);
+        Some(entry)
+    }
+}
+
+impl DoubleEndedIterator for Range<'_, '_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.fill_back();
+        let (key, record) = self.back.pop()?;
+        // the front already returned it
+        if !above(&self.lower, &key) {
+            self.back.clear();
+            self.back_leaf = None;
+            return None;
+        }
+        let entry = self.entry(&key, &record);
+        self.upper = Bound::Excluded(key);
+        Some(entry)
+    }
+}
@@ -0,0 +1,245 @@
+use super::{BTree, BTreeError, Entry, KeyColumn};
+use crate::bufferpool::{Pool, ID};
+use crate::page_interpretation::encoding::EncodingError;
+use crate::page_interpretation::{Column, TupleField, TupleFieldTypes};
+use crate::utils::heap::{HeapFile, RecordId};
+
+// an unclustered index, where many rows can share a key. each entry is the
+// indexed fields followed by the fields that locate the row, the table's primary
+// key or the row's rid, all in one b+ tree key with an empty record. that makes
+// every entry unique, and all the entries for a key sit next to each other,
+// ordered by where their rows are
+pub struct SecondaryIndex<'a> {
+    tree: BTree<'a>,
+    // how many of the tree's key columns are indexed, the rest locate the row
+    key_len: usize,
+}
+
+// locator columns for rows in a heap file, see rid_fields
+pub fn rid_columns() -> Vec<Column> {
+    vec![
+        Column::not_null(TupleFieldTypes::INT),
+        Column::not_null(TupleFieldTypes::INT),
+    ]
+}
+
+pub fn rid_fields(rid: RecordId) -> Vec<TupleField> {
+    vec![TupleField::Int(rid.page), TupleField::Int(rid.slot as u32)]
+}
+
+fn rid_from_fields(fields: &[TupleField]) -> RecordId {
+    match fields {
+        [TupleField::Int(page), TupleField::Int(slot)] => RecordId {
+            page: *page,
+            slot: *slot as u16,
+        },
+        _ => panic!("secondary index locator isn't a rid"),
+    }
+}
+
+impl<'a> SecondaryIndex<'a> {
+    // None if we ran out of pages
+    pub fn create(pool: &'a Pool, key: Vec<KeyColumn>, locator: Vec<Column>) -> Option<Self> {
+        let key_len = key.len();
+        let tree = BTree::create(pool, Self::tree_key(key, locator), Vec::new())?;
+        Some(SecondaryIndex { tree, key_len })
+    }
+
+    pub fn open(pool: &'a Pool, root: ID, key: Vec<KeyColumn>, locator: Vec<Column>) -> Self {
+        let key_len = key.len();
+        let tree = BTree::open(pool, root, Self::tree_key(key, locator), Vec::new());
+        SecondaryIndex { tree, key_len }
+    }
+
+    fn tree_key(mut key: Vec<KeyColumn>, locator: Vec<Column>) -> Vec<KeyColumn> {
+        key.extend(locator.into_iter().map(KeyColumn::asc));
+        key
+    }
+
+    pub fn root_page(&self) -> ID {
+        self.tree.root_page()
+    }
+
+    // false if this exact key and locator are already indexed
+    pub fn insert(&self, key: &[TupleField], locator: &[TupleField]) -> Result<bool, BTreeError> {
+        self.tree.insert(&self.entry(key, locator)?, &[])
+    }
+
+    // false if this key and locator weren't indexed
+    pub fn delete(&self, key: &[TupleField], locator: &[TupleField]) -> Result<bool, BTreeError> {
+        self.tree.delete(&self.entry(key, locator)?)
+    }
+
+    fn entry(
+        &self,
+        key: &[TupleField],
+        locator: &[TupleField],
+    ) -> Result<Vec<TupleField>, EncodingError> {
+        if key.len() != self.key_len {
+            return Err(EncodingError::ColumnCount {
+                expected: self.key_len,
+                found: key.len(),
+            });
+        }
+        Ok(key.iter().chain(locator).cloned().collect())
+    }
+
+    // the locators of every row with the key, in order. the key can leave
+    // off trailing columns to find every row starting with the ones given
+    pub fn lookup(&self, key: &[TupleField]) -> Result<Vec<Vec<TupleField>>, BTreeError> {
+        if key.len() > self.key_len {
+            return Err(EncodingError::ColumnCount {
+                expected: self.key_len,
+                found: key.len(),
+            }
+            .into());
+        }
+        Ok(self
+            .tree
+            .prefix(key)?
+            .map(|(mut entry, _)| entry.split_off(self.key_len))
+            .collect())
+    }
+
+    // look the key up, then each row in the table's clustered tree, returning
+    // their primary keys and records. entries for rows that are gone are
+    // skipped, like when the index is updated after the table
+    pub fn fetch(&self, key: &[TupleField], clustered: &BTree) -> Result<Vec<Entry>, BTreeError> {
+        Ok(self
+            .lookup(key)?
+            .into_iter()
+            .filter_map(|primary| {
+                let record = clustered.search(&primary)?;
+                Some((primary, record))
+            })
+            .collect())
+    }
+
+    // like fetch, for an index of rows in a heap file, with rid_columns as its locator
+    pub fn fetch_from_heap(
+        &self,
+        key: &[TupleField],
+        heap: &HeapFile,
+    ) -> Result<Vec<(RecordId, Vec<u8>)>, BTreeError> {
+        Ok(self
+            .lookup(key)?
+            .iter()
+            .map(|locator| rid_from_fields(locator))
+            .filter_map(|rid| Some((rid, heap.get(rid)?)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rid_columns, rid_fields, SecondaryIndex};
+    use crate::page_interpretation::encoding::EncodingError;
+    use crate::page_interpretation::{Column, TupleField, TupleFieldTypes};
+    use crate::utils::btree::{BTree, BTreeError, KeyColumn};
+    use crate::utils::heap::HeapFile;
+    use crate::utils::testing::{pool, Rng};
+    use std::collections::BTreeSet;
+
+    fn city(name: &str) -> Vec<TupleField> {
+        vec![TupleField::Varchar(name.to_string())]
+    }
+
+    #[test]
+    fn duplicate_keys_drill_into_the_clustered_tree() {
+        let pool = pool("secondary_clustered", 32);
+        let id = Column::not_null(TupleFieldTypes::BIGINT);
+        let city_column = Column::nullable(TupleFieldTypes::VARCHAR(64));
+        // people by id, and an index on their city
+        let people = BTree::create(&pool, vec![KeyColumn::asc(id)], vec![city_column]).unwrap();
+        let by_city =
+            SecondaryIndex::create(&pool, vec![KeyColumn::asc(city_column)], vec![id]).unwrap();
+
+        let cities = ["lisbon", "oslo", "quito", "lima"];
+        let mut model = BTreeSet::new();
+        let mut rng = Rng::new(46);
+        for person in 0..2000u64 {
+            let name = cities[rng.below(cities.len() as u64) as usize];
+            people
+                .insert(&[TupleField::BigInt(person)], &city(name))
+                .unwrap();
+            assert!(by_city
+                .insert(&city(name), &[TupleField::BigInt(person)])
+                .unwrap());
+            model.insert((name, person));
+        }
+        // the same row can't be indexed under the same key twice
+        let &(name, person) = model.first().unwrap();
+        assert!(!by_city
+            .insert(&city(name), &[TupleField::BigInt(person)])
+            .unwrap());
+
+        for name in cities {
+            let expected: Vec<_> = model
+                .iter()
+                .filter(|(c, _)| *c == name)
+                .map(|&(_, p)| vec![TupleField::BigInt(p)])
+                .collect();
+            assert!(expected.len() > 100);
+            assert_eq!(by_city.lookup(&city(name)).unwrap(), expected);
+
+            let rows = by_city.fetch(&city(name), &people).unwrap();
+            assert_eq!(rows.len(), expected.len());
+            assert!(rows.iter().all(|(_, record)| record == &city(name)));
+        }
+        assert!(by_city.lookup(&city("paris")).unwrap().is_empty());
+        // no columns at all is a prefix of every key
+        assert_eq!(by_city.lookup(&[]).unwrap().len(), model.len());
+
+        // moving someone is a delete and an insert, and rows deleted from the
+        // table before the index are skipped
+        let (name, person) = *model.iter().find(|(c, _)| *c == "lima").unwrap();
+        assert!(by_city
+            .delete(&city(name), &[TupleField::BigInt(person)])
+            .unwrap());
+        assert!(!by_city
+            .delete(&city(name), &[TupleField::BigInt(person)])
+            .unwrap());
+        by_city
+            .insert(&city("paris"), &[TupleField::BigInt(person)])
+            .unwrap();
+        people.delete(&[TupleField::BigInt(person)]).unwrap();
+        assert_eq!(by_city.lookup(&city("paris")).unwrap().len(), 1);
+        assert!(by_city.fetch(&city("paris"), &people).unwrap().is_empty());
+
+        assert!(matches!(
+            by_city.lookup(&[TupleField::Null, TupleField::BigInt(1)]),
+            Err(BTreeError::Encoding(EncodingError::ColumnCount { .. }))
+        ));
+        assert!(matches!(
+            by_city.insert(&[], &[TupleField::BigInt(1)]),
+            Err(BTreeError::Encoding(EncodingError::ColumnCount { .. }))
+        ));
+    }
+
+    #[test]
+    fn heap_rows_are_found_by_rid() {
+        let pool = pool("secondary_heap", 32);
+        let heap = HeapFile::create(&pool).unwrap();
+        let parity = Column::not_null(TupleFieldTypes::BOOL);
+        let index =
+            SecondaryIndex::create(&pool, vec![KeyColumn::asc(parity)], rid_columns()).unwrap();
+        let mut evens = Vec::new();
+        for i in 0..500u32 {
+            let record = i.to_be_bytes().repeat(10);
+            let rid = heap.insert(&record).unwrap();
+            index
+                .insert(&[TupleField::Bool(i % 2 == 0)], &rid_fields(rid))
+                .unwrap();
+            if i % 2 == 0 {
+                evens.push((rid, record));
+            }
+        }
+        evens.sort();
+        assert_eq!(
+            index
+                .fetch_from_heap(&[TupleField::Bool(true)], &heap)
+                .unwrap(),
+            evens
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use super::node::{BTreeNode, NO_PAGE};
+use super::{BTree, Order};
+use crate::bufferpool::{ReadLatch, ID};
+use crate::disk::Corruption;
+use crate::page_interpretation::keys::{decode_descending_key, decode_key};
+use crate::page_interpretation::{TupleField, TupleFieldTypes};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+// what verify has seen so far
+#[derive(Default)]
+struct Walk {
+    // each node's page and where it points, for every level from the root down
+    levels: Vec<Vec<(ID, ID)>>,
+    // how deep the first leaf was, every other one has to match it
+    leaf_depth: Option<usize>,
+    // a corrupt child pointer can lead back up the tree
+    visited: HashSet<ID>,
+}
+
+fn corrupt(page_id: ID, reason: &'static str) -> Corruption {
+    Corruption { page_id, reason }
+}
+
+// characters that mean something in a graphviz record label
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' | '\\' | '{' | '}' | '|' | '<' | '>' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn label(field: &TupleField) -> String {
+    match field.cast(TupleFieldTypes::VARCHAR(u32::MAX)) {
+        Ok(TupleField::Varchar(text)) => text,
+        Ok(TupleField::Null) => "NULL".to_string(),
+        _ => format!("{:?}", field),
+    }
+}
+
+impl BTree<'_> {
+    // walk the whole tree, checking that every node parses, fits its page and
+    // is at least a quarter full unless it's the root, that keys are in order,
+    // decode, and fall within the range their parent gives them, that each
+    // node's high key is the separator to its right, that every leaf is at the
+    // same depth, and that each node points at the next one on its level.
+    // children that aren't in the file, fail their checksum, or lead back to a
+    // page already walked are corruption too
+    //
+    // latches are held from the root down to wherever the walk is, so writers
+    // can keep working elsewhere in the tree
+    pub fn verify(&self) -> Result<(), Corruption> {
+        let mut walk = Walk::default();
+        self.verify_node(self.root, (None, None), 0, &mut walk)?;
+
+        for level in walk.levels {
+            for pair in level.windows(2) {
+                if pair[0].1 != pair[1].0 {
+                    return Err(corrupt(
+                        pair[0].0,
+                        "node doesn't point at the node after it on its level",
+                    ));
+                }
+            }
+            let &(last, next) = level.last().unwrap();
+            if next != NO_PAGE {
+                return Err(corrupt(
+                    last,
+                    "last node on its level points at another page",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_node(
+        &self,
+        page: ID,
+        bounds: (Option<&[u8]>, Option<&[u8]>),
+        depth: usize,
+        walk: &mut Walk,
+    ) -> Result<(), Corruption> {
+        if !walk.visited.insert(page) {
+            return Err(corrupt(page, "page is in the tree more than once"));
+        }
+        if walk.leaf_depth.is_some_and(|leaf_depth| depth > leaf_depth) {
+            return Err(corrupt(page, "node is deeper than the first leaf"));
+        }
+        let latch = self.try_read_latch(page)?;
+        let node = BTreeNode::try_from_bytes(&latch).map_err(|reason| corrupt(page, reason))?;
+        if page != self.root && node.size() < self.min_node_size() {
+            return Err(corrupt(page, "node is less than a quarter full"));
+        }
+        let keys = match &node {
+            BTreeNode::Leaf(leaf) => &leaf.keys,
+            BTreeNode::Internal(internal) => &internal.keys,
+        };
+        if !keys.windows(2).all(|pair| pair[0] < pair[1]) {
+            return Err(corrupt(page, "keys are out of order"));
+        }
+        let (first, last) = (keys.first(), keys.last());
+        if matches!((bounds.0, first), (Some(lower), Some(first)) if first.as_slice() < lower)
+            || matches!((bounds.1, last), (Some(upper), Some(last)) if last.as_slice() >= upper)
+        {
+            return Err(corrupt(
+                page,
+                "key is outside the range its parent gives it",
+            ));
+        }
+        if node.high() != bounds.1 {
+            return Err(corrupt(
+                page,
+                "high key isn't the separator to the node's right",
+            ));
+        }
+        if walk.levels.len() == depth {
+            walk.levels.push(Vec::new());
+        }
+        walk.levels[depth].push((page, node.next()));
+        match &node {
+            BTreeNode::Leaf(leaf) => {
+                // separators are cut short, so only whole keys have to decode
+                if leaf.keys.iter().any(|key| !self.key_decodes(key)) {
+                    return Err(corrupt(page, "key doesn't match the tree's key columns"));
+                }
+                if *walk.leaf_depth.get_or_insert(depth) != depth {
+                    return Err(corrupt(
+                        page,
+                        "leaf is at a different depth than the first leaf",
+                    ));
+                }
+            }
+            BTreeNode::Internal(internal) => {
+                if internal.keys.is_empty() {
+                    return Err(corrupt(page, "internal node has no keys"));
+                }
+                for (i, &child) in internal.children.iter().enumerate() {
+                    let lower = match i {
+                        0 => bounds.0,
+                        _ => Some(&internal.keys[i - 1][..]),
+                    };
+                    let upper = internal.keys.get(i).map(|k| &k[..]).or(bounds.1);
+                    self.verify_node(child, (lower, upper), depth + 1, walk)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // whether the bytes are exactly one key of the tree's columns
+    fn key_decodes(&self, bytes: &[u8]) -> bool {
+        let (fields, rest) = self.decode_leading(bytes);
+        fields.len() == self.key.len() && rest.is_empty()
+    }
+
+    // as many of the key's columns as decode, and whatever's left after them
+    fn decode_leading<'b>(&self, mut bytes: &'b [u8]) -> (Vec<TupleField>, &'b [u8]) {
+        let mut fields = Vec::new();
+        for key in &self.key {
+            let mut rest = bytes;
+            let field_type = key.column.field_type;
+            let field = match key.order {
+                Order::Ascending => decode_key(field_type, &mut rest),
+                Order::Descending => decode_descending_key(field_type, &mut rest),
+            };
+            match field {
+                Ok(field) => fields.push(field),
+                Err(_) => break,
+            }
+            bytes = rest;
+        }
+        (fields, bytes)
+    }
+
+    // unlike read_latch, a page that's missing or fails its checksum is an error
+    fn try_read_latch(&self, page: ID) -> Result<ReadLatch<'_>, Corruption> {
+        Ok(self.pool.try_get_page(page)?.read_latch())
+    }
+
+    // the tree as a graphviz digraph, one record per node page ending in its
+    // high key, with edges to each child and dashed edges to the next node over.
+    // stops at the first node that can't be read
+    pub fn dump(&self) -> Result<String, Corruption> {
+        let mut out = String::from("digraph btree {\n    node [shape=record];\n");
+        self.dump_node(self.root, &mut HashSet::new(), &mut out)?;
+        out.push_str("}\n");
+        Ok(out)
+    }
+
+    fn dump_node(
+        &self,
+        page: ID,
+        visited: &mut HashSet<ID>,
+        out: &mut String,
+    ) -> Result<(), Corruption> {
+        if !visited.insert(page) {
+            return Err(corrupt(page, "page is in the tree more than once"));
+        }
+        let latch = self.try_read_latch(page)?;
+        // a separator can stop partway through a column, which shows as ...
+        let key = |bytes: &[u8]| {
+            let (fields, rest) = self.decode_leading(bytes);
+            let mut labels: Vec<_> = fields.iter().map(label).collect();
+            if !rest.is_empty() {
+                labels.push("...".to_string());
+            }
+            escape(&labels.join(", "))
+        };
+        let node = BTreeNode::try_from_bytes(&latch).map_err(|reason| corrupt(page, reason))?;
+        let mut fields = format!("page {}", page);
+        match &node {
+            BTreeNode::Internal(internal) => {
+                fields.push_str("|<c0>");
+                for (i, k) in internal.keys.iter().enumerate() {
+                    write!(fields, "|{}|<c{}>", key(k), i + 1).unwrap();
+                }
+            }
+            BTreeNode::Leaf(leaf) => {
+                for k in &leaf.keys {
+                    write!(fields, "|{}", key(k)).unwrap();
+                }
+            }
+        }
+        if let Some(high) = node.high() {
+            write!(fields, "|high {}", key(high)).unwrap();
+        }
+        writeln!(out, "    page{} [label=\"{}\"];", page, fields).unwrap();
+        if node.next() != NO_PAGE {
+            writeln!(
+                out,
+                "    page{} -> page{} [style=dashed, constraint=false];",
+                page,
+                node.next()
+            )
+            .unwrap();
+        }
+
+        if let BTreeNode::Internal(internal) = node {
+            for (i, &child) in internal.children.iter().enumerate() {
+                writeln!(out, "    page{}:c{} -> page{};", page, i, child).unwrap();
+            }
+            for child in internal.children {
+                self.dump_node(child, visited, out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::node::{BTreeNode, LEAF};
+    use super::super::{BTree, KeyColumn};
+    use crate::bufferpool::Pool;
+    use crate::page_interpretation::{Column, TupleField, TupleFieldTypes};
+    use crate::utils::testing::pool;
+
+    fn tree(pool: &Pool) -> BTree<'_> {
+        let key = vec![
+            KeyColumn::asc(Column::not_null(TupleFieldTypes::INT)),
+            KeyColumn::desc(Column::nullable(TupleFieldTypes::VARCHAR(100))),
+        ];
+        let schema = vec![Column::not_null(TupleFieldTypes::VARCHAR(400))];
+        let tree = BTree::create(pool, key, schema).unwrap();
+        for i in 0..300 {
+            let key = [TupleField::Int(i), TupleField::Varchar(format!("k{}", i))];
+            tree.insert(&key, &[TupleField::Varchar("r".repeat(200))])
+                .unwrap();
+        }
+        tree
+    }
+
+    // the page of the first leaf
+    fn first_leaf(tree: &BTree) -> u32 {
+        let mut page = tree.root;
+        while let BTreeNode::Internal(internal) =
+            BTreeNode::from_bytes(&tree.pool.get_page(page).read())
+        {
+            page = internal.children[0];
+        }
+        page
+    }
+
+    fn mangle(tree: &BTree, page: u32, change: impl FnOnce(&mut BTreeNode)) {
+        let guard = tree.pool.get_page(page);
+        let mut bytes = guard.write();
+        let mut node = BTreeNode::from_bytes(&bytes);
+        change(&mut node);
+        node.write_to(&mut bytes);
+    }
+
+    #[test]
+    fn verify_finds_broken_trees() {
+        let pool = pool("btree_verify", 32);
+        let tree = tree(&pool);
+        assert!(tree.depth() >= 2);
+        tree.verify().unwrap();
+        let leaf = first_leaf(&tree);
+
+        let check = |reason: &str| {
+            let err = tree.verify().unwrap_err();
+            assert_eq!(err.reason, reason);
+            assert_eq!(err.page_id, leaf);
+        };
+
+        mangle(&tree, leaf, |node| match node {
+            BTreeNode::Leaf(leaf) => leaf.keys.swap(0, 1),
+            BTreeNode::Internal(_) => unreachable!(),
+        });
+        check("keys are out of order");
+        mangle(&tree, leaf, |node| match node {
+            BTreeNode::Leaf(leaf) => leaf.keys.swap(0, 1),
+            BTreeNode::Internal(_) => unreachable!(),
+        });
+
+        let next = mangle_next(&tree, leaf, 0);
+        check("node doesn't point at the node after it on its level");
+        mangle_next(&tree, leaf, next);
+
+        let mut high = None;
+        mangle(&tree, leaf, |node| match node {
+            BTreeNode::Leaf(leaf) => high = leaf.high.replace(vec![0xff]),
+            BTreeNode::Internal(_) => unreachable!(),
+        });
+        check("high key isn't the separator to the node's right");
+        mangle(&tree, leaf, |node| match node {
+            BTreeNode::Leaf(leaf) => leaf.high = high,
+            BTreeNode::Internal(_) => unreachable!(),
+        });
+        tree.verify().unwrap();
+
+        mangle(&tree, leaf, |node| match node {
+            BTreeNode::Leaf(leaf) => leaf.keys[0].push(0),
+            BTreeNode::Internal(_) => unreachable!(),
+        });
+        check("key doesn't match the tree's key columns");
+        mangle(&tree, leaf, |node| match node {
+            BTreeNode::Leaf(leaf) => {
+                leaf.keys[0].pop();
+            }
+            BTreeNode::Internal(_) => unreachable!(),
+        });
+
+        mangle(&tree, leaf, |node| match node {
+            BTreeNode::Leaf(leaf) => {
+                leaf.keys.truncate(1);
+                leaf.records.truncate(1);
+            }
+            BTreeNode::Internal(_) => unreachable!(),
+        });
+        check("node is less than a quarter full");
+
+        tree.pool.get_page(leaf).write()[0] = LEAF + 7;
+        check("page isn't a b+ tree node");
+    }
+
+    #[test]
+    fn bad_child_pointers_are_corruption() {
+        let pool = pool("btree_verify_children", 32);
+        let tree = tree(&pool);
+        let root = tree.root;
+        let point_first_child = |child: u32| {
+            let mut old = 0;
+            mangle(&tree, root, |node| match node {
+                BTreeNode::Internal(internal) => {
+                    old = std::mem::replace(&mut internal.children[0], child)
+                }
+                BTreeNode::Leaf(_) => unreachable!(),
+            });
+            old
+        };
+
+        // a loop back up the tree would recurse forever
+        let first = point_first_child(root);
+        let err = tree.verify().unwrap_err();
+        assert_eq!(
+            (err.page_id, err.reason),
+            (root, "page is in the tree more than once")
+        );
+        assert_eq!(tree.dump().unwrap_err(), err);
+
+        // reading past the end of the file would panic
+        point_first_child(1_000_000);
+        let err = tree.verify().unwrap_err();
+        assert_eq!(
+            (err.page_id, err.reason),
+            (1_000_000, "page is past the end of the file")
+        );
+        assert_eq!(tree.dump().unwrap_err(), err);
+
+        point_first_child(first);
+        tree.verify().unwrap();
+    }
+
+    // point the leaf somewhere else, returning where it pointed
+    fn mangle_next(tree: &BTree, page: u32, next: u32) -> u32 {
+        let mut old = 0;
+        mangle(tree, page, |node| match node {
+            BTreeNode::Leaf(leaf) => old = std::mem::replace(&mut leaf.next, next),
+            BTreeNode::Internal(_) => unreachable!(),
+        });
+        old
+    }
+
+    #[test]
+    fn dump_draws_every_node() {
+        let pool = pool("btree_dump", 32);
+        let tree = tree(&pool);
+        let dot = tree.dump().unwrap();
+        assert!(dot.starts_with("digraph btree {"));
+        assert!(dot.trim_end().ends_with('}'));
+        // a key's columns are joined into one field of the record
+        assert!(dot.contains("|0, k0|1, k1|"));
+        let links = dot.matches("style=dashed").count();
+        let edges = dot.matches(":c").count();
+        // every node but the last on its level points at the next, and every
+        // page but the root has a parent
+        assert_eq!(edges, dot.matches("[label=").count() - 1);
+        assert_eq!(links, edges + 1 - tree.depth());
+        assert!(dot.contains("|high "));
+        assert!(dot.contains(&format!(
+            "page{} [label=\"page {}|<c0>|",
+            tree.root, tree.root
+        )));
+    }
+}
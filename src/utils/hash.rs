@@ -0,0 +1,795 @@
+use crate::bufferpool::{Pool, ReadLatch, WriteLatch, ID};
+use crate::page_interpretation::encoding::{
+    decode_row, encode_row, get_u16, get_u32, EncodingError,
+};
+use crate::page_interpretation::keys::{check_key_field, encode_key};
+use crate::page_interpretation::{Column, TupleField};
+use std::fmt;
+use std::thread;
+
+// an extendible hash table from unique keys to records, for indexes that only
+// ever look keys up by equality. see docs/hash.md
+//
+// it's found by its header page, which splits the hash space between up to
+// 2^header depth directories by the top bits of a key's hash:
+//
+// | kind (u8) | header depth (u8) | max directory depth (u8) | directory page (u32) ... |
+//
+// a directory nothing has hashed to yet is NO_PAGE, and is only made on the
+// first insert that needs it. a directory picks a bucket by the low global
+// depth bits of the hash, and remembers how many of those bits each bucket
+// really goes by, its local depth:
+//
+// | kind (u8) | global depth (u8) | local depth (u8) ... | bucket page (u32) ... |
+//
+// with room for 2^max directory depth of each, as many as fit in a page. buckets are a list of entries:
+//
+// | kind (u8) | count (u16) | (key len u16, record len u16, key, record) ... |
+//
+// lookups read latch the directory, then the bucket, and let go of the
+// directory once they have the bucket. inserts and deletes write latch just the
+// bucket, and only write latch the directory when the bucket has to split or
+// merge, since that's the only time the directory changes
+pub struct HashIndex<'a> {
+    pool: &'a Pool,
+    header: ID,
+    // read from the header page, so they stay what the index was made with
+    header_depth: u8,
+    max_depth: u8,
+    key: Vec<Column>,
+    schema: Vec<Column>,
+}
+
+// after the b+ tree's node kinds, so one can't be mistaken for the other
+const HEADER_PAGE: u8 = 3;
+const DIRECTORY_PAGE: u8 = 4;
+const BUCKET_PAGE: u8 = 5;
+
+const NO_PAGE: ID = u32::MAX;
+
+// the header depth new indexes get. it fits the smallest page size, and a
+// bigger one would spread small indexes over more buckets than they need
+const HEADER_DEPTH: u8 = 6;
+
+const HEADER_SIZE: usize = 3;
+const DIRECTORY_HEADER: usize = 2;
+const BUCKET_HEADER: usize = 3;
+const BUCKET_ENTRY_OVERHEAD: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum HashError {
+    // the key or record doesn't match the index's schema
+    Encoding(EncodingError),
+    // the key and record together need this many bytes, more than max_entry_size
+    TooLarge(usize),
+    OutOfPages,
+    // the key's bucket is full and its directory can't grow any more
+    Full,
+}
+
+impl fmt::Display for HashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashError::Encoding(e) => write!(f, "{}", e),
+            HashError::TooLarge(size) => {
+                write!(f, "hash index entry of {} bytes is too large", size)
+            }
+            HashError::OutOfPages => write!(f, "ran out of pages"),
+            HashError::Full => write!(f, "hash index directory is full"),
+        }
+    }
+}
+
+impl std::error::Error for HashError {}
+
+impl From<EncodingError> for HashError {
+    fn from(e: EncodingError) -> Self {
+        HashError::Encoding(e)
+    }
+}
+
+// fnv-1a, then murmur3's finalizer so the low bits the directories
+// go by are as well mixed as the high bits the header goes by.
+// it's written to disk in effect, so it can never change
+fn hash(key: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in key {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+fn header_slot(hash: u64, header_depth: u8) -> usize {
+    HEADER_SIZE + (hash >> (64 - header_depth as u32)) as usize * 4
+}
+
+// the deepest a directory can get with a local depth and a bucket page per slot
+// still fitting in a page. 9 with 4 KiB pages, up to 12 with 32 KiB
+fn max_directory_depth(page_size: usize) -> u8 {
+    ((page_size - DIRECTORY_HEADER) / 5).ilog2() as u8
+}
+
+struct Directory {
+    global_depth: u8,
+    // how many slots there's room for, from the header
+    max_depth: u8,
+    // one of each for every global depth bit pattern
+    local_depths: Vec<u8>,
+    buckets: Vec<ID>,
+}
+
+impl Directory {
+    fn new(bucket: ID, max_depth: u8) -> Self {
+        Directory {
+            global_depth: 0,
+            max_depth,
+            local_depths: vec![0],
+            buckets: vec![bucket],
+        }
+    }
+
+    fn from_bytes(data: &[u8], max_depth: u8) -> Self {
+        assert_eq!(data[0], DIRECTORY_PAGE, "not a hash directory page");
+        let global_depth = data[1];
+        let len = 1 << global_depth;
+        let buckets_at = DIRECTORY_HEADER + (1 << max_depth);
+        Directory {
+            global_depth,
+            max_depth,
+            local_depths: data[DIRECTORY_HEADER..DIRECTORY_HEADER + len].to_vec(),
+            buckets: (0..len)
+                .map(|i| get_u32(data, buckets_at + i * 4))
+                .collect(),
+        }
+    }
+
+    fn write_to(&self, data: &mut [u8]) {
+        data[0] = DIRECTORY_PAGE;
+        data[1] = self.global_depth;
+        let len = self.buckets.len();
+        data[DIRECTORY_HEADER..DIRECTORY_HEADER + len].copy_from_slice(&self.local_depths);
+        let buckets_at = DIRECTORY_HEADER + (1 << self.max_depth);
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            data[buckets_at + i * 4..buckets_at + i * 4 + 4].copy_from_slice(&bucket.to_be_bytes());
+        }
+    }
+
+    fn index(&self, hash: u64) -> usize {
+        hash as usize & (self.buckets.len() - 1)
+    }
+
+    // double the directory, each new slot pointing where its twin in
+    // the lower half does
+    fn grow(&mut self) {
+        self.global_depth += 1;
+        self.local_depths.extend_from_within(..);
+        self.buckets.extend_from_within(..);
+    }
+
+    // halve the directory while no bucket needs all of its bits
+    fn shrink(&mut self) {
+        while self.global_depth > 0 && self.local_depths.iter().all(|&d| d < self.global_depth) {
+            self.global_depth -= 1;
+            let len = 1 << self.global_depth;
+            self.local_depths.truncate(len);
+            self.buckets.truncate(len);
+        }
+    }
+
+    // point every slot that went to either bucket at one of them
+    fn replace(&mut self, from: ID, to: ID, depth: u8) {
+        for (i, bucket) in self.buckets.iter_mut().enumerate() {
+            if *bucket == from || *bucket == to {
+                *bucket = to;
+                self.local_depths[i] = depth;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Bucket {
+    keys: Vec<Vec<u8>>,
+    records: Vec<Vec<u8>>,
+}
+
+impl Bucket {
+    fn from_bytes(data: &[u8]) -> Self {
+        assert_eq!(data[0], BUCKET_PAGE, "not a hash bucket page");
+        let count = get_u16(data, 1) as usize;
+        let mut bucket = Bucket::default();
+        let mut at = BUCKET_HEADER;
+        for _ in 0..count {
+            let key_len = get_u16(data, at) as usize;
+            let record_len = get_u16(data, at + 2) as usize;
+            at += BUCKET_ENTRY_OVERHEAD;
+            bucket.keys.push(data[at..at + key_len].to_vec());
+            at += key_len;
+            bucket.records.push(data[at..at + record_len].to_vec());
+            at += record_len;
+        }
+        bucket
+    }
+
+    fn write_to(&self, data: &mut [u8]) {
+        data[0] = BUCKET_PAGE;
+        data[1..3].copy_from_slice(&(self.keys.len() as u16).to_be_bytes());
+        let mut at = BUCKET_HEADER;
+        for (key, record) in self.keys.iter().zip(&self.records) {
+            data[at..at + 2].copy_from_slice(&(key.len() as u16).to_be_bytes());
+            data[at + 2..at + 4].copy_from_slice(&(record.len() as u16).to_be_bytes());
+            at += BUCKET_ENTRY_OVERHEAD;
+            data[at..at + key.len()].copy_from_slice(key);
+            at += key.len();
+            data[at..at + record.len()].copy_from_slice(record);
+            at += record.len();
+        }
+    }
+
+    fn size(&self) -> usize {
+        BUCKET_HEADER
+            + self
+                .keys
+                .iter()
+                .zip(&self.records)
+                .map(|(key, record)| entry_size(key, record))
+                .sum::<usize>()
+    }
+
+    fn find(&self, key: &[u8]) -> Option<usize> {
+        self.keys.iter().position(|k| k == key)
+    }
+
+    // the entries whose hash has this bit set move out into a bucket of their own
+    fn split(&mut self, bit: u64) -> Bucket {
+        let mut moved = Bucket::default();
+        let mut kept = Bucket::default();
+        for (key, record) in self.keys.drain(..).zip(self.records.drain(..)) {
+            let to = match hash(&key) & bit {
+                0 => &mut kept,
+                _ => &mut moved,
+            };
+            to.keys.push(key);
+            to.records.push(record);
+        }
+        *self = kept;
+        moved
+    }
+}
+
+fn entry_size(key: &[u8], record: &[u8]) -> usize {
+    BUCKET_ENTRY_OVERHEAD + key.len() + record.len()
+}
+
+impl<'a> HashIndex<'a> {
+    // None if we ran out of pages
+    pub fn create(pool: &'a Pool, key: Vec<Column>, schema: Vec<Column>) -> Option<Self> {
+        let (header, guard) = pool.new_page()?;
+        let mut page = guard.write();
+        page[0] = HEADER_PAGE;
+        page[1] = HEADER_DEPTH;
+        page[2] = max_directory_depth(pool.page_size());
+        page[HEADER_SIZE..HEADER_SIZE + (4 << HEADER_DEPTH)].fill(0xff);
+        drop(page);
+        drop(guard);
+        Some(HashIndex::open(pool, header, key, schema))
+    }
+
+    pub fn open(pool: &'a Pool, header: ID, key: Vec<Column>, schema: Vec<Column>) -> Self {
+        let page = pool.get_page(header).read_latch();
+        assert_eq!(page[0], HEADER_PAGE, "not a hash index header page");
+        let (header_depth, max_depth) = (page[1], page[2]);
+        drop(page);
+        HashIndex {
+            pool,
+            header,
+            header_depth,
+            max_depth,
+            key,
+            schema,
+        }
+    }
+
+    // the page that identifies this index, pass it to open later
+    pub fn header_page(&self) -> ID {
+        self.header
+    }
+
+    // a bucket always has room for a few entries, so splitting
+    // one can always make room for one more
+    pub fn max_entry_size(&self) -> usize {
+        self.pool.page_size() / 4
+    }
+
+    fn encode_key(&self, key: &[TupleField]) -> Result<Vec<u8>, EncodingError> {
+        if key.len() != self.key.len() {
+            return Err(EncodingError::ColumnCount {
+                expected: self.key.len(),
+                found: key.len(),
+            });
+        }
+        let mut bytes = Vec::new();
+        for (i, (field, column)) in key.iter().zip(&self.key).enumerate() {
+            check_key_field(i, field, column)?;
+            // equal keys encode to equal bytes, so they hash the same
+            encode_key(field, &mut bytes);
+        }
+        Ok(bytes)
+    }
+
+    fn decode_record(&self, bytes: &[u8]) -> Vec<TupleField> {
+        decode_row(bytes, &self.schema).expect("hash index record is corrupt")
+    }
+
+    fn read_latch(&self, page: ID) -> ReadLatch<'a> {
+        self.pool.get_page(page).read_latch()
+    }
+
+    fn write_latch(&self, page: ID) -> WriteLatch<'a> {
+        self.pool.get_page(page).write_latch()
+    }
+
+    // a reader lets go of a bucket's latch a moment before its pin,
+    // so a bucket we just merged away may still be pinned for a bit
+    fn free(&self, page: ID) {
+        while !self.pool.try_delete_page(page) {
+            thread::yield_now();
+        }
+    }
+
+    // directories never move once they're made, so the header
+    // doesn't have to stay latched
+    fn find_directory(&self, hash: u64) -> Option<ID> {
+        let page = get_u32(
+            &self.read_latch(self.header),
+            header_slot(hash, self.header_depth),
+        );
+        (page != NO_PAGE).then_some(page)
+    }
+
+    // like find_directory, making the directory if it isn't there yet
+    fn directory(&self, hash: u64) -> Result<ID, HashError> {
+        if let Some(page) = self.find_directory(hash) {
+            return Ok(page);
+        }
+        let mut header = self.write_latch(self.header);
+        let slot = header_slot(hash, self.header_depth);
+        let page = get_u32(&header, slot);
+        if page != NO_PAGE {
+            // someone else made it while we waited for the latch
+            return Ok(page);
+        }
+        // new pages can't be reached until the header points at them, so they don't need a latch
+        let (bucket, guard) = self.pool.new_page().ok_or(HashError::OutOfPages)?;
+        Bucket::default().write_to(&mut guard.write());
+        drop(guard);
+        let Some((directory, guard)) = self.pool.new_page() else {
+            self.free(bucket);
+            return Err(HashError::OutOfPages);
+        };
+        Directory::new(bucket, self.max_depth).write_to(&mut guard.write());
+        drop(guard);
+        header[slot..slot + 4].copy_from_slice(&directory.to_be_bytes());
+        Ok(directory)
+    }
+
+    pub fn search(&self, key: &[TupleField]) -> Option<Vec<TupleField>> {
+        let key = self.encode_key(key).ok()?;
+        let hash = hash(&key);
+        let directory_latch = self.read_latch(self.find_directory(hash)?);
+        let directory = Directory::from_bytes(&directory_latch, self.max_depth);
+        let latch = self.read_latch(directory.buckets[directory.index(hash)]);
+        drop(directory_latch);
+        let bucket = Bucket::from_bytes(&latch);
+        let at = bucket.find(&key)?;
+        Some(self.decode_record(&bucket.records[at]))
+    }
+
+    // false if the key was already there, in which case nothing changes
+    pub fn insert(&self, key: &[TupleField], record: &[TupleField]) -> Result<bool, HashError> {
+        let key = self.encode_key(key)?;
+        let record = encode_row(record, &self.schema)?;
+        let size = entry_size(&key, &record);
+        if size > self.max_entry_size() {
+            return Err(HashError::TooLarge(size));
+        }
+        let hash = hash(&key);
+        let directory_page = self.directory(hash)?;
+
+        // most inserts fit in their bucket, and only need to latch it
+        {
+            let directory_latch = self.read_latch(directory_page);
+            let directory = Directory::from_bytes(&directory_latch, self.max_depth);
+            let mut latch = self.write_latch(directory.buckets[directory.index(hash)]);
+            // the bucket can't split or merge without its latch
+            drop(directory_latch);
+            let mut bucket = Bucket::from_bytes(&latch);
+            if bucket.find(&key).is_some() {
+                return Ok(false);
+            }
+            if bucket.size() + size <= self.pool.page_size() {
+                bucket.keys.push(key);
+                bucket.records.push(record);
+                bucket.write_to(&mut latch);
+                return Ok(true);
+            }
+        }
+
+        // otherwise split it until the key's half has room
+        let mut directory_latch = self.write_latch(directory_page);
+        let mut directory = Directory::from_bytes(&directory_latch, self.max_depth);
+        loop {
+            let index = directory.index(hash);
+            let page = directory.buckets[index];
+            let mut latch = self.write_latch(page);
+            let mut bucket = Bucket::from_bytes(&latch);
+            // the bucket could have changed while we had no latches
+            if bucket.find(&key).is_some() {
+                return Ok(false);
+            }
+            if bucket.size() + size <= self.pool.page_size() {
+                bucket.keys.push(key);
+                bucket.records.push(record);
+                bucket.write_to(&mut latch);
+                return Ok(true);
+            }
+
+            let depth = directory.local_depths[index];
+            if depth == directory.global_depth {
+                if depth == directory.max_depth {
+                    return Err(HashError::Full);
+                }
+                directory.grow();
+            }
+            let (image, guard) = self.pool.new_page().ok_or(HashError::OutOfPages)?;
+            let bit = 1 << depth;
+            bucket.split(bit).write_to(&mut guard.write());
+            drop(guard);
+            bucket.write_to(&mut latch);
+            for (i, slot) in directory.buckets.iter_mut().enumerate() {
+                if *slot == page {
+                    directory.local_depths[i] = depth + 1;
+                    if i as u64 & bit != 0 {
+                        *slot = image;
+                    }
+                }
+            }
+            directory.write_to(&mut directory_latch);
+        }
+    }
+
+    // false if there was no such key
+    pub fn delete(&self, key: &[TupleField]) -> Result<bool, HashError> {
+        let key = self.encode_key(key)?;
+        let hash = hash(&key);
+        let Some(directory_page) = self.find_directory(hash) else {
+            return Ok(false);
+        };
+
+        // a delete that doesn't empty its bucket only needs to latch the bucket
+        {
+            let directory_latch = self.read_latch(directory_page);
+            let directory = Directory::from_bytes(&directory_latch, self.max_depth);
+            let index = directory.index(hash);
+            let mut latch = self.write_latch(directory.buckets[index]);
+            drop(directory_latch);
+            let mut bucket = Bucket::from_bytes(&latch);
+            let Some(at) = bucket.find(&key) else {
+                return Ok(false);
+            };
+            if bucket.keys.len() > 1 || directory.local_depths[index] == 0 {
+                bucket.keys.remove(at);
+                bucket.records.remove(at);
+                bucket.write_to(&mut latch);
+                return Ok(true);
+            }
+        }
+
+        // otherwise merge the empty bucket back into its split image
+        let mut directory_latch = self.write_latch(directory_page);
+        let mut directory = Directory::from_bytes(&directory_latch, self.max_depth);
+        let index = directory.index(hash);
+        {
+            let mut latch = self.write_latch(directory.buckets[index]);
+            let mut bucket = Bucket::from_bytes(&latch);
+            let Some(at) = bucket.find(&key) else {
+                return Ok(false);
+            };
+            bucket.keys.remove(at);
+            bucket.records.remove(at);
+            bucket.write_to(&mut latch);
+        }
+        self.merge(&mut directory, index);
+        directory.shrink();
+        directory.write_to(&mut directory_latch);
+        Ok(true)
+    }
+
+    // merge the bucket at index with its split image while either is empty.
+    // the directory has to be write latched, so no one else can reach them
+    fn merge(&self, directory: &mut Directory, index: usize) {
+        loop {
+            let depth = directory.local_depths[index];
+            if depth == 0 {
+                return;
+            }
+            let image_index = index ^ (1 << (depth - 1));
+            if directory.local_depths[image_index] != depth {
+                // the image split further, so it isn't one bucket to merge with
+                return;
+            }
+            let page = directory.buckets[index];
+            let image = directory.buckets[image_index];
+            // a writer that got a bucket before we had the directory may still be in it
+            let empty = Bucket::from_bytes(&self.write_latch(page)).keys.is_empty();
+            let image_empty = Bucket::from_bytes(&self.write_latch(image)).keys.is_empty();
+            let (keep, gone) = match (empty, image_empty) {
+                (true, _) => (image, page),
+                (_, true) => (page, image),
+                _ => return,
+            };
+            directory.replace(gone, keep, depth - 1);
+            self.free(gone);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_u32, hash, header_slot, max_directory_depth, Directory, HashError, HashIndex,
+        HEADER_DEPTH, HEADER_SIZE, NO_PAGE,
+    };
+    use crate::bufferpool::{eviction::LruK, EvictionStrategy, Pool, ID};
+    use crate::disk::{DiskManager, PageSize};
+    use crate::page_interpretation::encoding::EncodingError;
+    use crate::page_interpretation::{Column, TupleField, TupleFieldTypes};
+    use crate::utils::testing::{pool, temp_db, temp_path, Rng};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::thread;
+
+    fn index(pool: &Pool) -> HashIndex<'_> {
+        HashIndex::create(
+            pool,
+            vec![Column::not_null(TupleFieldTypes::BIGINT)],
+            vec![Column::not_null(TupleFieldTypes::VARCHAR(2000))],
+        )
+        .unwrap()
+    }
+
+    fn key(k: u64) -> [TupleField; 1] {
+        [TupleField::BigInt(k)]
+    }
+
+    fn record(text: &str) -> [TupleField; 1] {
+        [TupleField::Varchar(text.to_string())]
+    }
+
+    // every directory the index has made so far
+    fn directories(index: &HashIndex) -> Vec<Directory> {
+        let header = index.pool.get_page(index.header).read();
+        (0..1 << index.header_depth)
+            .map(|i| get_u32(&header, HEADER_SIZE + i * 4))
+            .filter(|&page| page != NO_PAGE)
+            .map(|page| Directory::from_bytes(&index.pool.get_page(page).read(), index.max_depth))
+            .collect()
+    }
+
+    #[test]
+    fn insert_search_delete() {
+        let pool = pool("hash_basics", 8);
+        let index = index(&pool);
+        assert_eq!(index.search(&key(1)), None);
+        assert!(index.insert(&key(1), &record("one")).unwrap());
+        assert!(index.insert(&key(2), &record("two")).unwrap());
+        // keys are unique, the first record stays
+        assert!(!index.insert(&key(1), &record("uno")).unwrap());
+        assert_eq!(index.search(&key(1)), Some(record("one").to_vec()));
+        assert_eq!(index.search(&key(2)), Some(record("two").to_vec()));
+
+        assert!(index.delete(&key(1)).unwrap());
+        assert!(!index.delete(&key(1)).unwrap());
+        assert!(!index.delete(&key(3)).unwrap());
+        assert_eq!(index.search(&key(1)), None);
+        assert_eq!(index.search(&key(2)), Some(record("two").to_vec()));
+
+        assert_eq!(
+            index.insert(&[TupleField::Int(1)], &record("x")),
+            Err(HashError::Encoding(EncodingError::TypeMismatch {
+                column: 0,
+                expected: TupleFieldTypes::BIGINT,
+                found: TupleFieldTypes::INT,
+            }))
+        );
+        // lengths, key, the record's null bitmap and text length, text
+        assert_eq!(
+            index.insert(&key(5), &record(&"x".repeat(1100))),
+            Err(HashError::TooLarge(4 + 9 + 1 + 4 + 1100))
+        );
+        assert_eq!(
+            index.delete(&[]),
+            Err(HashError::Encoding(EncodingError::ColumnCount {
+                expected: 1,
+                found: 0
+            }))
+        );
+    }
+
+    #[test]
+    fn matches_a_hash_map() {
+        let pool = pool("hash_random", 64);
+        let index = index(&pool);
+        let mut expected = HashMap::new();
+        let mut rng = Rng::new(50);
+        for _ in 0..20_000 {
+            let k = rng.below(4000);
+            let text = "v".repeat(rng.below(200) as usize);
+            if rng.below(3) == 0 {
+                assert_eq!(
+                    index.delete(&key(k)).unwrap(),
+                    expected.remove(&k).is_some()
+                );
+            } else {
+                let fresh = !expected.contains_key(&k);
+                assert_eq!(index.insert(&key(k), &record(&text)).unwrap(), fresh);
+                expected.entry(k).or_insert(text);
+            }
+        }
+        for k in 0..4000 {
+            assert_eq!(
+                index.search(&key(k)),
+                expected.get(&k).map(|text| record(text).to_vec())
+            );
+        }
+        // the directories grew past their first bucket, and each slot's local
+        // depth says how many of its bits its bucket shares with it
+        let grown = directories(&index);
+        assert!(grown.iter().any(|d| d.global_depth > 0));
+        for directory in &grown {
+            for (i, (&bucket, &depth)) in directory
+                .buckets
+                .iter()
+                .zip(&directory.local_depths)
+                .enumerate()
+            {
+                assert!(depth <= directory.global_depth);
+                for (j, &other) in directory.buckets.iter().enumerate() {
+                    let same_bits = (i ^ j) & ((1 << depth) - 1) == 0;
+                    assert_eq!(other == bucket, same_bits);
+                }
+            }
+        }
+
+        // emptying it merges every directory back down to one bucket
+        let before = pool.used_pages();
+        for &k in expected.keys() {
+            assert!(index.delete(&key(k)).unwrap());
+        }
+        let shrunk = directories(&index);
+        assert!(shrunk.iter().all(|d| d.global_depth == 0));
+        assert!(pool.used_pages() < before);
+        assert_eq!(pool.used_pages(), 1 + 2 * shrunk.len() as u32);
+    }
+
+    #[test]
+    fn text_keys_and_reopening() {
+        let path = temp_db("hash_reopen");
+        let key = vec![
+            Column::not_null(TupleFieldTypes::VARCHAR(100)),
+            Column::nullable(TupleFieldTypes::INT),
+        ];
+        let schema = vec![Column::not_null(TupleFieldTypes::BIGINT)];
+        let header = {
+            let strat: Mutex<Box<dyn EvictionStrategy>> = Mutex::new(Box::new(LruK::new(16, 2)));
+            let pool = Pool::with_disk(16, strat, DiskManager::open(&path));
+            let index = HashIndex::create(&pool, key.clone(), schema.clone()).unwrap();
+            for i in 0..2000u64 {
+                let k = [
+                    TupleField::Varchar(format!("user-{}", i / 2)),
+                    match i % 2 {
+                        0 => TupleField::Null,
+                        _ => TupleField::Int(i as u32),
+                    },
+                ];
+                assert!(index.insert(&k, &[TupleField::BigInt(i)]).unwrap());
+            }
+            pool.flush_all();
+            index.header_page()
+        };
+        let strat: Mutex<Box<dyn EvictionStrategy>> = Mutex::new(Box::new(LruK::new(16, 2)));
+        let pool = Pool::with_disk(16, strat, DiskManager::open(&path));
+        let index = HashIndex::open(&pool, header, key, schema);
+        for i in 0..2000u64 {
+            let k = [
+                TupleField::Varchar(format!("user-{}", i / 2)),
+                match i % 2 {
+                    0 => TupleField::Null,
+                    _ => TupleField::Int(i as u32),
+                },
+            ];
+            assert_eq!(index.search(&k), Some(vec![TupleField::BigInt(i)]));
+        }
+        let missing = [
+            TupleField::Varchar("user-1".to_string()),
+            TupleField::Int(2),
+        ];
+        assert_eq!(index.search(&missing), None);
+    }
+
+    #[test]
+    fn header_spreads_keys_over_directories() {
+        let mut used = [false; 1 << HEADER_DEPTH];
+        for k in 0u64..20_000 {
+            used[(header_slot(hash(&k.to_be_bytes()), HEADER_DEPTH) - HEADER_SIZE) / 4] = true;
+        }
+        assert!(used.iter().all(|&u| u));
+    }
+
+    #[test]
+    fn directories_grow_with_the_page_size() {
+        assert_eq!(max_directory_depth(4096), 9);
+        assert_eq!(max_directory_depth(16384), 11);
+        assert_eq!(max_directory_depth(32768), 12);
+
+        let strat: Mutex<Box<dyn EvictionStrategy>> = Mutex::new(Box::new(LruK::new(8, 2)));
+        let disk = DiskManager::create(temp_path("hash_16k"), PageSize::Size16K);
+        let pool = Pool::with_disk(8, strat, disk);
+        let index = index(&pool);
+        assert_eq!(index.max_depth, 11);
+
+        // a full directory still fits in its page
+        let mut directory = Directory::new(0, index.max_depth);
+        while directory.global_depth < directory.max_depth {
+            directory.grow();
+        }
+        for (i, bucket) in directory.buckets.iter_mut().enumerate() {
+            *bucket = i as ID;
+        }
+        let mut page = vec![0; pool.page_size()];
+        directory.write_to(&mut page);
+        let read = Directory::from_bytes(&page, index.max_depth);
+        assert_eq!(read.global_depth, 11);
+        assert_eq!(read.buckets, directory.buckets);
+    }
+
+    #[test]
+    fn concurrent_writers_and_readers() {
+        const THREADS: u64 = 8;
+        const KEYS_PER_THREAD: u64 = 1500;
+
+        let pool = pool("hash_concurrent", 128);
+        let index = index(&pool);
+        thread::scope(|scope| {
+            for t in 0..THREADS {
+                let index = &index;
+                scope.spawn(move || {
+                    let mut rng = Rng::new(t);
+                    // each thread has its own keys, mixed in with everyone else's
+                    let keys: Vec<u64> = (0..KEYS_PER_THREAD).map(|i| i * THREADS + t).collect();
+                    for &k in &keys {
+                        let text = "v".repeat(rng.below(200) as usize);
+                        assert!(index.insert(&key(k), &record(&text)).unwrap());
+                        if k % 7 == 0 {
+                            assert!(index.search(&key(k)).is_some());
+                        }
+                    }
+                    for &k in keys.iter().filter(|&&k| k % 3 != 0) {
+                        assert!(index.delete(&key(k)).unwrap());
+                    }
+                    for &k in &keys {
+                        assert_eq!(index.search(&key(k)).is_some(), k % 3 == 0);
+                    }
+                });
+            }
+        });
+        for k in 0..THREADS * KEYS_PER_THREAD {
+            assert_eq!(index.search(&key(k)).is_some(), k % 3 == 0);
+        }
+    }
+}
@@ -0,0 +1,757 @@
+use crate::bufferpool::{PageGuard, Pool, ID};
+use crate::page_interpretation::encoding::{get_u16, get_u32};
+use crate::page_interpretation::slotted::SlottedPage;
+use std::sync::RwLock;
+
+// a heap file is an unordered collection of records spread over slotted pages.
+//
+// it is found through its first directory page. directory pages list the data
+// pages of the file along with how many bytes each one has free, which is our
+// free space map, and chain to the next directory page once they fill up:
+//
+// | next directory page | entry count | flags | (page id, free bytes) | (page id, free bytes) | ...
+//
+// page ids are u32s and counts are u16s, all big endian.
+// flags are a byte of options for the whole file, only kept in the first directory page
+const NO_PAGE: ID = u32::MAX;
+const DIRECTORY_HEADER: usize = 7;
+const DIRECTORY_ENTRY: usize = 6;
+
+// every page of the file is compressed on disk
+const FLAG_COMPRESSED: u8 = 1;
+
+// every stored record starts with a tag byte.
+// a record that no longer fits in its page after an update moves elsewhere and
+// leaves a forward behind, so its record id never changes
+const TAG_RECORD: u8 = 0;
+const TAG_FORWARD: u8 = 1;
+// a record that moved here, only reachable through its forward
+const TAG_MOVED: u8 = 2;
+const TAG_MASK: u8 = 0b11;
+
+const RID_SIZE: usize = 6;
+
+// records are padded so a forward can always take their place.
+// the next bits of the tag byte count the padding
+const MIN_STORED: usize = 1 + RID_SIZE;
+const PADDING_SHIFT: u8 = 2;
+const PADDING_MASK: u8 = 0b111;
+
+// set on records that are too big to keep on a data page. what's stored instead is
+// the record's length and the first page of the overflow chain holding its bytes
+const OVERFLOW: u8 = 1 << 5;
+const OVERFLOW_POINTER: usize = 8;
+
+// overflow pages hold a piece of a record each, and chain to the next piece:
+//
+// | next overflow page | bytes on this page | bytes ... |
+//
+// NO_PAGE ends the chain
+const OVERFLOW_HEADER: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordId {
+    pub page: ID,
+    pub slot: u16,
+}
+
+impl RecordId {
+    pub fn to_bytes(self) -> [u8; RID_SIZE] {
+        let mut bytes = [0; RID_SIZE];
+        bytes[..4].copy_from_slice(&self.page.to_be_bytes());
+        bytes[4..].copy_from_slice(&self.slot.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        RecordId {
+            page: u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            slot: u16::from_be_bytes([bytes[4], bytes[5]]),
+        }
+    }
+}
+
+fn tagged(tag: u8, record: &[u8]) -> Vec<u8> {
+    let padding = MIN_STORED.saturating_sub(record.len() + 1);
+    let mut bytes = Vec::with_capacity(record.len() + 1 + padding);
+    bytes.push(tag | (padding as u8) << PADDING_SHIFT);
+    bytes.extend_from_slice(record);
+    bytes.resize(record.len() + 1 + padding, 0);
+    bytes
+}
+
+// the tag and the record without its padding.
+// for overflowed records that's the pointer to their chain
+fn untagged(stored: &[u8]) -> (u8, &[u8]) {
+    let padding = (stored[0] >> PADDING_SHIFT & PADDING_MASK) as usize;
+    (stored[0] & TAG_MASK, &stored[1..stored.len() - padding])
+}
+
+fn is_overflow(stored: &[u8]) -> bool {
+    stored[0] & OVERFLOW != 0
+}
+
+// what a record looks like on its data page, before it's tagged:
+// the record itself, or a pointer to its overflow chain
+struct Body {
+    bytes: Vec<u8>,
+    overflow: bool,
+}
+
+impl Body {
+    fn tagged(&self, tag: u8) -> Vec<u8> {
+        let flag = if self.overflow { OVERFLOW } else { 0 };
+        tagged(tag | flag, &self.bytes)
+    }
+}
+
+pub struct HeapFile<'a> {
+    pool: &'a Pool,
+    directory: ID,
+    flags: u8,
+    // writers change a data page and the directory together
+    latch: RwLock<()>,
+}
+
+impl<'a> HeapFile<'a> {
+    pub fn create(pool: &'a Pool) -> Option<Self> {
+        HeapFile::create_with_flags(pool, 0)
+    }
+
+    // a heap file for cold data, whose pages are lz4 compressed on disk.
+    // it saves space with pages bigger than the filesystem's blocks, see disk/compression.rs
+    #[cfg(feature = "compression")]
+    pub fn create_compressed(pool: &'a Pool) -> Option<Self> {
+        HeapFile::create_with_flags(pool, FLAG_COMPRESSED)
+    }
+
+    fn create_with_flags(pool: &'a Pool, flags: u8) -> Option<Self> {
+        let (directory, guard) = pool.new_page()?;
+        let mut page = guard.write();
+        page[..4].copy_from_slice(&NO_PAGE.to_be_bytes());
+        page[4..6].fill(0);
+        page[6] = flags;
+        drop(page);
+        Some(HeapFile::open(pool, directory))
+    }
+
+    pub fn open(pool: &'a Pool, directory: ID) -> Self {
+        // the first directory page is never compressed, so this can always be read
+        let flags = pool.get_page(directory).read()[6];
+        assert!(
+            cfg!(feature = "compression") || flags & FLAG_COMPRESSED == 0,
+            "heap file {} is compressed, build with --features compression to open it",
+            directory
+        );
+        HeapFile {
+            pool,
+            directory,
+            flags,
+            latch: RwLock::new(()),
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags & FLAG_COMPRESSED != 0
+    }
+
+    // the page that identifies this heap file, pass it to open later
+    pub fn directory_page(&self) -> ID {
+        self.directory
+    }
+
+    // records bigger than this go to overflow pages, so a data page
+    // always has room for a few records
+    pub fn max_inline_size(&self) -> usize {
+        self.pool.page_size() / 4
+    }
+
+    // None if we ran out of pages
+    pub fn insert(&self, record: &[u8]) -> Option<RecordId> {
+        let _latch = self.latch.write().unwrap();
+        let body = self.store(record)?;
+        let rid = self.insert_tagged(&body.tagged(TAG_RECORD));
+        if rid.is_none() {
+            self.free_body(&body);
+        }
+        rid
+    }
+
+    pub fn get(&self, rid: RecordId) -> Option<Vec<u8>> {
+        let _latch = self.latch.read().unwrap();
+        self.get_latched(rid)
+    }
+
+    // like get, for callers that already hold the latch
+    fn get_latched(&self, rid: RecordId) -> Option<Vec<u8>> {
+        let stored = self.read_slot(rid)?;
+        match untagged(&stored) {
+            (TAG_RECORD, _) => Some(self.load(&stored)),
+            (TAG_FORWARD, moved) => {
+                let moved = self.read_slot(RecordId::from_bytes(moved))?;
+                Some(self.load(&moved))
+            }
+            // only reachable through the forward
+            _ => None,
+        }
+    }
+
+    // returns false if there is no record with that id, or we ran out of pages
+    pub fn update(&self, rid: RecordId, record: &[u8]) -> bool {
+        let _latch = self.latch.write().unwrap();
+        let stored = match self.read_slot(rid) {
+            Some(stored) => stored,
+            None => return false,
+        };
+        // where the record's bytes are now, and what's stored there
+        let (location, tag, old) = match untagged(&stored) {
+            (TAG_RECORD, _) => (rid, TAG_RECORD, stored.clone()),
+            (TAG_FORWARD, moved) => {
+                let moved = RecordId::from_bytes(moved);
+                match self.read_slot(moved) {
+                    Some(old) => (moved, TAG_MOVED, old),
+                    None => return false,
+                }
+            }
+            _ => return false,
+        };
+        let body = match self.store(record) {
+            Some(body) => body,
+            None => return false,
+        };
+
+        if !self.update_slot(location, &body.tagged(tag)) {
+            // doesn't fit where it is anymore, move it and leave a forward behind
+            let new_location = match self.insert_tagged(&body.tagged(TAG_MOVED)) {
+                Some(new_location) => new_location,
+                None => {
+                    self.free_body(&body);
+                    return false;
+                }
+            };
+            if location != rid {
+                self.delete_slot(location);
+            }
+            let forward = tagged(TAG_FORWARD, &new_location.to_bytes());
+            assert!(
+                self.update_slot(rid, &forward),
+                "records are padded to fit a forward"
+            );
+        }
+        self.free_overflow(&old);
+        true
+    }
+
+    pub fn delete(&self, rid: RecordId) -> bool {
+        let _latch = self.latch.write().unwrap();
+        let stored = match self.read_slot(rid) {
+            Some(stored) => stored,
+            None => return false,
+        };
+        match untagged(&stored) {
+            (TAG_RECORD, _) => {
+                self.delete_slot(rid);
+                self.free_overflow(&stored);
+            }
+            (TAG_FORWARD, moved) => {
+                let moved = RecordId::from_bytes(moved);
+                if let Some(moved_stored) = self.read_slot(moved) {
+                    self.free_overflow(&moved_stored);
+                }
+                self.delete_slot(moved);
+                self.delete_slot(rid);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // every record in the file, in no particular order.
+    // pages are copied out of the pool one at a time, so nothing stays pinned
+    pub fn scan(&self) -> HeapScan<'_, 'a> {
+        HeapScan {
+            heap: self,
+            data_pages: self.data_pages().into_iter(),
+            current: None,
+        }
+    }
+
+    // keep small records as they are, and write big ones out to an overflow chain
+    fn store(&self, record: &[u8]) -> Option<Body> {
+        if record.len() <= self.max_inline_size() {
+            return Some(Body {
+                bytes: record.to_vec(),
+                overflow: false,
+            });
+        }
+        let first = self.write_overflow(record)?;
+        let mut pointer = Vec::with_capacity(OVERFLOW_POINTER);
+        pointer.extend_from_slice(&(record.len() as u32).to_be_bytes());
+        pointer.extend_from_slice(&first.to_be_bytes());
+        Some(Body {
+            bytes: pointer,
+            overflow: true,
+        })
+    }
+
+    // the record in a stored slot, fetching it from its overflow chain if it has one
+    fn load(&self, stored: &[u8]) -> Vec<u8> {
+        let (_, body) = untagged(stored);
+        if !is_overflow(stored) {
+            return body.to_vec();
+        }
+        let len = get_u32(body, 0) as usize;
+        let mut record = Vec::with_capacity(len);
+        let mut next = get_u32(body, 4);
+        while next != NO_PAGE {
+            let page = self.pool.get_page(next).read();
+            let count = get_u16(&page, 4) as usize;
+            record.extend_from_slice(&page[OVERFLOW_HEADER..OVERFLOW_HEADER + count]);
+            next = get_u32(&page, 0);
+        }
+        assert_eq!(record.len(), len, "overflow chain doesn't match its record");
+        record
+    }
+
+    // returns the first page of the chain
+    fn write_overflow(&self, record: &[u8]) -> Option<ID> {
+        let per_page = self.pool.page_size() - OVERFLOW_HEADER;
+        let mut pages = Vec::new();
+        for _ in 0..record.len().div_ceil(per_page) {
+            match self.new_page() {
+                Some((page, _)) => pages.push(page),
+                None => {
+                    for page in pages {
+                        self.pool.delete_page(page);
+                    }
+                    return None;
+                }
+            }
+        }
+        for (i, chunk) in record.chunks(per_page).enumerate() {
+            let next = pages.get(i + 1).copied().unwrap_or(NO_PAGE);
+            let guard = self.pool.get_page(pages[i]);
+            let mut page = guard.write();
+            page[..4].copy_from_slice(&next.to_be_bytes());
+            page[4..6].copy_from_slice(&(chunk.len() as u16).to_be_bytes());
+            page[OVERFLOW_HEADER..OVERFLOW_HEADER + chunk.len()].copy_from_slice(chunk);
+        }
+        Some(pages[0])
+    }
+
+    // give the overflow pages of a stored record back to the pool
+    fn free_overflow(&self, stored: &[u8]) {
+        if is_overflow(stored) {
+            self.free_chain(get_u32(untagged(stored).1, 4));
+        }
+    }
+
+    // for a body that never made it onto a data page
+    fn free_body(&self, body: &Body) {
+        if body.overflow {
+            self.free_chain(get_u32(&body.bytes, 4));
+        }
+    }
+
+    fn free_chain(&self, mut next: ID) {
+        while next != NO_PAGE {
+            let page = next;
+            next = get_u32(&self.pool.get_page(page).read(), 0);
+            self.pool.delete_page(page);
+        }
+    }
+
+    // every page the file allocates goes through here, so compressed files compress all of them
+    fn new_page(&self) -> Option<(ID, PageGuard<'a>)> {
+        let (page, guard) = self.pool.new_page()?;
+        self.mark_page(page);
+        Some((page, guard))
+    }
+
+    #[cfg(feature = "compression")]
+    fn mark_page(&self, page: ID) {
+        if self.is_compressed() {
+            self.pool.set_compressed(page, true);
+        }
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn mark_page(&self, _page: ID) {}
+
+    fn read_slot(&self, rid: RecordId) -> Option<Vec<u8>> {
+        let page = self.pool.get_page(rid.page).read();
+        SlottedPage::from(&page[..])
+            .get(rid.slot)
+            .map(|r| r.to_vec())
+    }
+
+    fn update_slot(&self, rid: RecordId, stored: &[u8]) -> bool {
+        let guard = self.pool.get_page(rid.page);
+        let mut page = guard.write();
+        let mut slotted = SlottedPage::from(&mut page[..]);
+        if slotted.update(rid.slot, stored).is_err() {
+            return false;
+        }
+        let free = slotted.free_space();
+        drop(page);
+        self.set_free_space(rid.page, free);
+        true
+    }
+
+    fn delete_slot(&self, rid: RecordId) {
+        let guard = self.pool.get_page(rid.page);
+        let mut page = guard.write();
+        let mut slotted = SlottedPage::from(&mut page[..]);
+        slotted.delete(rid.slot).unwrap();
+        let free = slotted.free_space();
+        drop(page);
+        self.set_free_space(rid.page, free);
+    }
+
+    fn insert_tagged(&self, stored: &[u8]) -> Option<RecordId> {
+        let page_id = match self.find_free_page(stored.len()) {
+            Some(page_id) => page_id,
+            None => self.add_data_page()?,
+        };
+        let guard = self.pool.get_page(page_id);
+        let mut page = guard.write();
+        let mut slotted = SlottedPage::from(&mut page[..]);
+        let slot = slotted
+            .insert(stored)
+            .expect("free space map said the record fits");
+        let free = slotted.free_space();
+        drop(page);
+        self.set_free_space(page_id, free);
+        Some(RecordId {
+            page: page_id,
+            slot,
+        })
+    }
+
+    // walk the directory, calling f on each (directory page, entry index, data page, free bytes)
+    // until it returns true
+    fn find_entry(&self, mut f: impl FnMut(ID, usize, ID, usize) -> bool) -> bool {
+        let mut directory = self.directory;
+        while directory != NO_PAGE {
+            let page = self.pool.get_page(directory).read();
+            let count = get_u16(&page, 4) as usize;
+            for entry in 0..count {
+                let offset = DIRECTORY_HEADER + entry * DIRECTORY_ENTRY;
+                let data_page = get_u32(&page, offset);
+                let free = get_u16(&page, offset + 4) as usize;
+                if f(directory, entry, data_page, free) {
+                    return true;
+                }
+            }
+            directory = get_u32(&page, 0);
+        }
+        false
+    }
+
+    fn data_pages(&self) -> Vec<ID> {
+        let _latch = self.latch.read().unwrap();
+        let mut pages = Vec::new();
+        self.find_entry(|_, _, data_page, _| {
+            pages.push(data_page);
+            false
+        });
+        pages
+    }
+
+    fn find_free_page(&self, needed: usize) -> Option<ID> {
+        let mut found = None;
+        self.find_entry(|_, _, data_page, free| {
+            if free >= needed {
+                found = Some(data_page);
+            }
+            found.is_some()
+        });
+        found
+    }
+
+    fn set_free_space(&self, data_page: ID, free: usize) {
+        let mut location = None;
+        self.find_entry(|directory, entry, page, _| {
+            if page == data_page {
+                location = Some((directory, entry));
+            }
+            location.is_some()
+        });
+        let (directory, entry) = location.expect("data page is missing from the directory");
+        let guard = self.pool.get_page(directory);
+        let offset = DIRECTORY_HEADER + entry * DIRECTORY_ENTRY + 4;
+        guard.write()[offset..offset + 2].copy_from_slice(&(free as u16).to_be_bytes());
+    }
+
+    // allocate a new data page and record it in the last directory page,
+    // chaining a new directory page if that one is full
+    fn add_data_page(&self) -> Option<ID> {
+        let (data_page, data_guard) = self.new_page()?;
+        let mut page = data_guard.write();
+        let free = SlottedPage::init(&mut page[..]).free_space();
+        drop(page);
+        drop(data_guard);
+
+        let per_directory = (self.pool.page_size() - DIRECTORY_HEADER) / DIRECTORY_ENTRY;
+        let mut directory = self.directory;
+        loop {
+            let guard = self.pool.get_page(directory);
+            let mut page = guard.write();
+            let count = get_u16(&page, 4) as usize;
+            if count < per_directory {
+                let offset = DIRECTORY_HEADER + count * DIRECTORY_ENTRY;
+                page[offset..offset + 4].copy_from_slice(&data_page.to_be_bytes());
+                page[offset + 4..offset + 6].copy_from_slice(&(free as u16).to_be_bytes());
+                page[4..6].copy_from_slice(&(count as u16 + 1).to_be_bytes());
+                return Some(data_page);
+            }
+            let next = get_u32(&page, 0);
+            if next != NO_PAGE {
+                directory = next;
+                continue;
+            }
+            let (next, next_guard) = self.new_page()?;
+            let mut next_page = next_guard.write();
+            next_page[..4].copy_from_slice(&NO_PAGE.to_be_bytes());
+            next_page[4..7].fill(0);
+            page[..4].copy_from_slice(&next.to_be_bytes());
+            directory = next;
+        }
+    }
+}
+
+pub struct HeapScan<'h, 'a> {
+    heap: &'h HeapFile<'a>,
+    data_pages: std::vec::IntoIter<ID>,
+    // records left on the page we're currently scanning
+    current: Option<std::vec::IntoIter<(RecordId, Vec<u8>)>>,
+}
+
+impl Iterator for HeapScan<'_, '_> {
+    type Item = (RecordId, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(records) = &mut self.current {
+                if let Some(record) = records.next() {
+                    return Some(record);
+                }
+            }
+            let page_id = self.data_pages.next()?;
+            // a writer could free an overflow chain while we follow it
+            let _latch = self.heap.latch.read().unwrap();
+            let page = self.heap.pool.get_page(page_id).read();
+            let mut records = Vec::new();
+            for (slot, stored) in SlottedPage::from(&page[..]).iter() {
+                let rid = RecordId {
+                    page: page_id,
+                    slot,
+                };
+                match untagged(stored) {
+                    (TAG_RECORD, _) => records.push((rid, self.heap.load(stored))),
+                    // moved records are returned under their original id
+                    (TAG_FORWARD, _) => {
+                        if let Some(record) = self.heap.get_latched(rid) {
+                            records.push((rid, record));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            self.current = Some(records.into_iter());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HeapFile, RecordId};
+    use crate::utils::testing::{pool, Rng};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn scans_see_whole_records_while_overflow_chains_change() {
+        let pool = pool("heap_scan_concurrent", 32);
+        let heap = HeapFile::create(&pool).unwrap();
+        // every record is one byte repeated, and too big to stay on its page
+        let rids: Vec<_> = (0..8u8)
+            .map(|i| heap.insert(&vec![i; 3000]).unwrap())
+            .collect();
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let (heap, rids, done) = (&heap, &rids, &done);
+            scope.spawn(move || {
+                let mut rng = Rng::new(38);
+                for _ in 0..2000 {
+                    let rid = rids[rng.below(rids.len() as u64) as usize];
+                    let len = 2000 + rng.below(8000) as usize;
+                    assert!(heap.update(rid, &vec![rng.below(256) as u8; len]));
+                }
+                done.store(true, Ordering::Relaxed);
+            });
+            while !done.load(Ordering::Relaxed) {
+                let records: Vec<_> = heap.scan().collect();
+                assert_eq!(records.len(), rids.len());
+                for (_, record) in records {
+                    assert!(record.iter().all(|&b| b == record[0]));
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn insert_get_delete() {
+        let pool = pool("heap_insert_get_delete", 4);
+        let heap = HeapFile::create(&pool).unwrap();
+        let a = heap.insert(b"first").unwrap();
+        let b = heap.insert(b"second").unwrap();
+        assert_eq!(heap.get(a), Some(b"first".to_vec()));
+        assert_eq!(heap.get(b), Some(b"second".to_vec()));
+        assert!(heap.delete(a));
+        assert!(!heap.delete(a));
+        assert_eq!(heap.get(a), None);
+        assert_eq!(
+            heap.get(RecordId {
+                page: b.page,
+                slot: 99
+            }),
+            None
+        );
+        // short records are padded on the page, but come back as they went in
+        let empty = heap.insert(b"").unwrap();
+        assert_eq!(heap.get(empty), Some(Vec::new()));
+    }
+
+    #[test]
+    fn large_records_overflow() {
+        let pool = pool("heap_overflow", 4);
+        let heap = HeapFile::create(&pool).unwrap();
+        let small = heap.insert(b"small").unwrap();
+        let used = pool.used_pages();
+
+        let big: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+        let rid = heap.insert(&big).unwrap();
+        // the record shares its data page, the rest is 5 overflow pages
+        assert_eq!(rid.page, small.page);
+        assert_eq!(pool.used_pages(), used + 5);
+        assert_eq!(heap.get(rid), Some(big.clone()));
+
+        // updates free the old chain, whether the record stays big or not
+        assert!(heap.update(rid, &big[..9000]));
+        assert_eq!(pool.used_pages(), used + 3);
+        assert_eq!(heap.get(rid).as_deref(), Some(&big[..9000]));
+        assert!(heap.update(rid, b"tiny"));
+        assert_eq!(pool.used_pages(), used);
+        assert!(heap.update(rid, &big));
+        assert_eq!(heap.scan().count(), 2);
+
+        assert!(heap.delete(rid));
+        assert_eq!(pool.used_pages(), used);
+        assert_eq!(heap.get(small), Some(b"small".to_vec()));
+    }
+
+    #[test]
+    fn growing_update_keeps_record_id() {
+        let pool = pool("heap_growing_update", 4);
+        let heap = HeapFile::create(&pool).unwrap();
+        let rids: Vec<_> = (0..30u8).map(|i| heap.insert(&[i; 100]).unwrap()).collect();
+        // too big to stay on the full first page
+        assert!(heap.update(rids[0], &[0; 2000]));
+        assert!(heap.update(rids[0], &[9; 3000]));
+        assert_eq!(heap.get(rids[0]), Some(vec![9; 3000]));
+        assert_eq!(heap.scan().filter(|(rid, _)| *rid == rids[0]).count(), 1);
+        assert!(heap.delete(rids[0]));
+        assert_eq!(heap.scan().count(), 29);
+    }
+
+    #[test]
+    fn scan_survives_reopen_and_eviction() {
+        let pool = pool("heap_scan", 3);
+        let heap = HeapFile::create(&pool).unwrap();
+        let mut expected = HashMap::new();
+        for i in 0..2000u32 {
+            let record = i.to_be_bytes().repeat(1 + i as usize % 20);
+            expected.insert(heap.insert(&record).unwrap(), record);
+        }
+        let heap = HeapFile::open(&pool, heap.directory_page());
+        let scanned: HashMap<_, _> = heap.scan().collect();
+        assert_eq!(scanned, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compressed_heap_round_trips() {
+        use crate::bufferpool::{eviction::LruK, EvictionStrategy, Pool};
+        use crate::disk::{DiskManager, PageSize};
+        use crate::utils::testing::temp_path;
+        use std::sync::Mutex;
+
+        let path = temp_path("heap_compressed");
+        // dropping the pool flushes it, so everything is read back from disk
+        let (rids, directory) = {
+            let strat: Mutex<Box<dyn EvictionStrategy>> = Mutex::new(Box::new(LruK::new(2, 2)));
+            let pool = Pool::with_disk(2, strat, DiskManager::create(&path, PageSize::Size16K));
+            let heap = HeapFile::create_compressed(&pool).unwrap();
+            let rids: Vec<_> = (0..200u32)
+                .map(|i| heap.insert(&i.to_be_bytes().repeat(50)).unwrap())
+                .collect();
+            (rids, heap.directory_page())
+        };
+
+        let strat: Mutex<Box<dyn EvictionStrategy>> = Mutex::new(Box::new(LruK::new(2, 2)));
+        let pool = Pool::with_disk(2, strat, DiskManager::open(&path));
+        let heap = HeapFile::open(&pool, directory);
+        assert!(heap.is_compressed());
+        for (i, rid) in rids.iter().enumerate() {
+            assert_eq!(heap.get(*rid), Some((i as u32).to_be_bytes().repeat(50)));
+        }
+    }
+
+    #[test]
+    fn random_operations_match_model() {
+        let pool = pool("heap_random", 8);
+        let heap = HeapFile::create(&pool).unwrap();
+        let mut model: HashMap<RecordId, Vec<u8>> = HashMap::new();
+        let mut rng = Rng::new(7);
+        for step in 0..3000 {
+            // some records big enough to overflow, a few of them over several pages
+            let len = match rng.below(10) {
+                0 => rng.below(12_000),
+                _ => rng.below(1500),
+            };
+            let record = vec![step as u8; len as usize];
+            let live: Vec<RecordId> = model.keys().copied().collect();
+            match rng.below(4) {
+                0 | 1 => {
+                    let rid = heap.insert(&record).unwrap();
+                    assert!(model.insert(rid, record).is_none());
+                }
+                2 if !live.is_empty() => {
+                    let rid = live[rng.below(live.len() as u64) as usize];
+                    assert!(heap.update(rid, &record));
+                    model.insert(rid, record);
+                }
+                _ if !live.is_empty() => {
+                    let rid = live[rng.below(live.len() as u64) as usize];
+                    assert!(heap.delete(rid));
+                    model.remove(&rid);
+                }
+                _ => {}
+            }
+        }
+        for (rid, record) in &model {
+            assert_eq!(heap.get(*rid).as_ref(), Some(record));
+        }
+        let scanned: HashMap<_, _> = heap.scan().collect();
+        assert_eq!(scanned, model);
+
+        // with every record gone, only the directory and data pages are left
+        let data_pages = heap.data_pages().len() as u32;
+        for rid in model.keys() {
+            assert!(heap.delete(*rid));
+        }
+        assert_eq!(pool.used_pages(), 1 + data_pages);
+    }
+}
@@ -1,2 +1,10 @@
 pub mod bitmap;
+// nothing outside the tests builds an index or a heap yet
+#[allow(dead_code)]
 pub mod btree;
+#[allow(dead_code)]
+pub mod hash;
+#[allow(dead_code)]
+pub mod heap;
+#[cfg(test)]
+pub mod testing;
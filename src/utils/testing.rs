@@ -0,0 +1,129 @@
+use crate::bufferpool::{eviction::LruK, EvictionStrategy, Pool};
+use crate::disk::{DiskManager, PageSize};
+use crate::page_interpretation::{Decimal, Text256, TupleField, TupleFieldTypes};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+
+// a path unique to this test run so tests can run in parallel
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("db-test-{}-{}.dat", process::id(), name))
+}
+
+// creates an empty database file of 4 KiB pages, like scripts/create_db.py
+pub fn temp_db(name: &str) -> PathBuf {
+    let path = temp_path(name);
+    drop(DiskManager::create(&path, PageSize::Size4K));
+    path
+}
+
+// a pool over a fresh temp_db
+pub fn pool(name: &str, capacity: usize) -> Pool {
+    let strat: Mutex<Box<dyn EvictionStrategy>> = Mutex::new(Box::new(LruK::new(capacity, 2)));
+    Pool::with_disk(capacity, strat, DiskManager::open(temp_db(name)))
+}
+
+// small seeded xorshift generator for randomized tests,
+// so a failing case can be reproduced from its seed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on 0
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    // uniform-ish in 0..bound
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.next_u64() & 1 == 1
+    }
+
+    pub fn char(&mut self) -> char {
+        // mostly ascii, sometimes anything
+        if self.bool() {
+            (b' ' + self.below(95) as u8) as char
+        } else {
+            loop {
+                if let Some(c) = char::from_u32(self.below(0x11_0000) as u32) {
+                    return c;
+                }
+            }
+        }
+    }
+}
+
+// one of every column type, for randomized tests
+pub const TYPES: [TupleFieldTypes; 14] = [
+    TupleFieldTypes::INT,
+    TupleFieldTypes::SIGNEDINT,
+    TupleFieldTypes::DOUBLE,
+    TupleFieldTypes::CHAR,
+    TupleFieldTypes::TEXT256,
+    TupleFieldTypes::BOOL,
+    TupleFieldTypes::BIGINT,
+    TupleFieldTypes::SIGNEDBIGINT,
+    TupleFieldTypes::DOUBLE64,
+    TupleFieldTypes::DECIMAL {
+        precision: 10,
+        scale: 3,
+    },
+    TupleFieldTypes::DATE,
+    TupleFieldTypes::TIMESTAMP,
+    TupleFieldTypes::VARCHAR(40),
+    TupleFieldTypes::BLOB,
+];
+
+pub fn random_field(rng: &mut Rng, field_type: TupleFieldTypes) -> TupleField {
+    match field_type {
+        TupleFieldTypes::INT => TupleField::Int(rng.next_u32()),
+        TupleFieldTypes::SIGNEDINT => TupleField::SignedInt(rng.next_u32() as i32),
+        // any bit pattern, so NaNs, infinities and subnormals all show up
+        TupleFieldTypes::DOUBLE => TupleField::Double(f32::from_bits(rng.next_u32())),
+        TupleFieldTypes::CHAR => TupleField::Char(rng.char()),
+        TupleFieldTypes::TEXT256 => {
+            let mut text = String::new();
+            loop {
+                let c = rng.char();
+                if text.len() + c.len_utf8() > Text256::MAX_LEN || rng.below(50) == 0 {
+                    break;
+                }
+                text.push(c);
+            }
+            TupleField::Text256(Text256::new(&text).unwrap())
+        }
+        TupleFieldTypes::BOOL => TupleField::Bool(rng.bool()),
+        TupleFieldTypes::BIGINT => TupleField::BigInt(rng.next_u64()),
+        TupleFieldTypes::SIGNEDBIGINT => TupleField::SignedBigInt(rng.next_u64() as i64),
+        TupleFieldTypes::DOUBLE64 => TupleField::Double64(f64::from_bits(rng.next_u64())),
+        TupleFieldTypes::DECIMAL { precision, scale } => {
+            let max = 10i128.pow(precision as u32);
+            let unscaled = rng.below(2 * max as u64 - 1) as i128 - (max - 1);
+            TupleField::Decimal(Decimal::new(unscaled, scale))
+        }
+        TupleFieldTypes::DATE => TupleField::Date(rng.next_u32() as i32),
+        TupleFieldTypes::TIMESTAMP => TupleField::Timestamp(rng.next_u64() as i64),
+        TupleFieldTypes::VARCHAR(max) => {
+            let len = rng.below(max as u64 + 1);
+            TupleField::Varchar((0..len).map(|_| rng.char()).collect())
+        }
+        TupleFieldTypes::BLOB => {
+            let len = rng.below(100);
+            TupleField::Blob((0..len).map(|_| rng.next_u32() as u8).collect())
+        }
+    }
+}